@@ -0,0 +1,152 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+
+#[derive(Debug)]
+pub struct NoSuitableMemoryType(pub vk::MemoryPropertyFlags);
+impl std::error::Error for NoSuitableMemoryType {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for NoSuitableMemoryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No memory type with {:?}", self.0)
+    }
+}
+
+/// The first memory type allowed by `type_bits` (from `VkMemoryRequirements`) that has all of `flags`.
+pub fn find_memory_type(memory_properties: &vk::PhysicalDeviceMemoryProperties, type_bits: u32, flags: vk::MemoryPropertyFlags) -> Result<u32, NoSuitableMemoryType> {
+    (0..memory_properties.memory_type_count)
+        .find(|idx| type_bits & (1 << idx) != 0 && memory_properties.memory_types[*idx as usize].property_flags.contains(flags))
+        .ok_or(NoSuitableMemoryType(flags))
+}
+
+/// Records commands into a temporary command buffer, submits it and waits for it to finish.
+/// Meant for uploads and readbacks outside the frame loop.
+pub fn one_time_submit<F: FnOnce(vk::CommandBuffer)>(device: &ash::Device, command_pool: vk::CommandPool, queue: vk::Queue, record: F) -> Result<(), vk::Result> {
+    let alloc_info = vk::CommandBufferAllocateInfo::builder()
+        .command_pool(command_pool)
+        .level(vk::CommandBufferLevel::PRIMARY)
+        .command_buffer_count(1);
+    let command_buffers = unsafe { device.allocate_command_buffers(&alloc_info) }?;
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+    let result = unsafe {
+        device.begin_command_buffer(command_buffers[0], &begin_info).and_then(|_| {
+            record(command_buffers[0]);
+            device.end_command_buffer(command_buffers[0])
+        }).and_then(|_| {
+            let submit_info = [vk::SubmitInfo::builder()
+                .command_buffers(&command_buffers)
+                .build()];
+            let fence = device.create_fence(&vk::FenceCreateInfo::builder(), None)?;
+            let result = device.queue_submit(queue, &submit_info, fence)
+                .and_then(|_| device.wait_for_fences(&[fence], true, std::u64::MAX));
+            device.destroy_fence(fence, None);
+            result
+        })
+    };
+    unsafe { device.free_command_buffers(command_pool, &command_buffers) };
+    result
+}
+
+/// A buffer with its own memory allocation.
+pub struct Buffer {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+    pub size: vk::DeviceSize,
+}
+
+impl Buffer {
+    pub fn new(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, size: vk::DeviceSize, usage: vk::BufferUsageFlags, flags: vk::MemoryPropertyFlags) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffer_info = vk::BufferCreateInfo::builder()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.create_buffer(&buffer_info, None) }?;
+        let requirements = unsafe { device.get_buffer_memory_requirements(buffer) };
+        let memory = find_memory_type(memory_properties, requirements.memory_type_bits, flags).map_err(Box::<dyn std::error::Error>::from).and_then(|memory_type_index| {
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index);
+            Ok(unsafe { device.allocate_memory(&alloc_info, None) }?)
+        });
+        let memory = match memory {
+            Ok(memory) => memory,
+            Err(err) => {
+                unsafe { device.destroy_buffer(buffer, None) };
+                return Err(err);
+            }
+        };
+        let buffer = Self { buffer, memory, size };
+        if let Err(err) = unsafe { device.bind_buffer_memory(buffer.buffer, buffer.memory, 0) } {
+            buffer.destroy(device);
+            return Err(Box::new(err));
+        }
+        Ok(buffer)
+    }
+
+    /// A host visible and coherent buffer, for staging and readback.
+    pub fn host_visible(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, size: vk::DeviceSize, usage: vk::BufferUsageFlags) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::new(device, memory_properties, size, usage, vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    /// A device local buffer filled with `data` through a staging buffer.
+    pub fn device_local_with_data<T: Copy>(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, command_pool: vk::CommandPool, queue: vk::Queue, usage: vk::BufferUsageFlags, data: &[T]) -> Result<Self, Box<dyn std::error::Error>> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        let staging = Self::host_visible(device, memory_properties, size, vk::BufferUsageFlags::TRANSFER_SRC)?;
+        let result = staging.write(device, data).map_err(Box::<dyn std::error::Error>::from).and_then(|_| {
+            let buffer = Self::new(device, memory_properties, size, usage | vk::BufferUsageFlags::TRANSFER_DST, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+            if let Err(err) = staging.copy_to(device, command_pool, queue, &buffer) {
+                buffer.destroy(device);
+                return Err(err.into());
+            }
+            Ok(buffer)
+        });
+        staging.destroy(device);
+        result
+    }
+
+    /// Copies `data` to the start of the buffer, which has to be host visible and coherent.
+    pub fn write<T: Copy>(&self, device: &ash::Device, data: &[T]) -> Result<(), vk::Result> {
+        let size = std::mem::size_of_val(data) as vk::DeviceSize;
+        assert!(size <= self.size, "writing {} bytes to a buffer of {} bytes", size, self.size);
+        unsafe {
+            let mapped = device.map_memory(self.memory, 0, size, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped as *mut T, data.len());
+            device.unmap_memory(self.memory);
+        }
+        Ok(())
+    }
+
+    /// Reads the buffer back as elements of `T`. It has to be host visible and coherent.
+    pub fn read<T: Copy>(&self, device: &ash::Device) -> Result<Vec<T>, vk::Result> {
+        let count = self.size as usize / std::mem::size_of::<T>();
+        let mut data = Vec::with_capacity(count);
+        unsafe {
+            let mapped = device.map_memory(self.memory, 0, self.size, vk::MemoryMapFlags::empty())?;
+            std::ptr::copy_nonoverlapping(mapped as *const T, data.as_mut_ptr(), count);
+            data.set_len(count);
+            device.unmap_memory(self.memory);
+        }
+        Ok(data)
+    }
+
+    pub fn copy_to(&self, device: &ash::Device, command_pool: vk::CommandPool, queue: vk::Queue, destination: &Buffer) -> Result<(), vk::Result> {
+        let regions = [vk::BufferCopy::builder()
+            .size(std::cmp::min(self.size, destination.size))
+            .build()];
+        one_time_submit(device, command_pool, queue, |command_buffer| unsafe {
+            device.cmd_copy_buffer(command_buffer, self.buffer, destination.buffer, &regions);
+        })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_buffer(self.buffer, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use std::ffi::CStr;
+
+use crate::{
+    descriptors::create_descriptor_set_layouts,
+    reflection::{PipelineReflection, ShaderReflection},
+    specialization::SpecializationConstants,
+};
+
+const ENTRY_POINT: &[u8] = b"main\0";
+
+/// Number of workgroups of `local_size` invocations needed to cover `items`.
+pub fn workgroup_count(items: u32, local_size: u32) -> u32 {
+    items.div_ceil(local_size)
+}
+
+pub fn workgroup_counts(items: [u32; 3], local_size: [u32; 3]) -> [u32; 3] {
+    [workgroup_count(items[0], local_size[0]), workgroup_count(items[1], local_size[1]), workgroup_count(items[2], local_size[2])]
+}
+
+/// A compute pipeline with the layouts derived from its shader.
+pub struct ComputePipeline {
+    pub pipeline: vk::Pipeline,
+    pub layout: vk::PipelineLayout,
+    pub descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    pub reflection: PipelineReflection,
+    pub local_size: [u32; 3],
+}
+
+impl ComputePipeline {
+    /// The shader module can be destroyed once this returns.
+    pub fn new(device: &ash::Device, pipeline_cache: vk::PipelineCache, module: vk::ShaderModule, shader: &ShaderReflection, specialization: &SpecializationConstants) -> Result<Self, Box<dyn std::error::Error>> {
+        specialization.check(shader)?;
+        let reflection = PipelineReflection::merge(&[shader])?;
+        let mut result = Self {
+            pipeline: vk::Pipeline::null(),
+            layout: vk::PipelineLayout::null(),
+            descriptor_set_layouts: create_descriptor_set_layouts(device, &reflection)?,
+            local_size: specialization.local_size(shader),
+            reflection,
+        };
+        let layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&result.descriptor_set_layouts)
+            .push_constant_ranges(&result.reflection.push_constant_ranges);
+        let created = unsafe { device.create_pipeline_layout(&layout_info, None) }.and_then(|layout| {
+            result.layout = layout;
+            let specialization_info = specialization.info();
+            let mut stage = vk::PipelineShaderStageCreateInfo::builder()
+                .stage(vk::ShaderStageFlags::COMPUTE)
+                .module(module)
+                .name(unsafe { CStr::from_bytes_with_nul_unchecked(ENTRY_POINT) })
+                .build();
+            if !specialization.is_empty() {
+                stage.p_specialization_info = &specialization_info;
+            }
+            let pipeline_info = [vk::ComputePipelineCreateInfo::builder()
+                .stage(stage)
+                .layout(layout)
+                .base_pipeline_index(-1)
+                .build()];
+            unsafe { device.create_compute_pipelines(pipeline_cache, &pipeline_info, None) }.map_err(|(_, err)| err)
+        });
+        match created {
+            Ok(pipelines) => {
+                result.pipeline = pipelines[0];
+                Ok(result)
+            }
+            Err(err) => {
+                result.destroy(device);
+                Err(Box::new(err))
+            }
+        }
+    }
+
+    pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, descriptor_sets: &[vk::DescriptorSet]) {
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            if !descriptor_sets.is_empty() {
+                device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::COMPUTE, self.layout, 0, descriptor_sets, &[]);
+            }
+        }
+    }
+
+    /// Pushes `constants` at offset 0. `T` has to match the layout of the shader's push constant block.
+    pub fn push_constants<T: Copy>(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, constants: &T) {
+        let bytes = unsafe { std::slice::from_raw_parts(constants as *const T as *const u8, std::mem::size_of::<T>()) };
+        unsafe { device.cmd_push_constants(command_buffer, self.layout, vk::ShaderStageFlags::COMPUTE, 0, bytes) };
+    }
+
+    pub fn dispatch(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, workgroups: [u32; 3]) {
+        unsafe { device.cmd_dispatch(command_buffer, workgroups[0], workgroups[1], workgroups[2]) };
+    }
+
+    /// Dispatches enough workgroups for one invocation per item. The shader has to skip
+    /// the invocations past the end, as the last workgroup may not be full.
+    pub fn dispatch_items(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, items: [u32; 3]) {
+        self.dispatch(device, command_buffer, workgroup_counts(items, self.local_size));
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.layout, None);
+            for descriptor_set_layout in &self.descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(*descriptor_set_layout, None);
+            }
+        }
+    }
+}
+
+/// Makes compute writes to a storage image visible to fragment shaders, moving it from
+/// `GENERAL` to `SHADER_READ_ONLY_OPTIMAL`.
+#[allow(dead_code)]
+pub fn compute_to_fragment_image_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer, image: vk::Image, subresource_range: vk::ImageSubresourceRange) {
+    let barriers = [storage_image_read_barrier(image, subresource_range)];
+    unsafe { device.cmd_pipeline_barrier(command_buffer, vk::PipelineStageFlags::COMPUTE_SHADER, vk::PipelineStageFlags::FRAGMENT_SHADER, vk::DependencyFlags::empty(), &[], &[], &barriers) };
+}
+
+#[allow(dead_code)]
+fn storage_image_read_barrier(image: vk::Image, subresource_range: vk::ImageSubresourceRange) -> vk::ImageMemoryBarrier {
+    vk::ImageMemoryBarrier::builder()
+        .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+        .dst_access_mask(vk::AccessFlags::SHADER_READ)
+        .old_layout(vk::ImageLayout::GENERAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    #[test]
+    fn workgroup_count_rounds_up() {
+        let cases = [
+            (0, 64, 0),
+            (1, 64, 1),
+            (63, 64, 1),
+            (64, 64, 1),
+            (65, 64, 2),
+            (1000, 1, 1000),
+        ];
+        for &(items, local_size, expected) in &cases {
+            assert_eq!(workgroup_count(items, local_size), expected, "{} items, local size {}", items, local_size);
+        }
+    }
+
+    #[test]
+    fn workgroup_counts_per_axis() {
+        assert_eq!(workgroup_counts([1920, 1080, 1], [16, 16, 1]), [120, 68, 1]);
+        assert_eq!(workgroup_counts([100, 1, 1], [64, 1, 1]), [2, 1, 1]);
+        assert_eq!(workgroup_counts([0, 0, 0], [8, 8, 8]), [0, 0, 0]);
+    }
+
+    #[test]
+    fn storage_image_read_barrier_hands_over_to_fragment_reads() {
+        let range = vk::ImageSubresourceRange::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .base_mip_level(1)
+            .level_count(2)
+            .layer_count(1)
+            .build();
+        let barrier = storage_image_read_barrier(vk::Image::from_raw(7), range);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags::SHADER_WRITE);
+        assert_eq!(barrier.dst_access_mask, vk::AccessFlags::SHADER_READ);
+        assert_eq!(barrier.old_layout, vk::ImageLayout::GENERAL);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(barrier.src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(barrier.dst_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(barrier.image.as_raw(), 7);
+        assert_eq!((barrier.subresource_range.base_mip_level, barrier.subresource_range.level_count), (1, 2));
+    }
+}
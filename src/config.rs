@@ -0,0 +1,173 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::post_process::Effect;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: String,
+}
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Settings that can be changed while the app is running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Requested samples per pixel, 1 disables multisampling. Capped by what the device supports.
+    pub msaa_samples: u32,
+    /// Minimum fraction of samples shaded individually, `None` disables sample shading.
+    pub sample_shading: Option<f32>,
+    /// Full-screen effects applied to the scene in order, before it is copied to the swapchain.
+    pub post_process: Vec<Effect>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            msaa_samples: 4,
+            sample_shading: None,
+            post_process: vec![Effect::Tonemap, Effect::Fxaa],
+        }
+    }
+}
+
+impl Config {
+    /// Parses `key = value` lines. Empty lines and lines starting with `#` are skipped,
+    /// keys that aren't set keep their default.
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut config = Self::default();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| ConfigError { line: idx + 1, message };
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = parts.next().ok_or_else(|| error(format!("expected `key = value`, got `{}`", line)))?.trim();
+            match key {
+                "msaa_samples" => {
+                    config.msaa_samples = match value.parse() {
+                        Ok(samples @ 1) | Ok(samples @ 2) | Ok(samples @ 4) | Ok(samples @ 8) => samples,
+                        _ => return Err(error(format!("msaa_samples has to be 1, 2, 4 or 8, got `{}`", value))),
+                    };
+                }
+                "sample_shading" => {
+                    config.sample_shading = match value {
+                        "off" => None,
+                        _ => match value.parse::<f32>() {
+                            Ok(fraction) if fraction > 0.0 && fraction <= 1.0 => Some(fraction),
+                            _ => return Err(error(format!("sample_shading has to be `off` or in (0, 1], got `{}`", value))),
+                        },
+                    };
+                }
+                "post_process" => {
+                    config.post_process = match value {
+                        "none" => Vec::new(),
+                        _ => value.split(',').map(|name| name.trim().parse()).collect::<Result<_, _>>().map_err(error)?,
+                    };
+                }
+                _ => return Err(error(format!("unknown key `{}`", key))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Reloads the config file when it changes on disk. A missing file means the defaults.
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+}
+
+impl ConfigWatcher {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            modified: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn modification_time(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    pub fn load(&mut self) -> Result<Config, Box<dyn std::error::Error>> {
+        self.modified = self.modification_time();
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => Ok(Config::parse(&text)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The config, if the file was modified since it was last loaded.
+    /// Checks at most every `POLL_INTERVAL` so it can be called once per frame.
+    pub fn poll(&mut self) -> Option<Result<Config, Box<dyn std::error::Error>>> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+        if self.modification_time() == self.modified {
+            return None;
+        }
+        Some(self.load())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_file() {
+        let text = "# comment\n\nmsaa_samples = 8\nsample_shading = 0.5\npost_process = vignette, tonemap ,fxaa\n";
+        assert_eq!(Config::parse(text).unwrap(), Config {
+            msaa_samples: 8,
+            sample_shading: Some(0.5),
+            post_process: vec![Effect::Vignette, Effect::Tonemap, Effect::Fxaa],
+        });
+    }
+
+    #[test]
+    fn missing_keys_keep_their_default() {
+        assert_eq!(Config::parse("sample_shading = off\n").unwrap(), Config::default());
+        assert_eq!(Config::parse("post_process = none").unwrap().post_process, Vec::new());
+    }
+
+    #[test]
+    fn malformed_line() {
+        let err = Config::parse("msaa_samples = 2\nmsaa_samples 4\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.message, "expected `key = value`, got `msaa_samples 4`");
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert_eq!(Config::parse("msaa_samples = 3").unwrap_err().line, 1);
+        assert_eq!(Config::parse("sample_shading = 0").unwrap_err().line, 1);
+        assert_eq!(Config::parse("\nfoo = 1").unwrap_err().message, "unknown key `foo`");
+    }
+
+    #[test]
+    fn unknown_effect() {
+        let err = Config::parse("post_process = tonemap, bloom").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "unknown effect `bloom`");
+    }
+}
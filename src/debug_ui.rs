@@ -0,0 +1,480 @@
+use std::time::Instant;
+
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use egui::{
+    epaint::Vertex,
+    ClippedMesh, CtxRef, Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, TextureId, Vec2,
+};
+use log::{debug, warn};
+use winit::event::{ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+use crate::{
+    buffer::Buffer,
+    descriptors::{allocate_descriptor_sets, create_descriptor_pool, create_descriptor_set_layouts, write_combined_image_sampler},
+    frame::MAX_FRAMES_IN_FLIGHT,
+    image::Image,
+    pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines},
+    reflection::{DescriptorBinding, PipelineReflection},
+    texture::TextureLoader,
+};
+
+pub const DEBUG_UI_SHADERS: [&str; 2] = ["debug_ui.vs", "debug_ui.fs"];
+
+/// Bytes the buffers of a frame are created with at least, they grow when the UI needs more.
+const MIN_BUFFER_SIZE: usize = 256 * 1024;
+/// Points scrolled per line of the mouse wheel.
+const SCROLL_LINE_HEIGHT: f32 = 50.0;
+
+/// Settings changed in the debug UI, applied by the app after the frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Tweakables {
+    /// Linear color the scene is cleared to.
+    pub clear_color: [f32; 3],
+    /// `None` until the swapchain picked one.
+    pub present_mode: Option<vk::PresentModeKHR>,
+    /// Requested samples per pixel, see `Config::msaa_samples`.
+    pub msaa_samples: u32,
+}
+
+/// Matches the push constant block in debug_ui.vs.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DebugUiParameters {
+    screen_size: [f32; 2],
+}
+
+/// A mesh in the buffers of a frame, clipped to its scissor rectangle.
+struct MeshDraw {
+    scissor: vk::Rect2D,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// An egui user interface drawn over the final image. Window events are forwarded with
+/// `handle_event`, `run` builds the UI of a frame and `prepare` uploads it. Everything egui
+/// draws samples its font atlas, which is uploaded again whenever egui changes it.
+pub struct DebugUi {
+    pub visible: bool,
+    /// Window pixels per egui point, the window's HiDPI factor.
+    pub pixels_per_point: f32,
+    context: CtxRef,
+    /// Input gathered since the last `run`.
+    input: RawInput,
+    modifiers: Modifiers,
+    pointer_position: Pos2,
+    start: Instant,
+    extent: vk::Extent2D,
+    /// What the last `run` drew, in points.
+    meshes: Vec<ClippedMesh>,
+    draws: Vec<MeshDraw>,
+    descriptor_bindings: Vec<DescriptorBinding>,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_pool: vk::DescriptorPool,
+    /// One per frame in flight, so the font atlas can change while earlier frames still sample the previous one.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    /// The font atlas version bound to each frame's descriptor set.
+    bound_versions: Vec<Option<u64>>,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    sampler: vk::Sampler,
+    font_atlas: Option<Image>,
+    font_atlas_version: Option<u64>,
+    /// Font atlases replaced while preparing each frame, destroyed when it's prepared again.
+    retired_font_atlases: Vec<Vec<Image>>,
+    /// Vertex and index buffer of each frame in flight, created when the UI is first drawn.
+    vertex_buffers: Vec<Option<Buffer>>,
+    index_buffers: Vec<Option<Buffer>>,
+}
+
+impl DebugUi {
+    /// The shader modules can be destroyed once this returns. `sampler` is used for the font atlas.
+    pub fn new(device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection, sampler: vk::Sampler) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut result = Self {
+            visible: false,
+            pixels_per_point: 1.0,
+            context: CtxRef::default(),
+            input: RawInput::default(),
+            modifiers: Modifiers::default(),
+            pointer_position: Pos2::ZERO,
+            start: Instant::now(),
+            extent: vk::Extent2D::default(),
+            meshes: Vec::new(),
+            draws: Vec::new(),
+            descriptor_bindings: reflection.descriptor_bindings.clone(),
+            descriptor_set_layouts: Vec::new(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_sets: Vec::new(),
+            bound_versions: vec![None; MAX_FRAMES_IN_FLIGHT],
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            sampler,
+            font_atlas: None,
+            font_atlas_version: None,
+            retired_font_atlases: (0..MAX_FRAMES_IN_FLIGHT).map(|_| Vec::new()).collect(),
+            vertex_buffers: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+            index_buffers: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+        };
+        if let Err(err) = result.create_resources(device, pipeline_cache, render_pass, vertex_shader, fragment_shader, reflection) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create_resources(&mut self, device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection) -> Result<(), Box<dyn std::error::Error>> {
+        if reflection.descriptor_bindings.iter().any(|binding| binding.set != 0) {
+            return Err(format!("{}: all inputs have to be in descriptor set 0", DEBUG_UI_SHADERS[1]).into());
+        }
+        self.descriptor_set_layouts = create_descriptor_set_layouts(device, reflection)?;
+        self.descriptor_pool = create_descriptor_pool(device, &reflection.descriptor_bindings, MAX_FRAMES_IN_FLIGHT as u32)?;
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            self.descriptor_sets.push(allocate_descriptor_sets(device, self.descriptor_pool, &self.descriptor_set_layouts)?[0]);
+        }
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        self.pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None) }?;
+        // laid out like egui's `Vertex`, the color is in bytes
+        let bindings = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Vertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+        let attributes = [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(8)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .offset(16)
+                .build(),
+        ];
+        let builder = GraphicsPipelineBuilder::new(self.pipeline_layout, render_pass)
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader)
+            .vertex_input(&bindings, &attributes)
+            // egui doesn't keep a consistent winding order
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+            .color_attachments(&[GraphicsPipelineBuilder::premultiplied_alpha_blend_attachment()]);
+        self.pipeline = create_graphics_pipelines(device, pipeline_cache, &[builder])?[0];
+        Ok(())
+    }
+
+    /// Passes a window event on to the UI while it's visible. Returns whether the UI used it,
+    /// in which case the app shouldn't react to it as well.
+    pub fn handle_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        match event {
+            WindowEvent::CursorMoved { position, modifiers, .. } => {
+                self.set_modifiers(*modifiers);
+                self.pointer_position = Pos2::new(position.x as f32, position.y as f32);
+                self.input.events.push(Event::PointerMoved(self.pointer_position));
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.input.events.push(Event::PointerGone);
+                false
+            }
+            WindowEvent::MouseInput { state, button, modifiers, .. } => {
+                self.set_modifiers(*modifiers);
+                let button = match button {
+                    MouseButton::Left => PointerButton::Primary,
+                    MouseButton::Right => PointerButton::Secondary,
+                    MouseButton::Middle => PointerButton::Middle,
+                    MouseButton::Other(_) => return false,
+                };
+                self.input.events.push(Event::PointerButton {
+                    pos: self.pointer_position,
+                    button,
+                    pressed: *state == ElementState::Pressed,
+                    modifiers: self.modifiers,
+                });
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::MouseWheel { delta, modifiers, .. } => {
+                self.set_modifiers(*modifiers);
+                self.input.scroll_delta += match delta {
+                    MouseScrollDelta::LineDelta(x, y) => Vec2::new(*x, *y) * SCROLL_LINE_HEIGHT,
+                    MouseScrollDelta::PixelDelta(position) => Vec2::new(position.x as f32, position.y as f32),
+                };
+                self.context.wants_pointer_input()
+            }
+            WindowEvent::ReceivedCharacter(c) => {
+                // control characters arrive as keys
+                if !c.is_control() {
+                    self.input.events.push(Event::Text(c.to_string()));
+                }
+                self.context.wants_keyboard_input()
+            }
+            WindowEvent::KeyboardInput { input: KeyboardInput { state, virtual_keycode, modifiers, .. }, .. } => {
+                self.set_modifiers(*modifiers);
+                if let Some(key) = virtual_keycode.and_then(key) {
+                    self.input.events.push(Event::Key {
+                        key,
+                        pressed: *state == ElementState::Pressed,
+                        modifiers: self.modifiers,
+                    });
+                }
+                self.context.wants_keyboard_input()
+            }
+            _ => false,
+        }
+    }
+
+    fn set_modifiers(&mut self, modifiers: ModifiersState) {
+        self.modifiers = Modifiers {
+            alt: modifiers.alt,
+            ctrl: modifiers.ctrl,
+            shift: modifiers.shift,
+            mac_cmd: false,
+            command: modifiers.ctrl,
+        };
+    }
+
+    /// Builds the UI of a frame over `extent` with `build` if it's visible, consuming the input
+    /// forwarded since the previous frame.
+    pub fn run<F: FnOnce(&CtxRef)>(&mut self, delta_time: f32, extent: vk::Extent2D, build: F) {
+        self.meshes.clear();
+        let mut input = self.input.take();
+        if !self.visible {
+            return;
+        }
+        input.screen_rect = Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(extent.width as f32, extent.height as f32) / self.pixels_per_point));
+        input.pixels_per_point = Some(self.pixels_per_point);
+        input.time = Some(self.start.elapsed().as_secs_f64());
+        input.predicted_dt = delta_time;
+        input.modifiers = self.modifiers;
+        self.extent = extent;
+        self.context.begin_frame(input);
+        build(&self.context);
+        let (_, shapes) = self.context.end_frame();
+        self.meshes = self.context.tessellate(shapes);
+    }
+
+    /// Uploads what `run` drew to the buffers of `frame`, which the GPU has to be done with,
+    /// and the font atlas if egui changed it.
+    pub fn prepare(&mut self, loader: &TextureLoader, frame: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let device = loader.device;
+        for font_atlas in self.retired_font_atlases[frame].drain(..) {
+            font_atlas.destroy(device);
+        }
+        self.draws.clear();
+        if self.meshes.is_empty() {
+            return Ok(());
+        }
+
+        let texture = self.context.texture();
+        if self.font_atlas_version != Some(texture.version) {
+            let extent = vk::Extent2D { width: texture.width as u32, height: texture.height as u32 };
+            let font_atlas = loader.create(extent, vk::Format::R8_UNORM, &[&texture.pixels], false)?;
+            debug!("Debug UI font atlas: {}x{}, version {}", extent.width, extent.height, texture.version);
+            // the previous frames can still be sampling the previous one
+            if let Some(previous) = self.font_atlas.replace(font_atlas) {
+                self.retired_font_atlases[frame].push(previous);
+            }
+            self.font_atlas_version = Some(texture.version);
+        }
+        if self.bound_versions[frame] != self.font_atlas_version {
+            let view = self.font_atlas.as_ref().unwrap().view;
+            for binding in &self.descriptor_bindings {
+                match (binding.name.as_str(), binding.descriptor_type) {
+                    ("fontAtlas", vk::DescriptorType::COMBINED_IMAGE_SAMPLER) => write_combined_image_sampler(device, self.descriptor_sets[frame], binding.binding, view, self.sampler),
+                    (name, descriptor_type) => warn!("{}: nothing to bind to {} ({:?})", DEBUG_UI_SHADERS[1], name, descriptor_type),
+                }
+            }
+            self.bound_versions[frame] = self.font_atlas_version;
+        }
+
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let (width, height) = (self.extent.width as f32, self.extent.height as f32);
+        for ClippedMesh(clip_rect, mesh) in &self.meshes {
+            // only the font atlas is drawn from, there are no user textures
+            if mesh.texture_id != TextureId::Egui || mesh.indices.is_empty() {
+                continue;
+            }
+            let min_x = (clip_rect.min.x * self.pixels_per_point).round().max(0.0).min(width);
+            let min_y = (clip_rect.min.y * self.pixels_per_point).round().max(0.0).min(height);
+            let max_x = (clip_rect.max.x * self.pixels_per_point).round().max(min_x).min(width);
+            let max_y = (clip_rect.max.y * self.pixels_per_point).round().max(min_y).min(height);
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+            self.draws.push(MeshDraw {
+                scissor: vk::Rect2D {
+                    offset: vk::Offset2D { x: min_x as i32, y: min_y as i32 },
+                    extent: vk::Extent2D { width: (max_x - min_x) as u32, height: (max_y - min_y) as u32 },
+                },
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+        if self.draws.is_empty() {
+            return Ok(());
+        }
+        Self::reserve(device, loader.memory_properties, &mut self.vertex_buffers[frame], std::mem::size_of_val(&vertices[..]), vk::BufferUsageFlags::VERTEX_BUFFER)?.write(device, &vertices)?;
+        Self::reserve(device, loader.memory_properties, &mut self.index_buffers[frame], std::mem::size_of_val(&indices[..]), vk::BufferUsageFlags::INDEX_BUFFER)?.write(device, &indices)?;
+        Ok(())
+    }
+
+    /// Creates `buffer`, or replaces it by a larger one, if it doesn't have `size` bytes.
+    fn reserve<'b>(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, buffer: &'b mut Option<Buffer>, size: usize, usage: vk::BufferUsageFlags) -> Result<&'b Buffer, Box<dyn std::error::Error>> {
+        if buffer.as_ref().map_or(0, |buffer| buffer.size) < size as vk::DeviceSize {
+            let larger = Buffer::host_visible(device, memory_properties, size.max(MIN_BUFFER_SIZE).next_power_of_two() as vk::DeviceSize, usage)?;
+            if let Some(previous) = buffer.replace(larger) {
+                previous.destroy(device);
+            }
+        }
+        Ok(buffer.as_ref().unwrap())
+    }
+
+    /// Draws what `prepare` uploaded for `frame`. Has to be recorded inside the debug UI's render pass.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        if self.draws.is_empty() {
+            return;
+        }
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let parameters = DebugUiParameters {
+            screen_size: [self.extent.width as f32 / self.pixels_per_point, self.extent.height as f32 / self.pixels_per_point],
+        };
+        let bytes = unsafe { std::slice::from_raw_parts(&parameters as *const DebugUiParameters as *const u8, std::mem::size_of::<DebugUiParameters>()) };
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, &[self.descriptor_sets[frame]], &[]);
+            device.cmd_push_constants(command_buffer, self.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, bytes);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffers[frame].as_ref().unwrap().buffer], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, self.index_buffers[frame].as_ref().unwrap().buffer, 0, vk::IndexType::UINT32);
+            for draw in &self.draws {
+                device.cmd_set_scissor(command_buffer, 0, &[draw.scissor]);
+                device.cmd_draw_indexed(command_buffer, draw.index_count, 1, draw.first_index, draw.vertex_offset, 0);
+            }
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            for descriptor_set_layout in &self.descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(*descriptor_set_layout, None);
+            }
+        }
+        for font_atlas in self.font_atlas.iter().chain(self.retired_font_atlases.iter().flatten()) {
+            font_atlas.destroy(device);
+        }
+        for buffer in self.vertex_buffers.iter().chain(&self.index_buffers).flatten() {
+            buffer.destroy(device);
+        }
+    }
+}
+
+/// The window with the tweakables: the clear color, the present modes in `present_modes` and
+/// the sample counts in `sample_counts`.
+pub fn tweakables_window(context: &CtxRef, tweakables: &mut Tweakables, present_modes: &[vk::PresentModeKHR], sample_counts: &[u32]) {
+    egui::Window::new("Tweakables").resizable(false).show(context, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Clear color");
+            ui.color_edit_button_rgb(&mut tweakables.clear_color);
+        });
+        let selected = tweakables.present_mode.map_or_else(String::new, |present_mode| format!("{:?}", present_mode));
+        egui::ComboBox::from_label("Present mode").selected_text(selected).show_ui(ui, |ui| {
+            for present_mode in present_modes {
+                ui.selectable_value(&mut tweakables.present_mode, Some(*present_mode), format!("{:?}", present_mode));
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("MSAA");
+            for count in sample_counts {
+                let label = if *count == 1 { "Off".to_owned() } else { format!("{}x", count) };
+                ui.radio_value(&mut tweakables.msaa_samples, *count, label);
+            }
+        });
+    });
+}
+
+/// The egui key of a virtual key code, for the keys egui handles.
+fn key(keycode: VirtualKeyCode) -> Option<Key> {
+    Some(match keycode {
+        VirtualKeyCode::Down => Key::ArrowDown,
+        VirtualKeyCode::Left => Key::ArrowLeft,
+        VirtualKeyCode::Right => Key::ArrowRight,
+        VirtualKeyCode::Up => Key::ArrowUp,
+        VirtualKeyCode::Escape => Key::Escape,
+        VirtualKeyCode::Tab => Key::Tab,
+        VirtualKeyCode::Back => Key::Backspace,
+        VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => Key::Enter,
+        VirtualKeyCode::Space => Key::Space,
+        VirtualKeyCode::Insert => Key::Insert,
+        VirtualKeyCode::Delete => Key::Delete,
+        VirtualKeyCode::Home => Key::Home,
+        VirtualKeyCode::End => Key::End,
+        VirtualKeyCode::PageUp => Key::PageUp,
+        VirtualKeyCode::PageDown => Key::PageDown,
+        VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => Key::Num0,
+        VirtualKeyCode::Key1 | VirtualKeyCode::Numpad1 => Key::Num1,
+        VirtualKeyCode::Key2 | VirtualKeyCode::Numpad2 => Key::Num2,
+        VirtualKeyCode::Key3 | VirtualKeyCode::Numpad3 => Key::Num3,
+        VirtualKeyCode::Key4 | VirtualKeyCode::Numpad4 => Key::Num4,
+        VirtualKeyCode::Key5 | VirtualKeyCode::Numpad5 => Key::Num5,
+        VirtualKeyCode::Key6 | VirtualKeyCode::Numpad6 => Key::Num6,
+        VirtualKeyCode::Key7 | VirtualKeyCode::Numpad7 => Key::Num7,
+        VirtualKeyCode::Key8 | VirtualKeyCode::Numpad8 => Key::Num8,
+        VirtualKeyCode::Key9 | VirtualKeyCode::Numpad9 => Key::Num9,
+        VirtualKeyCode::A => Key::A,
+        VirtualKeyCode::B => Key::B,
+        VirtualKeyCode::C => Key::C,
+        VirtualKeyCode::D => Key::D,
+        VirtualKeyCode::E => Key::E,
+        VirtualKeyCode::F => Key::F,
+        VirtualKeyCode::G => Key::G,
+        VirtualKeyCode::H => Key::H,
+        VirtualKeyCode::I => Key::I,
+        VirtualKeyCode::J => Key::J,
+        VirtualKeyCode::K => Key::K,
+        VirtualKeyCode::L => Key::L,
+        VirtualKeyCode::M => Key::M,
+        VirtualKeyCode::N => Key::N,
+        VirtualKeyCode::O => Key::O,
+        VirtualKeyCode::P => Key::P,
+        VirtualKeyCode::Q => Key::Q,
+        VirtualKeyCode::R => Key::R,
+        VirtualKeyCode::S => Key::S,
+        VirtualKeyCode::T => Key::T,
+        VirtualKeyCode::U => Key::U,
+        VirtualKeyCode::V => Key::V,
+        VirtualKeyCode::W => Key::W,
+        VirtualKeyCode::X => Key::X,
+        VirtualKeyCode::Y => Key::Y,
+        VirtualKeyCode::Z => Key::Z,
+        _ => return None,
+    })
+}
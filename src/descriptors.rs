@@ -0,0 +1,115 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+
+use crate::reflection::{DescriptorBinding, PipelineReflection};
+
+pub fn create_descriptor_set_layouts(device: &ash::Device, reflection: &PipelineReflection) -> Result<Vec<vk::DescriptorSetLayout>, vk::Result> {
+    reflection.descriptor_set_layout_bindings().iter().map(|bindings| {
+        let layout_info = vk::DescriptorSetLayoutCreateInfo::builder()
+            .bindings(bindings);
+        unsafe { device.create_descriptor_set_layout(&layout_info, None) }
+    }).collect()
+}
+
+/// A pool with room for `max_sets` copies of every set described by `bindings`.
+pub fn create_descriptor_pool(device: &ash::Device, bindings: &[DescriptorBinding], max_sets: u32) -> Result<vk::DescriptorPool, vk::Result> {
+    let mut pool_sizes: Vec<vk::DescriptorPoolSize> = Vec::new();
+    for binding in bindings {
+        match pool_sizes.iter_mut().find(|size| size.ty == binding.descriptor_type) {
+            Some(size) => size.descriptor_count += binding.count * max_sets,
+            None => pool_sizes.push(vk::DescriptorPoolSize::builder()
+                .ty(binding.descriptor_type)
+                .descriptor_count(binding.count * max_sets)
+                .build()),
+        }
+    }
+    let set_count = bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0);
+    let pool_info = vk::DescriptorPoolCreateInfo::builder()
+        .pool_sizes(&pool_sizes)
+        .max_sets(std::cmp::max(set_count, 1) * max_sets);
+    unsafe { device.create_descriptor_pool(&pool_info, None) }
+}
+
+pub fn allocate_descriptor_sets(device: &ash::Device, pool: vk::DescriptorPool, layouts: &[vk::DescriptorSetLayout]) -> Result<Vec<vk::DescriptorSet>, vk::Result> {
+    let alloc_info = vk::DescriptorSetAllocateInfo::builder()
+        .descriptor_pool(pool)
+        .set_layouts(layouts);
+    unsafe { device.allocate_descriptor_sets(&alloc_info) }
+}
+
+pub fn write_storage_buffer(device: &ash::Device, set: vk::DescriptorSet, binding: u32, buffer: vk::Buffer, range: vk::DeviceSize) {
+    let buffer_info = [vk::DescriptorBufferInfo::builder()
+        .buffer(buffer)
+        .offset(0)
+        .range(range)
+        .build()];
+    let writes = [vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(binding)
+        .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+        .buffer_info(&buffer_info)
+        .build()];
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// Binds an image view in `SHADER_READ_ONLY_OPTIMAL` layout together with a sampler.
+pub fn write_combined_image_sampler(device: &ash::Device, set: vk::DescriptorSet, binding: u32, image_view: vk::ImageView, sampler: vk::Sampler) {
+    let image_info = [vk::DescriptorImageInfo::builder()
+        .image_view(image_view)
+        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .sampler(sampler)
+        .build()];
+    let writes = [vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(binding)
+        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .image_info(&image_info)
+        .build()];
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+/// Binds an image view in `GENERAL` layout, which storage images have to be in while shaders access them.
+#[allow(dead_code)]
+pub fn write_storage_image(device: &ash::Device, set: vk::DescriptorSet, binding: u32, image_view: vk::ImageView) {
+    let image_info = [vk::DescriptorImageInfo::builder()
+        .image_view(image_view)
+        .image_layout(vk::ImageLayout::GENERAL)
+        .build()];
+    let writes = [storage_image_write(set, binding, &image_info)];
+    unsafe { device.update_descriptor_sets(&writes, &[]) };
+}
+
+#[allow(dead_code)]
+fn storage_image_write(set: vk::DescriptorSet, binding: u32, image_info: &[vk::DescriptorImageInfo]) -> vk::WriteDescriptorSet {
+    vk::WriteDescriptorSet::builder()
+        .dst_set(set)
+        .dst_binding(binding)
+        .descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+        .image_info(image_info)
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    #[test]
+    fn storage_image_write_uses_general_layout() {
+        let image_info = [vk::DescriptorImageInfo::builder()
+            .image_view(vk::ImageView::from_raw(5))
+            .image_layout(vk::ImageLayout::GENERAL)
+            .build()];
+        let write = storage_image_write(vk::DescriptorSet::from_raw(3), 2, &image_info);
+        assert_eq!(write.dst_set.as_raw(), 3);
+        assert_eq!(write.dst_binding, 2);
+        assert_eq!(write.descriptor_type, vk::DescriptorType::STORAGE_IMAGE);
+        assert_eq!(write.descriptor_count, 1);
+        let written = unsafe { &*write.p_image_info };
+        assert_eq!(written.image_view.as_raw(), 5);
+        assert_eq!(written.image_layout, vk::ImageLayout::GENERAL);
+        assert_eq!(written.sampler, vk::Sampler::null());
+    }
+}
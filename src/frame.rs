@@ -0,0 +1,247 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+
+use crate::{
+    queries::{OcclusionId, OcclusionQueries, OcclusionQuery},
+    sprites::SpriteBatch,
+    text::TextRenderer,
+};
+
+/// Frames the CPU records while the GPU still works on earlier ones.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+
+/// The command pool, command buffer and synchronization of one frame in flight.
+#[derive(Clone, Copy)]
+pub struct Frame {
+    /// Reset as a whole every time the frame is recorded again.
+    pub command_pool: vk::CommandPool,
+    pub command_buffer: vk::CommandBuffer,
+    /// Signaled when the swapchain image can be rendered to.
+    pub image_available: vk::Semaphore,
+    /// Signaled when the frame can be presented.
+    pub render_finished: vk::Semaphore,
+    /// Signaled when the GPU is done with the frame's commands.
+    pub in_flight: vk::Fence,
+}
+
+impl Frame {
+    pub fn new(device: &ash::Device, queue_family_index: u32) -> Result<Self, vk::Result> {
+        let mut result = Self {
+            command_pool: vk::CommandPool::null(),
+            command_buffer: vk::CommandBuffer::null(),
+            image_available: vk::Semaphore::null(),
+            render_finished: vk::Semaphore::null(),
+            in_flight: vk::Fence::null(),
+        };
+        if let Err(err) = result.create_resources(device, queue_family_index) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create_resources(&mut self, device: &ash::Device, queue_family_index: u32) -> Result<(), vk::Result> {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family_index);
+        self.command_pool = unsafe { device.create_command_pool(&pool_info, None) }?;
+        let alloc_info = vk::CommandBufferAllocateInfo::builder()
+            .command_pool(self.command_pool)
+            .level(vk::CommandBufferLevel::PRIMARY)
+            .command_buffer_count(1);
+        self.command_buffer = unsafe { device.allocate_command_buffers(&alloc_info) }?[0];
+        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+        self.image_available = unsafe { device.create_semaphore(&semaphore_info, None) }?;
+        self.render_finished = unsafe { device.create_semaphore(&semaphore_info, None) }?;
+        // signaled, so waiting for the first use of the frame returns right away
+        let fence_info = vk::FenceCreateInfo::builder()
+            .flags(vk::FenceCreateFlags::SIGNALED);
+        self.in_flight = unsafe { device.create_fence(&fence_info, None) }?;
+        Ok(())
+    }
+
+    /// Waits until the GPU is done with the previous use of the frame.
+    pub fn wait(&self, device: &ash::Device) -> Result<(), vk::Result> {
+        unsafe { device.wait_for_fences(&[self.in_flight], true, u64::MAX) }
+    }
+
+    /// Resets the command pool and begins the command buffer. The fence is left signaled, the
+    /// caller resets it right before submitting so an error while recording can't leave the next
+    /// `wait` blocking forever.
+    pub fn begin(&self, device: &ash::Device) -> Result<vk::CommandBuffer, vk::Result> {
+        unsafe {
+            device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty())?;
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            device.begin_command_buffer(self.command_buffer, &begin_info)?;
+        }
+        Ok(self.command_buffer)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_fence(self.in_flight, None);
+            device.destroy_semaphore(self.render_finished, None);
+            device.destroy_semaphore(self.image_available, None);
+            device.destroy_command_pool(self.command_pool, None);
+        }
+    }
+}
+
+/// A non-indexed draw, with the vertices coming from the shaders or from vertex buffers.
+#[derive(Debug, Clone)]
+pub struct DrawCommand {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    /// Bound starting at set 0.
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Bound starting at binding 0, each from its start.
+    pub vertex_buffers: Vec<vk::Buffer>,
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    /// The object tested by an occlusion query around the draw.
+    pub occlusion_id: Option<OcclusionId>,
+    /// The query of `occlusion_id`, allocated when the draw is recorded.
+    pub occlusion_query: Option<OcclusionQuery>,
+}
+
+impl DrawCommand {
+    pub fn new(pipeline: vk::Pipeline, pipeline_layout: vk::PipelineLayout, vertex_count: u32) -> Self {
+        Self {
+            pipeline,
+            pipeline_layout,
+            descriptor_sets: Vec::new(),
+            vertex_buffers: Vec::new(),
+            vertex_count,
+            instance_count: 1,
+            occlusion_id: None,
+            occlusion_query: None,
+        }
+    }
+
+    pub fn descriptor_sets(mut self, descriptor_sets: &[vk::DescriptorSet]) -> Self {
+        self.descriptor_sets = descriptor_sets.to_vec();
+        self
+    }
+
+    pub fn vertex_buffers(mut self, vertex_buffers: &[vk::Buffer]) -> Self {
+        self.vertex_buffers = vertex_buffers.to_vec();
+        self
+    }
+
+    pub fn instances(mut self, instance_count: u32) -> Self {
+        self.instance_count = instance_count;
+        self
+    }
+
+    /// Tests whether any sample of the draw passes the depth test, see `FrameContext::is_visible`.
+    pub fn occlusion_query(mut self, object: OcclusionId) -> Self {
+        self.occlusion_id = Some(object);
+        self
+    }
+}
+
+/// The draws of the scene in one frame, in order.
+#[derive(Default)]
+pub struct DrawList {
+    commands: Vec<DrawCommand>,
+}
+
+impl DrawList {
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn push(&mut self, command: DrawCommand) {
+        self.commands.push(command);
+    }
+
+    pub fn commands(&self) -> &[DrawCommand] {
+        &self.commands
+    }
+}
+
+/// Records `commands`, binding pipelines, descriptor sets and vertex buffers only when they change.
+pub fn record_draws(device: &ash::Device, command_buffer: vk::CommandBuffer, commands: &[DrawCommand]) {
+    let mut bound_pipeline = vk::Pipeline::null();
+    let mut bound_descriptor_sets: &[vk::DescriptorSet] = &[];
+    let mut bound_vertex_buffers: &[vk::Buffer] = &[];
+    for command in commands {
+        unsafe {
+            if command.pipeline != bound_pipeline {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, command.pipeline);
+                bound_pipeline = command.pipeline;
+                // the new pipeline's layout may not be compatible with the bound sets
+                bound_descriptor_sets = &[];
+            }
+            if !command.descriptor_sets.is_empty() && command.descriptor_sets[..] != *bound_descriptor_sets {
+                device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, command.pipeline_layout, 0, &command.descriptor_sets, &[]);
+                bound_descriptor_sets = &command.descriptor_sets;
+            }
+            if !command.vertex_buffers.is_empty() && command.vertex_buffers[..] != *bound_vertex_buffers {
+                let offsets = vec![0; command.vertex_buffers.len()];
+                device.cmd_bind_vertex_buffers(command_buffer, 0, &command.vertex_buffers, &offsets);
+                bound_vertex_buffers = &command.vertex_buffers;
+            }
+            if let Some(occlusion_query) = &command.occlusion_query {
+                occlusion_query.begin(device, command_buffer);
+            }
+            device.cmd_draw(command_buffer, command.vertex_count, command.instance_count, 0, 0);
+            if let Some(occlusion_query) = &command.occlusion_query {
+                occlusion_query.end(device, command_buffer);
+            }
+        }
+    }
+}
+
+/// Handed to the caller of `draw_frame` to describe the frame before it is recorded.
+pub struct FrameContext<'a> {
+    pub extent: vk::Extent2D,
+    /// Linear color the scene is cleared to, the one passed to `new` unless changed.
+    pub clear_color: [f32; 4],
+    draw_list: &'a mut DrawList,
+    occlusion_queries: &'a OcclusionQueries,
+    text: &'a mut TextRenderer,
+    sprites: &'a mut SpriteBatch,
+}
+
+impl<'a> FrameContext<'a> {
+    /// Starts with an empty draw list, no text and no sprites.
+    pub fn new(extent: vk::Extent2D, clear_color: [f32; 4], draw_list: &'a mut DrawList, occlusion_queries: &'a OcclusionQueries, text: &'a mut TextRenderer, sprites: &'a mut SpriteBatch) -> Self {
+        draw_list.clear();
+        text.clear();
+        sprites.clear();
+        Self {
+            extent,
+            clear_color,
+            draw_list,
+            occlusion_queries,
+            text,
+            sprites,
+        }
+    }
+
+    /// Adds a draw to the scene, drawn in every viewport.
+    pub fn draw(&mut self, command: DrawCommand) {
+        self.draw_list.push(command);
+    }
+
+    /// Whether `object` was visible in the frame whose occlusion queries were read back last,
+    /// `MAX_FRAMES_IN_FLIGHT` frames ago. Hidden objects can be skipped, a skipped object counts
+    /// as visible again once the frame it was skipped in is read back, so it's tested again.
+    pub fn is_visible(&self, object: OcclusionId) -> bool {
+        self.occlusion_queries.is_visible(object)
+    }
+
+    /// Draws `text` over the scene and post-processing, see `TextRenderer::draw_text`.
+    pub fn draw_text(&mut self, position: [f32; 2], size: f32, color: [f32; 4], text: &str) {
+        self.text.draw_text(position, size, color, text);
+    }
+
+    /// The 2D sprites and shapes drawn over the scene and post-processing, below the text.
+    pub fn sprites(&mut self) -> &mut SpriteBatch {
+        self.sprites
+    }
+}
@@ -0,0 +1,264 @@
+use ash::{
+    vk,
+    Entry,
+    version::{
+        DeviceV1_0,
+        EntryV1_0,
+        InstanceV1_0,
+    },
+};
+use log::{info, trace};
+use std::ffi::{CStr, CString};
+
+use crate::{
+    buffer::{Buffer, one_time_submit},
+    compute::ComputePipeline,
+    descriptors::{allocate_descriptor_sets, create_descriptor_pool, write_storage_buffer},
+    reflection::ShaderReflection,
+    specialization::SpecializationConstants,
+    instance_api_version,
+};
+#[cfg(feature = "shaderc")]
+use crate::{
+    shader_compiler::{ShaderCompiler, stage_for_filename},
+    shader_options::ShaderDefines,
+};
+#[cfg(not(feature = "shaderc"))]
+use crate::spirv::{default_spirv_dir, load_spirv, spirv_filename};
+
+const VALIDATION_LAYER: &str = "VK_LAYER_LUNARG_standard_validation";
+
+fn memory_barrier(device: &ash::Device, command_buffer: vk::CommandBuffer, src: (vk::PipelineStageFlags, vk::AccessFlags), dst: (vk::PipelineStageFlags, vk::AccessFlags)) {
+    let barriers = [vk::MemoryBarrier::builder()
+        .src_access_mask(src.1)
+        .dst_access_mask(dst.1)
+        .build()];
+    unsafe { device.cmd_pipeline_barrier(command_buffer, src.0, dst.0, vk::DependencyFlags::empty(), &barriers, &[], &[]) };
+}
+
+#[derive(Debug)]
+pub struct NoComputeDevice();
+impl std::error::Error for NoComputeDevice {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for NoComputeDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No device with a compute queue")
+    }
+}
+
+/// Runs compute shaders without a window: the instance has no surface extensions and
+/// the device only a single compute queue. Everything is synchronous, every call waits
+/// for the GPU to finish, so this works anywhere a Vulkan driver is installed, including
+/// CPU implementations like lavapipe or SwiftShader.
+pub struct HeadlessCompute {
+    _entry: Entry,
+    instance: ash::Instance,
+    device: ash::Device,
+    pub device_name: String,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    queue: vk::Queue,
+    command_pool: vk::CommandPool,
+}
+
+impl HeadlessCompute {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        trace!("HeadlessCompute::new");
+        let entry = Entry::new()?;
+        let app_info = vk::ApplicationInfo {
+            api_version: instance_api_version(&entry),
+            ..Default::default()
+        };
+        // validation is used when it's installed, but not required
+        let validation = CString::new(VALIDATION_LAYER).unwrap();
+        let layers_names_raw: Vec<*const i8> = entry.enumerate_instance_layer_properties()?.iter()
+            .filter(|layer| unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) } == validation.as_c_str())
+            .map(|_| validation.as_ptr())
+            .collect();
+        let create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layers_names_raw);
+        let instance = unsafe { entry.create_instance(&create_info, None) }?;
+
+        let selected = unsafe { instance.enumerate_physical_devices() }.map_err(Box::<dyn std::error::Error>::from).and_then(|devices| {
+            devices.into_iter().filter_map(|device| {
+                let queue_family = unsafe { instance.get_physical_device_queue_family_properties(device) }.iter()
+                    .position(|properties| properties.queue_flags.contains(vk::QueueFlags::COMPUTE))?;
+                let properties = unsafe { instance.get_physical_device_properties(device) };
+                let score = match properties.device_type {
+                    vk::PhysicalDeviceType::DISCRETE_GPU => 3,
+                    vk::PhysicalDeviceType::INTEGRATED_GPU => 2,
+                    vk::PhysicalDeviceType::VIRTUAL_GPU => 1,
+                    _ => 0,
+                };
+                let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) }.to_string_lossy().to_string();
+                Some((score, device, queue_family as u32, name))
+            }).max_by_key(|(score, ..)| *score).ok_or_else(|| NoComputeDevice().into())
+        });
+        let (_, physical_device, queue_family, device_name) = match selected {
+            Ok(selected) => selected,
+            Err(err) => {
+                unsafe { instance.destroy_instance(None) };
+                return Err(err);
+            }
+        };
+        info!("Headless compute on {}", device_name);
+
+        let queue_create_infos = [vk::DeviceQueueCreateInfo::builder()
+            .queue_family_index(queue_family)
+            .queue_priorities(&[1.0])
+            .build()];
+        let device_create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_create_infos);
+        let device = match unsafe { instance.create_device(physical_device, &device_create_info, None) } {
+            Ok(device) => device,
+            Err(err) => {
+                unsafe { instance.destroy_instance(None) };
+                return Err(Box::new(err));
+            }
+        };
+        let mut result = Self {
+            memory_properties: unsafe { instance.get_physical_device_memory_properties(physical_device) },
+            queue: unsafe { device.get_device_queue(queue_family, 0) },
+            command_pool: vk::CommandPool::null(),
+            _entry: entry,
+            instance,
+            device,
+            device_name,
+        };
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family);
+        result.command_pool = unsafe { result.device.create_command_pool(&pool_info, None) }?;
+        Ok(result)
+    }
+
+    /// The SPIR-V for a compute shader in the shader directory, compiled with shaderc or
+    /// precompiled by `--compile-shaders`, depending on the build.
+    pub fn load_shader(&self, filename: &str) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        #[cfg(feature = "shaderc")]
+        {
+            let mut compiler = ShaderCompiler::for_build().ok_or("Failed to initialize shaderc")?;
+            let stage = stage_for_filename(filename).unwrap_or(vk::ShaderStageFlags::COMPUTE);
+            compiler.compile(filename, stage, &ShaderDefines::new())
+        }
+        #[cfg(not(feature = "shaderc"))]
+        {
+            Ok(load_spirv(default_spirv_dir()?.join(spirv_filename(filename)))?)
+        }
+    }
+
+    /// A device local storage buffer holding a copy of `data`.
+    pub fn upload<T: Copy>(&self, data: &[T]) -> Result<Buffer, Box<dyn std::error::Error>> {
+        Buffer::device_local_with_data(&self.device, &self.memory_properties, self.command_pool, self.queue, vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC, data)
+    }
+
+    pub fn read<T: Copy>(&self, buffer: &Buffer) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+        let staging = Buffer::host_visible(&self.device, &self.memory_properties, buffer.size, vk::BufferUsageFlags::TRANSFER_DST)?;
+        let result = buffer.copy_to(&self.device, self.command_pool, self.queue, &staging)
+            .and_then(|_| staging.read(&self.device));
+        staging.destroy(&self.device);
+        Ok(result?)
+    }
+
+    /// Runs `code` once with one invocation per item. `buffers` are bound to set 0 in
+    /// binding order and `push_constants` has to match the shader's push constant block.
+    pub fn run<P: Copy>(&self, code: &[u32], specialization: &SpecializationConstants, buffers: &[&Buffer], push_constants: Option<&P>, items: [u32; 3]) -> Result<(), Box<dyn std::error::Error>> {
+        trace!("HeadlessCompute::run");
+        let reflection = ShaderReflection::reflect(code)?;
+        let create_info = vk::ShaderModuleCreateInfo::builder()
+            .code(code);
+        let module = unsafe { self.device.create_shader_module(&create_info, None) }?;
+        let pipeline = ComputePipeline::new(&self.device, vk::PipelineCache::null(), module, &reflection, specialization);
+        unsafe { self.device.destroy_shader_module(module, None) };
+        let pipeline = pipeline?;
+
+        let result = create_descriptor_pool(&self.device, &pipeline.reflection.descriptor_bindings, 1).map_err(Box::<dyn std::error::Error>::from).and_then(|descriptor_pool| {
+            let result = allocate_descriptor_sets(&self.device, descriptor_pool, &pipeline.descriptor_set_layouts).and_then(|descriptor_sets| {
+                for (binding, buffer) in buffers.iter().enumerate() {
+                    write_storage_buffer(&self.device, descriptor_sets[0], binding as u32, buffer.buffer, buffer.size);
+                }
+                one_time_submit(&self.device, self.command_pool, self.queue, |command_buffer| {
+                    // uploads and readbacks are separate submissions, the barriers order them with the dispatch
+                    memory_barrier(&self.device, command_buffer,
+                        (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+                        (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE));
+                    pipeline.bind(&self.device, command_buffer, &descriptor_sets);
+                    if let Some(push_constants) = push_constants {
+                        pipeline.push_constants(&self.device, command_buffer, push_constants);
+                    }
+                    pipeline.dispatch_items(&self.device, command_buffer, items);
+                    memory_barrier(&self.device, command_buffer,
+                        (vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE),
+                        (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ));
+                })
+            });
+            unsafe { self.device.destroy_descriptor_pool(descriptor_pool, None) };
+            Ok(result?)
+        });
+        pipeline.destroy(&self.device);
+        result
+    }
+
+    pub fn destroy_buffer(&self, buffer: Buffer) {
+        buffer.destroy(&self.device);
+    }
+}
+
+impl Drop for HeadlessCompute {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+            self.device.destroy_command_pool(self.command_pool, None);
+            self.device.destroy_device(None);
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// Matches the push constant block in saxpy.cs.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SaxpyParameters {
+    a: f32,
+    count: u32,
+}
+
+/// Runs saxpy.cs on a thousand items and compares the results with the CPU.
+pub fn saxpy(compute: &HeadlessCompute) -> Result<(), Box<dyn std::error::Error>> {
+    const COUNT: u32 = 1000;
+    let code = compute.load_shader("saxpy.cs")?;
+    let x: Vec<f32> = (0..COUNT).map(|idx| idx as f32).collect();
+    let y: Vec<f32> = (0..COUNT).map(|idx| (COUNT - idx) as f32).collect();
+    let parameters = SaxpyParameters { a: 2.0, count: COUNT };
+
+    let x_buffer = compute.upload(&x)?;
+    let y_buffer = compute.upload(&y)?;
+    let specialization = SpecializationConstants::new().set(0, 64u32);
+    let result = compute.run(&code, &specialization, &[&x_buffer, &y_buffer], Some(&parameters), [COUNT, 1, 1])
+        .and_then(|_| compute.read::<f32>(&y_buffer));
+    compute.destroy_buffer(x_buffer);
+    compute.destroy_buffer(y_buffer);
+
+    let mismatches = result?.iter().enumerate().filter(|(idx, value)| **value != parameters.a * x[*idx] + y[*idx]).count();
+    if mismatches > 0 {
+        return Err(format!("Compute self test on {}: {} of {} results are wrong", compute.device_name, mismatches, COUNT).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CI without a GPU can run this on lavapipe or SwiftShader with `cargo test -- --ignored`.
+    // Without the shaderc feature, the SPIR-V from --compile-shaders has to be next to the test executable.
+    #[test]
+    #[ignore = "needs a Vulkan driver"]
+    fn saxpy_runs_headless() {
+        let compute = HeadlessCompute::new().unwrap();
+        saxpy(&compute).unwrap();
+    }
+}
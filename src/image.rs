@@ -0,0 +1,206 @@
+use ash::{
+    vk,
+    version::{DeviceV1_0, InstanceV1_0},
+};
+
+use crate::buffer::find_memory_type;
+
+/// The first of `candidates` that supports `features` with the given tiling.
+pub fn find_supported_format(instance: &ash::Instance, physical_device: vk::PhysicalDevice, candidates: &[vk::Format], tiling: vk::ImageTiling, features: vk::FormatFeatureFlags) -> Option<vk::Format> {
+    candidates.iter().cloned().find(|format| {
+        let properties = unsafe { instance.get_physical_device_format_properties(physical_device, *format) };
+        match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+            _ => properties.optimal_tiling_features.contains(features),
+        }
+    })
+}
+
+pub fn has_stencil(format: vk::Format) -> bool {
+    matches!(format, vk::Format::S8_UINT | vk::Format::D16_UNORM_S8_UINT | vk::Format::D24_UNORM_S8_UINT | vk::Format::D32_SFLOAT_S8_UINT)
+}
+
+pub fn is_depth_format(format: vk::Format) -> bool {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => true,
+        _ => has_stencil(format) && format != vk::Format::S8_UINT,
+    }
+}
+
+/// The aspects an attachment view of `format` covers.
+pub fn aspect_flags(format: vk::Format) -> vk::ImageAspectFlags {
+    match (is_depth_format(format), has_stencil(format)) {
+        (true, true) => vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL,
+        (true, false) => vk::ImageAspectFlags::DEPTH,
+        (false, true) => vk::ImageAspectFlags::STENCIL,
+        (false, false) => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// Stages and accesses that have to finish before leaving `layout` or wait for entering it.
+fn layout_usage(layout: vk::ImageLayout) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    match layout {
+        vk::ImageLayout::UNDEFINED => (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty()),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_READ),
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT, vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => (vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS, vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        vk::ImageLayout::PRESENT_SRC_KHR => (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+        _ => (vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE),
+    }
+}
+
+/// Records a barrier moving `subresource_range` of `image` from `old_layout` to `new_layout`,
+/// with the stages and accesses derived from the layouts.
+pub fn transition_layout(device: &ash::Device, command_buffer: vk::CommandBuffer, image: vk::Image, subresource_range: vk::ImageSubresourceRange, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) {
+    let (src_stage, src_access) = layout_usage(old_layout);
+    let (dst_stage, dst_access) = layout_usage(new_layout);
+    let barriers = [vk::ImageMemoryBarrier::builder()
+        .old_layout(old_layout)
+        .new_layout(new_layout)
+        .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+        .image(image)
+        .subresource_range(subresource_range)
+        .src_access_mask(src_access)
+        .dst_access_mask(dst_access)
+        .build()];
+    unsafe { device.cmd_pipeline_barrier(command_buffer, src_stage, dst_stage, vk::DependencyFlags::empty(), &[], &[], &barriers) };
+}
+
+/// Number of levels in a full mip chain down to 1x1.
+pub fn mip_level_count(extent: vk::Extent2D) -> u32 {
+    32 - std::cmp::max(std::cmp::max(extent.width, extent.height), 1).leading_zeros()
+}
+
+/// Size of mip level `level` of an image of size `extent`.
+pub fn mip_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: std::cmp::max(extent.width >> level, 1),
+        height: std::cmp::max(extent.height >> level, 1),
+    }
+}
+
+/// A 2D image with its own memory allocation and a view of the whole image.
+pub struct Image {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl Image {
+    pub fn new(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, extent: vk::Extent2D, format: vk::Format, mip_levels: u32, samples: vk::SampleCountFlags, usage: vk::ImageUsageFlags) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut result = Self {
+            image: vk::Image::null(),
+            memory: vk::DeviceMemory::null(),
+            view: vk::ImageView::null(),
+            format,
+            extent,
+            mip_levels,
+            samples,
+        };
+        if let Err(err) = result.create(device, memory_properties, usage) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create(&mut self, device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, usage: vk::ImageUsageFlags) -> Result<(), Box<dyn std::error::Error>> {
+        let image_info = vk::ImageCreateInfo::builder()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(vk::Extent3D::builder().width(self.extent.width).height(self.extent.height).depth(1).build())
+            .mip_levels(self.mip_levels)
+            .array_layers(1)
+            .format(self.format)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .usage(usage)
+            .samples(self.samples)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        self.image = unsafe { device.create_image(&image_info, None) }?;
+
+        let requirements = unsafe { device.get_image_memory_requirements(self.image) };
+        let alloc_info = vk::MemoryAllocateInfo::builder()
+            .allocation_size(requirements.size)
+            .memory_type_index(find_memory_type(memory_properties, requirements.memory_type_bits, vk::MemoryPropertyFlags::DEVICE_LOCAL)?);
+        self.memory = unsafe { device.allocate_memory(&alloc_info, None) }?;
+        unsafe { device.bind_image_memory(self.image, self.memory, 0) }?;
+
+        let view_info = vk::ImageViewCreateInfo::builder()
+            .image(self.image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(self.format)
+            .subresource_range(self.subresource_range());
+        self.view = unsafe { device.create_image_view(&view_info, None) }?;
+        Ok(())
+    }
+
+    /// All of the image.
+    pub fn subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::builder()
+            .aspect_mask(aspect_flags(self.format))
+            .base_mip_level(0)
+            .level_count(self.mip_levels)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_image_view(self.view, None);
+            device.destroy_image(self.image, None);
+            device.free_memory(self.memory, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    #[test]
+    fn mip_level_counts() {
+        let cases = [
+            ((1, 1), 1),
+            ((0, 0), 1),
+            ((2, 1), 2),
+            ((256, 256), 9),
+            ((255, 255), 8),
+            ((300, 200), 9),
+            ((1, 1024), 11),
+            ((5, 3), 3),
+        ];
+        for ((width, height), levels) in &cases {
+            assert_eq!(mip_level_count(extent(*width, *height)), *levels, "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn mip_extents() {
+        let cases = [
+            ((256, 256), 0, (256, 256)),
+            ((256, 256), 8, (1, 1)),
+            ((300, 200), 1, (150, 100)),
+            ((300, 200), 3, (37, 25)),
+            ((300, 200), 8, (1, 1)),
+            ((5, 3), 1, (2, 1)),
+            ((5, 3), 2, (1, 1)),
+            ((1, 1024), 4, (1, 64)),
+        ];
+        for ((width, height), level, (level_width, level_height)) in &cases {
+            let level_extent = mip_extent(extent(*width, *height), *level);
+            assert_eq!((level_extent.width, level_extent.height), (*level_width, *level_height), "{}x{} level {}", width, height, level);
+        }
+    }
+}
@@ -1,8 +1,8 @@
 // #![allow(unused)]
 
-use log::{info, error, debug, trace, log};
+use log::{info, warn, error, debug, trace, log};
 use winit::{
-    event::{Event, WindowEvent},
+    event::{Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
     dpi::LogicalSize,
@@ -36,6 +36,9 @@ use std::{
     ptr::null,
     collections::HashSet,
     os::raw::c_char,
+    path::PathBuf,
+    time::Instant,
+    sync::Arc,
 };
 
 mod queue_families;
@@ -44,6 +47,71 @@ mod suitability;
 use crate::suitability::{is_device_suitable, DEVICE_EXTENSIONS};
 mod swap_chain_support;
 use crate::swap_chain_support::SwapChainSupportDetails;
+mod reflection;
+use crate::reflection::{ShaderReflection, PipelineReflection, InterfaceMismatches};
+#[cfg(feature = "shaderc")]
+mod shader_loader;
+mod shader_options;
+use crate::shader_options::ShaderDefines;
+#[cfg(feature = "shaderc")]
+use crate::shader_options::TargetEnvironment;
+#[cfg(feature = "shaderc")]
+mod shader_cache;
+#[cfg(feature = "shaderc")]
+mod shader_diagnostics;
+#[cfg(feature = "shaderc")]
+use crate::shader_diagnostics::ShaderCompileError;
+#[cfg(feature = "shaderc")]
+mod shader_compiler;
+#[cfg(feature = "shaderc")]
+use crate::shader_compiler::ShaderCompiler;
+mod spirv;
+mod pipeline_builder;
+use crate::pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines};
+mod pipeline_cache;
+use crate::pipeline_cache::{load_pipeline_cache, save_pipeline_cache};
+mod viewport;
+use crate::viewport::ViewportLayout;
+mod buffer;
+mod descriptors;
+use crate::descriptors::{allocate_descriptor_sets, create_descriptor_pool, create_descriptor_set_layouts, write_combined_image_sampler};
+mod compute;
+mod particles;
+use crate::particles::{ParticleSystem, PARTICLE_SHADERS, fountain};
+mod headless;
+use crate::headless::{HeadlessCompute, saxpy};
+mod specialization;
+mod image;
+use crate::image::{Image, find_supported_format};
+mod config;
+use crate::config::{Config, ConfigWatcher};
+mod multisampling;
+use crate::multisampling::{Multisampling, msaa_sample_counts, supported_sample_counts};
+mod texture;
+use crate::texture::{SamplerCache, SamplerKey, TextureLoader};
+mod render_graph;
+use crate::render_graph::{RenderGraph, ImageHandle, PassHandle, ImageInfo, AttachmentLoad};
+mod post_process;
+use crate::post_process::{Effect, PostProcessPass, FULLSCREEN_SHADER, HDR_FORMAT, output_format, record_blit};
+mod frame;
+use crate::frame::{Frame, DrawCommand, DrawList, FrameContext, MAX_FRAMES_IN_FLIGHT, record_draws};
+mod recording;
+use crate::recording::{Recorder, RecordingJob, Inheritance, split_draws};
+mod profiler;
+use crate::profiler::Profiler;
+mod queries;
+use crate::queries::{OcclusionId, OcclusionQueries};
+mod overlay;
+use crate::overlay::{Overlay, OverlayInfo, OVERLAY_SHADERS};
+mod debug_ui;
+use crate::debug_ui::{DebugUi, Tweakables, DEBUG_UI_SHADERS, tweakables_window};
+mod text;
+use crate::text::{SdfFont, TextRenderer, TEXT_SHADERS};
+mod sprites;
+use crate::sprites::{Sprite, SpriteBatch, SpriteTexture, SPRITE_SHADERS};
+use crate::spirv::default_spirv_dir;
+#[cfg(not(feature = "shaderc"))]
+use crate::spirv::{load_spirv, spirv_filename};
 
 extern "system" fn debug_messenger_callback(message_severity: vk::DebugUtilsMessageSeverityFlagsEXT, message_types: vk::DebugUtilsMessageTypeFlagsEXT, p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT, _p_user_data: *mut std::ffi::c_void) -> vk::Bool32 {
     let message = unsafe { CStr::from_ptr((*p_callback_data).p_message) };
@@ -76,7 +144,32 @@ struct SelectedDevice {
     swap_chain_support_details: SwapChainSupportDetails,
 }
 
+/// The render graph of a frame and the handles recording it needs.
+struct FrameGraph {
+    graph: RenderGraph,
+    swapchain_image: ImageHandle,
+    particles: PassHandle,
+    scene: PassHandle,
+    /// The image the scene is cleared in, multisampled with multisampling.
+    scene_color: ImageHandle,
+    /// Every post-process pass with the image it reads, in the order of `Config::post_process`.
+    post_process: Vec<(PassHandle, ImageHandle)>,
+    /// Copies the final image to the swapchain image.
+    blit: PassHandle,
+    blit_source: ImageHandle,
+    /// Draws the sprites and shapes of the frame over the swapchain image.
+    sprites: PassHandle,
+    /// Draws the text of the frame over the sprites.
+    text: PassHandle,
+    /// Draws the overlay over the swapchain image.
+    overlay: PassHandle,
+    /// Draws the debug UI over the overlay.
+    debug_ui: PassHandle,
+}
+
 struct VulkanExperiment {
+    /// The Vulkan version the instance was created with, see `instance_api_version`.
+    api_version: u32,
     instance: ash::Instance,
     debug_utils_messenger: vk::DebugUtilsMessengerEXT,
     surface: vk::SurfaceKHR,
@@ -84,21 +177,71 @@ struct VulkanExperiment {
     device: Option<ash::Device>,
     swapchain: vk::SwapchainKHR,
     swapchain_extent: vk::Extent2D,
+    present_mode: vk::PresentModeKHR,
     swapchain_images: Vec<vk::Image>,
     swapchain_image_views: Vec<vk::ImageView>,
+    /// Set when presenting reported that the swapchain no longer matches the window.
+    swapchain_outdated: bool,
+    viewport_layout: ViewportLayout,
+    config_watcher: ConfigWatcher,
+    config: Config,
+    supported_sample_counts: vk::SampleCountFlags,
+    sample_rate_shading: bool,
+    /// Whether pipeline statistics can be queried around the secondary command buffers.
+    pipeline_statistics: bool,
+    multisampling: Multisampling,
+    depth_format: vk::Format,
+    /// Sized like the swapchain and recreated with it.
+    frame_graph: Option<FrameGraph>,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    sampler_cache: SamplerCache,
+    texture: Option<Image>,
+    color_grading_lut: Option<Image>,
+    /// The pipelines of the effects in `Config::post_process`.
+    post_process: Vec<PostProcessPass>,
     pipeline_layout: vk::PipelineLayout,
+    /// The render pass of the scene, owned by the frame graph.
     render_pass: vk::RenderPass,
     graphics_pipeline: vk::Pipeline,
-    swapchain_framebuffers: Vec<vk::Framebuffer>,
+    pipeline_cache: vk::PipelineCache,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    particles: Option<ParticleSystem>,
+    /// For one-off commands like uploads, the frames have their own pools.
     command_pool: vk::CommandPool,
-    command_buffers: Vec<vk::CommandBuffer>,
-    image_available_semaphore: vk::Semaphore,
-    render_finished_semaphore: vk::Semaphore,
+    frames: Vec<Frame>,
+    current_frame: usize,
+    /// The fence of the frame using each swapchain image, so two frames don't render to the same image.
+    images_in_flight: Vec<vk::Fence>,
+    draw_list: DrawList,
+    /// Records the scene in secondary command buffers on worker threads.
+    recorder: Option<Recorder>,
+    /// CPU and GPU times of the frame and its passes.
+    profiler: Option<Profiler>,
+    occlusion_queries: Option<OcclusionQueries>,
+    /// Draws the sprites and shapes of `FrameContext::sprites`.
+    sprites: Option<SpriteBatch>,
+    /// The triangle's texture as a sprite texture.
+    texture_sprite: Option<SpriteTexture>,
+    /// Draws the text of `FrameContext::draw_text`.
+    text: Option<TextRenderer>,
+    /// Frame statistics, toggled with F1.
+    overlay: Option<Overlay>,
+    /// Settings window, toggled with F2.
+    debug_ui: Option<DebugUi>,
+    /// What the debug UI set, compared to the current state after every frame.
+    tweakables: Tweakables,
+    last_frame: Option<Instant>,
 
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
 
-    compiler: shaderc::Compiler,
+    #[cfg(feature = "shaderc")]
+    shader_compiler: ShaderCompiler,
+    /// Where the SPIR-V produced by `--compile-shaders` is loaded from.
+    #[cfg(not(feature = "shaderc"))]
+    spirv_dir: PathBuf,
 
     // VULKAN EXTENSIONS
     debug_utils_ext: DebugUtils,
@@ -107,34 +250,116 @@ struct VulkanExperiment {
     swapchain_ext: Option<Swapchain>,
 }
 
-type VulkanResult<T> = Result<T, Box<dyn std::error::Error>>;
+type VulkanError = Box<dyn std::error::Error>;
+type VulkanResult<T> = Result<T, VulkanError>;
+
+/// The newest Vulkan version used, requested only when the loader supports it.
+const API_VERSION: u32 = vk_make_version!(1, 1, 0);
+
+/// The Vulkan version to request from `entry`: `API_VERSION` unless the loader is older,
+/// in which case creating an instance with it would fail on Vulkan 1.0 loaders.
+pub fn instance_api_version(entry: &Entry) -> u32 {
+    match entry.try_enumerate_instance_version() {
+        Ok(Some(version)) => std::cmp::min(version, API_VERSION),
+        _ => vk_make_version!(1, 0, 0),
+    }
+}
+
+/// Pipeline cache contents from the previous run, relative to the working directory like `log.yaml`.
+const PIPELINE_CACHE_FILE: &str = "pipeline_cache.bin";
+
+/// Settings reloaded while running, relative to the working directory like `log.yaml`.
+const CONFIG_FILE: &str = "settings.cfg";
+
+const TRIANGLE_SHADERS: [&str; 2] = ["triangle.vs", "triangle.fs"];
+/// Sampled by the triangle, relative to the working directory.
+const TEXTURE_FILE: &str = "assets/textures/checker.png";
+const MAX_ANISOTROPY: u32 = 16;
+/// The triangle is culled while its occlusion query finds it hidden.
+const TRIANGLE_OCCLUSION: OcclusionId = OcclusionId(0);
+/// Used by the color grading effect, relative to the working directory.
+const LUT_FILE: &str = "assets/textures/neutral_lut.png";
+/// The font of `FrameContext::draw_text`, relative to the working directory.
+const FONT_FILE: &str = "assets/fonts/DejaVuSans.ttf";
+
+/// In order of preference.
+const DEPTH_FORMATS: [vk::Format; 3] = [vk::Format::D32_SFLOAT, vk::Format::D32_SFLOAT_S8_UINT, vk::Format::D24_UNORM_S8_UINT];
+
+const PARTICLE_COUNT: u32 = 4096;
+
+/// Drawn in the bottom left corner with `FrameContext::draw_text`.
+const HELP_TEXT: &str = "F1 statistics   F2 settings";
+const HELP_TEXT_SIZE: f32 = 18.0;
+const HELP_TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.7];
+
+/// Size of the panel in the top right corner that shows the sprite batch.
+const SPRITE_PANEL_SIZE: f32 = 160.0;
+
+/// Longest time step of the simulation, so it doesn't jump after a stall.
+const MAX_TIME_STEP: f32 = 0.1;
 
 impl VulkanExperiment {
     pub fn new(entry: &Entry) -> VulkanResult<Self> {
         trace!("VulkanExperiment::new");
-        let instance = Self::create_instance(&entry)?;
+        let api_version = instance_api_version(entry);
+        let instance = Self::create_instance(entry, api_version)?;
         Ok(VulkanExperiment {
+            api_version,
             debug_utils_messenger: Default::default(),
             surface: Default::default(),
             physical_device: Default::default(),
             device: Default::default(),
             swapchain: Default::default(),
             swapchain_extent: Default::default(),
+            present_mode: Default::default(),
             swapchain_images: Default::default(),
             swapchain_image_views: Default::default(),
+            swapchain_outdated: false,
+            viewport_layout: Default::default(),
+            config_watcher: ConfigWatcher::new(CONFIG_FILE),
+            config: Default::default(),
+            supported_sample_counts: Default::default(),
+            sample_rate_shading: false,
+            pipeline_statistics: false,
+            multisampling: Default::default(),
+            depth_format: Default::default(),
+            frame_graph: Default::default(),
+            descriptor_set_layouts: Default::default(),
+            descriptor_pool: Default::default(),
+            descriptor_sets: Default::default(),
+            sampler_cache: SamplerCache::new(None),
+            texture: Default::default(),
+            color_grading_lut: Default::default(),
+            post_process: Default::default(),
             pipeline_layout: Default::default(),
             render_pass: Default::default(),
             graphics_pipeline: Default::default(),
-            swapchain_framebuffers: Default::default(),
+            pipeline_cache: Default::default(),
+            memory_properties: Default::default(),
+            particles: Default::default(),
             command_pool: Default::default(),
-            command_buffers: Default::default(),
-            image_available_semaphore: Default::default(),
-            render_finished_semaphore: Default::default(),
+            frames: Default::default(),
+            current_frame: 0,
+            images_in_flight: Default::default(),
+            draw_list: Default::default(),
+            recorder: None,
+            profiler: None,
+            occlusion_queries: None,
+            sprites: None,
+            texture_sprite: None,
+            text: None,
+            overlay: None,
+            debug_ui: None,
+            tweakables: Default::default(),
+            last_frame: None,
 
             graphics_queue: Default::default(),
             present_queue: Default::default(),
 
-            compiler: shaderc::Compiler::new().unwrap(),
+            #[cfg(feature = "shaderc")]
+            shader_compiler: ShaderCompiler::for_build().unwrap(),
+            #[cfg(not(feature = "shaderc"))]
+            spirv_dir: default_spirv_dir()?,
 
             debug_utils_ext: DebugUtils::new(entry, &instance),
             surface_ext: Surface::new(entry, &instance),
@@ -146,10 +371,10 @@ impl VulkanExperiment {
     fn layer_names() -> Vec<CString> {
         vec![CString::new("VK_LAYER_LUNARG_standard_validation").unwrap()]
     }
-    pub fn create_instance(entry: &Entry) -> VulkanResult<ash::Instance> {
+    pub fn create_instance(entry: &Entry, api_version: u32) -> VulkanResult<ash::Instance> {
         trace!("create_instance");
         let app_info = vk::ApplicationInfo {
-            api_version: vk_make_version!(1, 0, 0),
+            api_version,
             ..Default::default()
         };
         let layer_names = Self::layer_names();
@@ -227,7 +452,20 @@ impl VulkanExperiment {
     }
     pub fn create_device(&mut self) -> VulkanResult<()> {
         trace!("create_device");
-        let physical_device_features = vk::PhysicalDeviceFeatures::builder();
+        let supported_features = unsafe { self.instance.get_physical_device_features(self.physical_device.device) };
+        self.sample_rate_shading = supported_features.sample_rate_shading == vk::TRUE;
+        let sampler_anisotropy = supported_features.sampler_anisotropy == vk::TRUE;
+        // the scene is recorded in secondary command buffers, which inherit the statistics query of the frame
+        self.pipeline_statistics = supported_features.pipeline_statistics_query == vk::TRUE && supported_features.inherited_queries == vk::TRUE;
+        // the compressed texture formats are only usable with their features enabled
+        let physical_device_features = vk::PhysicalDeviceFeatures::builder()
+            .sample_rate_shading(self.sample_rate_shading)
+            .sampler_anisotropy(sampler_anisotropy)
+            .pipeline_statistics_query(self.pipeline_statistics)
+            .inherited_queries(self.pipeline_statistics)
+            .texture_compression_bc(supported_features.texture_compression_bc == vk::TRUE)
+            .texture_compression_etc2(supported_features.texture_compression_etc2 == vk::TRUE)
+            .texture_compression_astc_ldr(supported_features.texture_compression_astc_ldr == vk::TRUE);
         let layer_names = Self::layer_names();
         let layers_names_raw: Vec<*const i8> = layer_names
             .iter()
@@ -252,6 +490,24 @@ impl VulkanExperiment {
             .enabled_extension_names(&device_extensions);
         
         self.device = Some(unsafe { self.instance.create_device(self.physical_device.device, &device_create_info, None) }?);
+
+        let properties = unsafe { self.instance.get_physical_device_properties(self.physical_device.device) };
+        let api_version = std::cmp::min(self.api_version, properties.api_version);
+        info!("Vulkan {}.{}", ash::vk_version_major!(api_version), ash::vk_version_minor!(api_version));
+        #[cfg(feature = "shaderc")]
+        {
+            self.shader_compiler.settings.target = TargetEnvironment::for_api_version(api_version);
+        }
+        self.memory_properties = unsafe { self.instance.get_physical_device_memory_properties(self.physical_device.device) };
+        self.supported_sample_counts = supported_sample_counts(&properties.limits);
+        debug!("Supported sample counts: {:?}", self.supported_sample_counts);
+        self.multisampling = Multisampling::from_config(&self.config, self.supported_sample_counts, self.sample_rate_shading);
+        info!("Multisampling: {:?}", self.multisampling);
+        self.depth_format = find_supported_format(&self.instance, self.physical_device.device, &DEPTH_FORMATS, vk::ImageTiling::OPTIMAL, vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+            .ok_or("No supported depth format")?;
+        debug!("Depth format: {:?}", self.depth_format);
+        self.sampler_cache = SamplerCache::new(Some(properties.limits.max_sampler_anisotropy).filter(|_| sampler_anisotropy));
+        self.pipeline_cache = load_pipeline_cache(self.device.as_ref().unwrap(), &properties, PIPELINE_CACHE_FILE)?;
         self.swapchain_ext = Some(Swapchain::new(&self.instance, self.device.as_ref().unwrap()));
 
         Ok(())
@@ -259,8 +515,16 @@ impl VulkanExperiment {
 
     pub fn create_swapchain(&mut self, window: &winit::window::Window) -> VulkanResult<()> {
         trace!("create_swapchain");
+        // the frame is copied to the swapchain image at the end
+        if !self.physical_device.swap_chain_support_details.capabilities.supported_usage_flags.contains(vk::ImageUsageFlags::TRANSFER_DST) {
+            return Err("The swapchain images can't be copied to".into());
+        }
         let surface_format = self.physical_device.swap_chain_support_details.choose_format();
-        let present_mode = self.physical_device.swap_chain_support_details.choose_present_mode();
+        let support_details = &self.physical_device.swap_chain_support_details;
+        // the one picked in the debug UI, as long as the surface still supports it
+        self.present_mode = self.tweakables.present_mode.filter(|present_mode| support_details.present_modes.contains(present_mode))
+            .unwrap_or_else(|| support_details.choose_present_mode());
+        self.tweakables.present_mode = Some(self.present_mode);
         self.swapchain_extent = self.physical_device.swap_chain_support_details.choose_swap_extent(window.inner_size().width as u32, window.inner_size().height as u32);
         let image_count = {
             if self.physical_device.swap_chain_support_details.capabilities.max_image_count > 0 &&
@@ -277,7 +541,11 @@ impl VulkanExperiment {
             .image_color_space(surface_format.color_space)
             .image_extent(self.swapchain_extent)
             .image_array_layers(1)
-            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT);
+            .image_usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_DST)
+            .pre_transform(self.physical_device.swap_chain_support_details.capabilities.current_transform)
+            .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+            .present_mode(self.present_mode)
+            .clipped(true);
         
         let queue_family_indices = [self.physical_device.indices.graphics.unwrap(), self.physical_device.indices.present.unwrap()];
 
@@ -286,13 +554,10 @@ impl VulkanExperiment {
                 .queue_family_indices(&queue_family_indices)
         } else {
             swap_chain_create_info.image_sharing_mode(vk::SharingMode::EXCLUSIVE)
-                .pre_transform(self.physical_device.swap_chain_support_details.capabilities.current_transform)
-                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
-                .present_mode(present_mode)
-                .clipped(true)
         };
         self.swapchain = unsafe { self.swapchain_ext.as_ref().unwrap().create_swapchain(&swap_chain_create_info, None) }?;
         self.swapchain_images = unsafe { self.swapchain_ext.as_ref().unwrap().get_swapchain_images(self.swapchain) }?;
+        self.images_in_flight = vec![vk::Fence::null(); self.swapchain_images.len()];
 
         Ok(())
     }
@@ -320,193 +585,437 @@ impl VulkanExperiment {
         Ok(())
     }
 
-    fn create_shader_module(&mut self, code: &str, filename: &str, kind: shaderc::ShaderKind) -> VulkanResult<vk::ShaderModule> {
+    fn create_shader_module(&mut self, filename: &str, stage: vk::ShaderStageFlags, defines: &ShaderDefines) -> VulkanResult<(vk::ShaderModule, ShaderReflection)> {
         trace!("create_shader_module {}", filename);
-        let artifact = self.compiler.compile_into_spirv(code, kind, filename, "main", None)?;
-        let binary = artifact.as_binary();
+        #[cfg(feature = "shaderc")]
+        let binary = self.shader_compiler.compile(filename, stage, defines)?;
+        #[cfg(not(feature = "shaderc"))]
+        let binary = {
+            if !defines.is_empty() {
+                return Err(format!("{}: shader variants with defines need runtime compilation", filename).into());
+            }
+            load_spirv(self.spirv_dir.join(spirv_filename(filename)))?
+        };
+        let reflection = ShaderReflection::reflect(&binary)?;
+        if reflection.stage != stage {
+            warn!("{}: expected a {:?} shader, got {:?}", filename, stage, reflection.stage);
+        }
+        debug!("{}: SPIR-V {}.{}, {:?} stage, entry point {}", filename, (binary[1] >> 16) & 0xff, (binary[1] >> 8) & 0xff, reflection.stage, reflection.entry_point);
+        for block in &reflection.push_constants {
+            debug!("{}: push constant block {} ({} bytes at offset {})", filename, block.name, block.size, block.offset);
+        }
+        for constant in &reflection.specialization_constants {
+            debug!("{}: specialization constant {} = {} ({:?})", filename, constant.constant_id, constant.name, constant.scalar_type);
+        }
         let create_info = vk::ShaderModuleCreateInfo::builder()
-            .code(binary);
-        Ok(unsafe { self.device.as_ref().unwrap().create_shader_module(&create_info, None) }?)
+            .code(&binary);
+        Ok((unsafe { self.device.as_ref().unwrap().create_shader_module(&create_info, None) }?, reflection))
+    }
+
+    fn create_descriptor_set_layouts(&mut self, reflection: &PipelineReflection) -> VulkanResult<()> {
+        trace!("create_descriptor_set_layouts");
+        for binding in &reflection.descriptor_bindings {
+            debug!("Descriptor set {} binding {}: {} ({:?} x{}, {:?})", binding.set, binding.binding, binding.name, binding.descriptor_type, binding.count, binding.stage_flags);
+        }
+        self.descriptor_set_layouts = create_descriptor_set_layouts(self.device.as_ref().unwrap(), reflection)?;
+        Ok(())
+    }
+
+    /// Allocates one descriptor set per set layout. Every sampler binds the texture.
+    fn create_descriptor_sets(&mut self, reflection: &PipelineReflection) -> VulkanResult<()> {
+        trace!("create_descriptor_sets");
+        if self.descriptor_set_layouts.is_empty() {
+            return Ok(());
+        }
+        let device = self.device.as_ref().unwrap();
+        self.descriptor_pool = create_descriptor_pool(device, &reflection.descriptor_bindings, 1)?;
+        self.descriptor_sets = allocate_descriptor_sets(device, self.descriptor_pool, &self.descriptor_set_layouts)?;
+        let sampler = self.sampler_cache.get(device, SamplerKey::new(vk::Filter::LINEAR, vk::SamplerAddressMode::REPEAT).anisotropy(MAX_ANISOTROPY))?;
+        let texture = self.texture.as_ref().unwrap();
+        for binding in &reflection.descriptor_bindings {
+            match binding.descriptor_type {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER => write_combined_image_sampler(device, self.descriptor_sets[binding.set as usize], binding.binding, texture.view, sampler),
+                descriptor_type => warn!("Nothing to bind to {} ({:?})", binding.name, descriptor_type),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn create_texture(&mut self) -> VulkanResult<()> {
+        trace!("create_texture");
+        let loader = TextureLoader {
+            instance: &self.instance,
+            physical_device: self.physical_device.device,
+            device: self.device.as_ref().unwrap(),
+            memory_properties: &self.memory_properties,
+            command_pool: self.command_pool,
+            queue: self.graphics_queue,
+        };
+        let texture = loader.load(TEXTURE_FILE)?;
+        debug!("{}: {}x{} {:?}, {} mip levels", TEXTURE_FILE, texture.extent.width, texture.extent.height, texture.format, texture.mip_levels);
+        self.texture = Some(texture);
+        // color_grading.fs only samples level 0
+        self.color_grading_lut = Some(loader.load_without_mipmaps(LUT_FILE)?);
+        Ok(())
     }
 
     pub fn create_graphics_pipeline(&mut self) -> VulkanResult<()> {
         trace!("create_graphics_pipeline");
-        let vertex_shader   = self.create_shader_module(include_str!("shaders/triangle.vs"), "triangle.vs", shaderc::ShaderKind::Vertex)?;
-        let fragment_shader = self.create_shader_module(include_str!("shaders/triangle.fs"), "triangle.fs", shaderc::ShaderKind::Fragment)?;
-
-        let main = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
-
-        let shader_stages = [
-            vk::PipelineShaderStageCreateInfo::builder()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .module(vertex_shader)
-                .name(main)
-                .build(),
-            vk::PipelineShaderStageCreateInfo::builder()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .module(fragment_shader)
-                .name(main)
-                .build(),
-        ];
-        
-        let vertex_input_info = vk::PipelineVertexInputStateCreateInfo::builder()
-            .vertex_binding_descriptions(&[])
-            .vertex_attribute_descriptions(&[]);
-        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
-            .primitive_restart_enable(false);
-        let viewports = [vk::Viewport::builder()
-            .x(0.0)
-            .y(0.0)
-            .width(self.swapchain_extent.width as f32)
-            .height(self.swapchain_extent.height as f32)
-            .min_depth(0.0)
-            .max_depth(1.0)
-            .build()
-        ];
-        let scissors = [vk::Rect2D::builder()
-            .offset(vk::Offset2D::builder().x(0).y(0).build())
-            .extent(self.swapchain_extent)
-            .build()
-        ];
-        
-        let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
-            .viewport_count(1)
-            .viewports(&viewports)
-            .scissors(&scissors);
-        let rasterizer = vk::PipelineRasterizationStateCreateInfo::builder()
-            .depth_clamp_enable(false)
-            .rasterizer_discard_enable(false)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .front_face(vk::FrontFace::CLOCKWISE)
-            .depth_bias_enable(false)
-            .depth_bias_constant_factor(0.0)
-            .depth_bias_clamp(0.0)
-            .depth_bias_slope_factor(0.0);
-        let multisampling = vk::PipelineMultisampleStateCreateInfo::builder()
-            .sample_shading_enable(false)
-            .rasterization_samples(vk::SampleCountFlags::TYPE_1)
-            .min_sample_shading(1.0)
-            // .sample_mask(&[])
-            .alpha_to_coverage_enable(false)
-            .alpha_to_one_enable(false);
-        
-        let color_blend_attachments = [vk::PipelineColorBlendAttachmentState::builder()
-            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A)
-            .blend_enable(false)
-            .src_color_blend_factor(vk::BlendFactor::ONE)
-            .dst_color_blend_factor(vk::BlendFactor::ZERO)
-            .color_blend_op(vk::BlendOp::ADD)
-            .src_alpha_blend_factor(vk::BlendFactor::ONE)
-            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
-            .alpha_blend_op(vk::BlendOp::ADD)
-            .build()
-        ];
+        let defines = ShaderDefines::new();
+        let (vertex_shader, vertex_reflection) = self.create_shader_module(TRIANGLE_SHADERS[0], vk::ShaderStageFlags::VERTEX, &defines)?;
+        let result = self.create_shader_module(TRIANGLE_SHADERS[1], vk::ShaderStageFlags::FRAGMENT, &defines).and_then(|(fragment_shader, fragment_reflection)| {
+            let result = PipelineReflection::merge(&[&vertex_reflection, &fragment_reflection]).map_err(VulkanError::from).and_then(|reflection| {
+                if !reflection.mismatches.is_empty() {
+                    return Err(Box::new(InterfaceMismatches(reflection.mismatches)));
+                }
+                self.build_graphics_pipeline(vertex_shader, fragment_shader, &reflection)
+            });
+            unsafe { self.device.as_ref().unwrap().destroy_shader_module(fragment_shader, None) };
+            result
+        });
+
+        trace!("cleanup shader modules");
+
+        unsafe { self.device.as_ref().unwrap().destroy_shader_module(vertex_shader, None) };
+        if result.is_err() {
+            // the layouts and descriptor pool created before the failure
+            self.destroy_graphics_pipeline();
+        }
+        result
+    }
+
+    /// Creates the triangle's descriptor sets, layouts and pipeline from its shader modules.
+    fn build_graphics_pipeline(&mut self, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection) -> VulkanResult<()> {
+        self.create_descriptor_set_layouts(reflection)?;
+        self.create_descriptor_sets(reflection)?;
 
-        let color_blending = vk::PipelineColorBlendStateCreateInfo::builder()
-            .logic_op_enable(false)
-            .logic_op(vk::LogicOp::COPY)
-            .attachments(&color_blend_attachments)
-            .blend_constants([0.0; 4]);
-        
         let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
-            .set_layouts(&[])
-            .push_constant_ranges(&[]);
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
 
         trace!("Creating pipeline layout");
 
         self.pipeline_layout = unsafe { self.device.as_ref().unwrap().create_pipeline_layout(&pipeline_layout_info, None) }?;
 
-        let pipeline_infos = [vk::GraphicsPipelineCreateInfo::builder()
-            .stages(&shader_stages)
-            .vertex_input_state(&vertex_input_info)
-            .input_assembly_state(&input_assembly)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterizer)
-            .multisample_state(&multisampling)
-            .color_blend_state(&color_blending)
-            .layout(self.pipeline_layout)
-            .render_pass(self.render_pass)
-            .subpass(0)
-            .base_pipeline_index(-1)
-            .build()
-        ];
+        let builder = GraphicsPipelineBuilder::new(self.pipeline_layout, self.render_pass)
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader)
+            .reflected_vertex_input(reflection)
+            .multisampling(&self.multisampling)
+            .depth_test(true, true, vk::CompareOp::LESS);
 
         trace!("Creating graphics pipeline");
 
-        self.graphics_pipeline = unsafe { self.device.as_ref().unwrap().create_graphics_pipelines(vk::PipelineCache::null(), &pipeline_infos, None) }.map_err(|err| err.1)?[0];
+        self.graphics_pipeline = create_graphics_pipelines(self.device.as_ref().unwrap(), self.pipeline_cache, &[builder])?[0];
+        Ok(())
+    }
 
-        trace!("cleanup shader modules");
+    pub fn create_particle_system(&mut self) -> VulkanResult<()> {
+        trace!("create_particle_system");
+        let defines = ShaderDefines::new();
+        let mut modules = Vec::new();
+        let mut reflections = Vec::new();
+        for (filename, stage) in PARTICLE_SHADERS.iter().zip(&[vk::ShaderStageFlags::COMPUTE, vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT]) {
+            match self.create_shader_module(filename, *stage, &defines) {
+                Ok((module, reflection)) => {
+                    modules.push(module);
+                    reflections.push(reflection);
+                }
+                Err(err) => {
+                    for module in modules {
+                        unsafe { self.device.as_ref().unwrap().destroy_shader_module(module, None) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
 
-        unsafe {
-            self.device.as_ref().unwrap().destroy_shader_module(vertex_shader, None);
-            self.device.as_ref().unwrap().destroy_shader_module(fragment_shader, None);
+        let device = self.device.as_ref().unwrap();
+        let result = PipelineReflection::merge(&[&reflections[1], &reflections[2]]).map_err(VulkanError::from).and_then(|reflection| {
+            if !reflection.mismatches.is_empty() {
+                return Err(Box::new(InterfaceMismatches(reflection.mismatches)));
+            }
+            ParticleSystem::new(device, &self.memory_properties, self.command_pool, self.graphics_queue, self.pipeline_cache, self.render_pass, &self.multisampling, (modules[0], &reflections[0]), (modules[1], &reflections[1]), modules[2], &fountain(PARTICLE_COUNT))
+        });
+        for module in modules {
+            unsafe { device.destroy_shader_module(module, None) };
         }
+        self.particles = Some(result?);
         Ok(())
     }
 
-    pub fn create_render_pass(&mut self) -> VulkanResult<()> {
-        trace!("create_render_pass");
-        let color_attachments = [vk::AttachmentDescription::builder()
-            .format(self.physical_device.swap_chain_support_details.choose_format().format)
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .build()
-        ];
-        
-        let color_attachment_refs = [vk::AttachmentReference::builder()
-            .attachment(0)
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .build()
-        ];
-        
-        let subpasses = [vk::SubpassDescription::builder()
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .color_attachments(&color_attachment_refs)
-            .build()
-        ];
+    /// Describes the frame: the particle simulation, followed by the scene drawn to an HDR
+    /// image, through a multisampled image with multisampling. The post-process effects each
+    /// read the previous image and write a new one, the last of which is copied to the
+    /// swapchain image. The render passes are compatible with the previous ones as long as
+    /// the formats, sample count and effects stay the same, so the pipelines outlive the graph.
+    pub fn create_frame_graph(&mut self) -> VulkanResult<()> {
+        trace!("create_frame_graph");
+        let swapchain_format = self.physical_device.swap_chain_support_details.choose_format().format;
+        let effects = &self.config.post_process;
+        let final_format = if effects.is_empty() { HDR_FORMAT } else { output_format(effects, effects.len() - 1) };
+        for (format, feature) in &[(swapchain_format, vk::FormatFeatureFlags::BLIT_DST), (final_format, vk::FormatFeatureFlags::BLIT_SRC)] {
+            let properties = unsafe { self.instance.get_physical_device_format_properties(self.physical_device.device, *format) };
+            if !properties.optimal_tiling_features.contains(*feature) {
+                return Err(format!("{:?} doesn't support {:?}", format, feature).into());
+            }
+        }
+        let samples = self.multisampling.samples;
+        let mut graph = RenderGraph::new();
+        let swapchain_image = graph.import_image("swapchain", ImageInfo::new(swapchain_format, self.swapchain_extent), vk::ImageLayout::UNDEFINED, vk::ImageLayout::PRESENT_SRC_KHR);
+        let hdr_image = graph.create_image("hdr", ImageInfo::new(HDR_FORMAT, self.swapchain_extent));
+        let particle_buffer = graph.import_buffer("particles");
+        let depth_image = graph.create_image("depth", ImageInfo::new(self.depth_format, self.swapchain_extent).samples(samples));
+
+        let particles = graph.add_pass("particles")
+            .read_buffer(particle_buffer, vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_READ)
+            .write_buffer(particle_buffer, vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE)
+            .handle();
+        let clear_color = AttachmentLoad::Clear(vk::ClearValue { color: vk::ClearColorValue::default() });
+        let clear_depth = AttachmentLoad::Clear(vk::ClearValue { depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 } });
+        let scene_color = if self.multisampling.is_enabled() {
+            graph.create_image("hdr_multisampled", ImageInfo::new(HDR_FORMAT, self.swapchain_extent).samples(samples))
+        } else {
+            hdr_image
+        };
+        let mut scene = graph.add_pass("scene")
+            .color_attachment(scene_color, clear_color)
+            .depth_attachment(depth_image, clear_depth)
+            .secondary_command_buffers();
+        if self.multisampling.is_enabled() {
+            scene = scene.resolve_attachment(hdr_image);
+        }
+        let scene = scene.read_buffer(particle_buffer, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ).handle();
+
+        let mut input = hdr_image;
+        let mut post_process = Vec::new();
+        for (idx, effect) in effects.iter().enumerate() {
+            let output = graph.create_image(effect.name(), ImageInfo::new(output_format(effects, idx), self.swapchain_extent));
+            let pass = graph.add_pass(effect.name())
+                .sampled_image(input, vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .color_attachment(output, AttachmentLoad::DontCare)
+                .handle();
+            post_process.push((pass, input));
+            input = output;
+        }
+        let blit = graph.add_pass("blit")
+            .transfer_source(input)
+            .transfer_destination(swapchain_image)
+            .handle();
+        let sprites = graph.add_pass("sprites")
+            .color_attachment(swapchain_image, AttachmentLoad::Load)
+            .handle();
+        let text = graph.add_pass("text")
+            .color_attachment(swapchain_image, AttachmentLoad::Load)
+            .handle();
+        let overlay = graph.add_pass("overlay")
+            .color_attachment(swapchain_image, AttachmentLoad::Load)
+            .handle();
+        let debug_ui = graph.add_pass("debug_ui")
+            .color_attachment(swapchain_image, AttachmentLoad::Load)
+            .handle();
+
+        let result = graph.compile(self.device.as_ref().unwrap(), &self.memory_properties);
+        if let Err(err) = result {
+            graph.destroy(self.device.as_ref().unwrap());
+            return Err(err);
+        }
+        self.render_pass = graph.render_pass(scene).unwrap();
+        self.frame_graph = Some(FrameGraph { graph, swapchain_image, particles, scene, scene_color, post_process, blit, blit_source: input, sprites, text, overlay, debug_ui });
+        Ok(())
+    }
 
-        let dependencies = [vk::SubpassDependency::builder()
-            .src_subpass(vk::SUBPASS_EXTERNAL)
-            .dst_subpass(0)
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-            .build()
-        ];
-        
-        let render_pass_info = vk::RenderPassCreateInfo::builder()
-            .attachments(&color_attachments)
-            .subpasses(&subpasses)
-            .dependencies(&dependencies);
-        
-        self.render_pass = unsafe { self.device.as_ref().unwrap().create_render_pass(&render_pass_info, None) }?;
+    /// Creates the pipelines of the effects in the config. Needs the frame graph for their render passes.
+    pub fn create_post_process(&mut self) -> VulkanResult<()> {
+        trace!("create_post_process");
+        let (vertex_shader, vertex_reflection) = self.create_shader_module(FULLSCREEN_SHADER, vk::ShaderStageFlags::VERTEX, &ShaderDefines::new())?;
+        let mut result = Ok(());
+        for effect in self.config.post_process.clone() {
+            result = self.create_post_process_pass(effect, vertex_shader, &vertex_reflection);
+            if result.is_err() {
+                break;
+            }
+        }
+        unsafe { self.device.as_ref().unwrap().destroy_shader_module(vertex_shader, None) };
+        result?;
+        self.bind_post_process_inputs()
+    }
+
+    /// Creates the sprite batch's pipelines and adds the triangle's texture to it. Needs the
+    /// frame graph for their render pass.
+    pub fn create_sprites(&mut self) -> VulkanResult<()> {
+        trace!("create_sprites");
+        let sampler = self.sampler_cache.get(self.device.as_ref().unwrap(), SamplerKey::new(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let defines = ShaderDefines::new();
+        let mut modules = Vec::new();
+        let mut reflections = Vec::new();
+        for (filename, stage) in SPRITE_SHADERS.iter().zip(&[vk::ShaderStageFlags::VERTEX, vk::ShaderStageFlags::FRAGMENT, vk::ShaderStageFlags::FRAGMENT]) {
+            match self.create_shader_module(filename, *stage, &defines) {
+                Ok((module, reflection)) => {
+                    modules.push(module);
+                    reflections.push(reflection);
+                }
+                Err(err) => {
+                    for module in modules {
+                        unsafe { self.device.as_ref().unwrap().destroy_shader_module(module, None) };
+                    }
+                    return Err(err);
+                }
+            }
+        }
 
+        let device = self.device.as_ref().unwrap();
+        let frame_graph = self.frame_graph.as_ref().unwrap();
+        let render_pass = frame_graph.graph.render_pass(frame_graph.sprites).unwrap();
+        let result = PipelineReflection::merge(&[&reflections[0], &reflections[1]])
+            .and_then(|reflection| PipelineReflection::merge(&[&reflections[0], &reflections[2]]).map(|shape_reflection| (reflection, shape_reflection)))
+            .map_err(VulkanError::from)
+            .and_then(|(reflection, shape_reflection)| {
+                let mismatches: Vec<_> = reflection.mismatches.iter().chain(&shape_reflection.mismatches).cloned().collect();
+                if !mismatches.is_empty() {
+                    return Err(Box::new(InterfaceMismatches(mismatches)));
+                }
+                SpriteBatch::new(device, self.pipeline_cache, render_pass, modules[0], modules[1], modules[2], &reflection, sampler)
+            });
+        for module in modules {
+            unsafe { device.destroy_shader_module(module, None) };
+        }
+        let mut sprites = result?;
+        match sprites.add_texture(device, self.texture.as_ref().unwrap().view) {
+            Ok(texture_sprite) => {
+                self.sprites = Some(sprites);
+                self.texture_sprite = Some(texture_sprite);
+                Ok(())
+            }
+            Err(err) => {
+                sprites.destroy(device);
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Loads the font and creates the text's pipeline. Needs the frame graph for its render pass.
+    pub fn create_text(&mut self) -> VulkanResult<()> {
+        trace!("create_text");
+        let font = SdfFont::new(std::fs::read(FONT_FILE)?)?;
+        let sampler = self.sampler_cache.get(self.device.as_ref().unwrap(), SamplerKey::new(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let defines = ShaderDefines::new();
+        let (vertex_shader, vertex_reflection) = self.create_shader_module(TEXT_SHADERS[0], vk::ShaderStageFlags::VERTEX, &defines)?;
+        let fragment_shader = self.create_shader_module(TEXT_SHADERS[1], vk::ShaderStageFlags::FRAGMENT, &defines);
+        let device = self.device.as_ref().unwrap();
+        let result = fragment_shader.and_then(|(fragment_shader, fragment_reflection)| {
+            let frame_graph = self.frame_graph.as_ref().unwrap();
+            let render_pass = frame_graph.graph.render_pass(frame_graph.text).unwrap();
+            let loader = TextureLoader {
+                instance: &self.instance,
+                physical_device: self.physical_device.device,
+                device,
+                memory_properties: &self.memory_properties,
+                command_pool: self.command_pool,
+                queue: self.graphics_queue,
+            };
+            let result = PipelineReflection::merge(&[&vertex_reflection, &fragment_reflection]).map_err(VulkanError::from).and_then(|reflection| {
+                if !reflection.mismatches.is_empty() {
+                    return Err(Box::new(InterfaceMismatches(reflection.mismatches)));
+                }
+                TextRenderer::new(&loader, self.pipeline_cache, render_pass, vertex_shader, fragment_shader, &reflection, sampler, font)
+            });
+            unsafe { device.destroy_shader_module(fragment_shader, None) };
+            result
+        });
+        unsafe { device.destroy_shader_module(vertex_shader, None) };
+        self.text = Some(result?);
+        Ok(())
+    }
+
+    /// Creates the overlay's pipeline. Needs the frame graph for its render pass.
+    pub fn create_overlay(&mut self) -> VulkanResult<()> {
+        trace!("create_overlay");
+        let defines = ShaderDefines::new();
+        let (vertex_shader, _) = self.create_shader_module(OVERLAY_SHADERS[0], vk::ShaderStageFlags::VERTEX, &defines)?;
+        let fragment_shader = self.create_shader_module(OVERLAY_SHADERS[1], vk::ShaderStageFlags::FRAGMENT, &defines);
+        let device = self.device.as_ref().unwrap();
+        let result = fragment_shader.and_then(|(fragment_shader, _)| {
+            let frame_graph = self.frame_graph.as_ref().unwrap();
+            let render_pass = frame_graph.graph.render_pass(frame_graph.overlay).unwrap();
+            let result = Overlay::new(device, &self.memory_properties, self.pipeline_cache, render_pass, vertex_shader, fragment_shader);
+            unsafe { device.destroy_shader_module(fragment_shader, None) };
+            result
+        });
+        unsafe { device.destroy_shader_module(vertex_shader, None) };
+        self.overlay = Some(result?);
+        Ok(())
+    }
+
+    /// Creates the debug UI's pipeline. Needs the frame graph for its render pass.
+    pub fn create_debug_ui(&mut self, hidpi_factor: f64) -> VulkanResult<()> {
+        trace!("create_debug_ui");
+        let sampler = self.sampler_cache.get(self.device.as_ref().unwrap(), SamplerKey::new(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let defines = ShaderDefines::new();
+        let (vertex_shader, vertex_reflection) = self.create_shader_module(DEBUG_UI_SHADERS[0], vk::ShaderStageFlags::VERTEX, &defines)?;
+        let fragment_shader = self.create_shader_module(DEBUG_UI_SHADERS[1], vk::ShaderStageFlags::FRAGMENT, &defines);
+        let device = self.device.as_ref().unwrap();
+        let result = fragment_shader.and_then(|(fragment_shader, fragment_reflection)| {
+            let frame_graph = self.frame_graph.as_ref().unwrap();
+            let render_pass = frame_graph.graph.render_pass(frame_graph.debug_ui).unwrap();
+            let result = PipelineReflection::merge(&[&vertex_reflection, &fragment_reflection]).map_err(VulkanError::from).and_then(|reflection| {
+                if !reflection.mismatches.is_empty() {
+                    return Err(Box::new(InterfaceMismatches(reflection.mismatches)));
+                }
+                DebugUi::new(device, self.pipeline_cache, render_pass, vertex_shader, fragment_shader, &reflection, sampler)
+            });
+            unsafe { device.destroy_shader_module(fragment_shader, None) };
+            result
+        });
+        unsafe { device.destroy_shader_module(vertex_shader, None) };
+        let mut debug_ui = result?;
+        debug_ui.pixels_per_point = hidpi_factor as f32;
+        self.debug_ui = Some(debug_ui);
+        Ok(())
+    }
+
+    fn create_post_process_pass(&mut self, effect: Effect, vertex_shader: vk::ShaderModule, vertex_reflection: &ShaderReflection) -> VulkanResult<()> {
+        let (fragment_shader, fragment_reflection) = self.create_shader_module(effect.shader(), vk::ShaderStageFlags::FRAGMENT, &ShaderDefines::new())?;
+        let device = self.device.as_ref().unwrap();
+        let frame_graph = self.frame_graph.as_ref().unwrap();
+        let render_pass = frame_graph.graph.render_pass(frame_graph.post_process[self.post_process.len()].0).unwrap();
+        let result = PipelineReflection::merge(&[vertex_reflection, &fragment_reflection]).map_err(VulkanError::from).and_then(|reflection| {
+            if !reflection.mismatches.is_empty() {
+                return Err(Box::new(InterfaceMismatches(reflection.mismatches)));
+            }
+            PostProcessPass::new(device, self.pipeline_cache, render_pass, effect, vertex_shader, fragment_shader, &reflection)
+        });
+        unsafe { device.destroy_shader_module(fragment_shader, None) };
+        self.post_process.push(result?);
         Ok(())
     }
 
-    pub fn create_framebuffers(&mut self) -> VulkanResult<()> {
-        trace!("create_framebuffers");
-        self.swapchain_framebuffers = self.swapchain_image_views.iter().map(|image_views| {
-            let attachments = [*image_views];
+    /// Binds the images of the frame graph to the effects reading them, after either was created.
+    fn bind_post_process_inputs(&mut self) -> VulkanResult<()> {
+        trace!("bind_post_process_inputs");
+        let device = self.device.as_ref().unwrap();
+        let sampler = self.sampler_cache.get(device, SamplerKey::new(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE))?;
+        let lut = self.color_grading_lut.as_ref().unwrap().view;
+        let frame_graph = self.frame_graph.as_ref().unwrap();
+        for (pass, (_, input)) in self.post_process.iter().zip(&frame_graph.post_process) {
+            pass.bind_inputs(device, frame_graph.graph.transient_image(*input).unwrap().1, lut, sampler);
+        }
+        Ok(())
+    }
 
-            let framebuffer_info = vk::FramebufferCreateInfo::builder()
-                .render_pass(self.render_pass)
-                .attachments(&attachments)
-                .width(self.swapchain_extent.width)
-                .height(self.swapchain_extent.height)
-                .layers(1);
-            unsafe { self.device.as_ref().unwrap().create_framebuffer(&framebuffer_info, None) }
-        }).collect::<Result<Vec<_>,_>>()?;
+    fn destroy_post_process(&mut self) {
+        trace!("destroy_post_process");
+        let device = self.device.as_ref().unwrap();
+        for pass in self.post_process.drain(..) {
+            pass.destroy(device);
+        }
+    }
 
+    /// Writes the frame graph in Graphviz dot format.
+    pub fn dump_frame_graph(&self, path: &str) -> VulkanResult<()> {
+        trace!("dump_frame_graph");
+        std::fs::write(path, self.frame_graph.as_ref().unwrap().graph.to_dot())?;
+        info!("Frame graph written to {}", path);
         Ok(())
     }
 
@@ -520,47 +1029,137 @@ impl VulkanExperiment {
         Ok(())
     }
 
-    pub fn create_command_buffers(&mut self) -> VulkanResult<()> {
-        trace!("create_command_buffers");
-        let alloc_info = vk::CommandBufferAllocateInfo::builder()
-            .command_pool(self.command_pool)
-            .level(vk::CommandBufferLevel::PRIMARY)
-            .command_buffer_count(self.swapchain_framebuffers.len() as u32);
-        
+    pub fn create_frames(&mut self) -> VulkanResult<()> {
+        trace!("create_frames");
         let device = self.device.as_ref().unwrap();
-        self.command_buffers = unsafe { device.allocate_command_buffers(&alloc_info) }?;
-
-        for (command_buffer, framebuffer) in self.command_buffers.iter().zip(self.swapchain_framebuffers.iter()) {
-            let begin_info = vk::CommandBufferBeginInfo::builder();
-            unsafe { device.begin_command_buffer(*command_buffer, &begin_info) }?;
-
-            let clear_color_values = [vk::ClearValue {
-                color: vk::ClearColorValue::default(),
-            }];
-            let render_pass_info = vk::RenderPassBeginInfo::builder()
-                .render_pass(self.render_pass)
-                .framebuffer(*framebuffer)
-                .render_area(vk::Rect2D::builder().offset(vk::Offset2D::builder().x(0).y(0).build()).extent(self.swapchain_extent).build())
-                .clear_values(&clear_color_values);
-
-            unsafe {
-                device.cmd_begin_render_pass(*command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
-                device.cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, self.graphics_pipeline);
-                device.cmd_draw(*command_buffer, 3, 1, 0, 0);
-                device.cmd_end_render_pass(*command_buffer);
-            }
-            unsafe { device.end_command_buffer(*command_buffer) }?;
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            self.frames.push(Frame::new(device, self.physical_device.indices.graphics.unwrap())?);
         }
-
+        let recorder = Recorder::new(device, self.physical_device.indices.graphics.unwrap())?;
+        info!("Recording the scene on {} threads", recorder.thread_count());
+        self.recorder = Some(recorder);
+        self.profiler = Some(Profiler::new(&self.instance, self.physical_device.device, device, self.physical_device.indices.graphics.unwrap(), self.pipeline_statistics)?);
+        self.occlusion_queries = Some(OcclusionQueries::new(device)?);
         Ok(())
     }
 
-    pub fn create_semaphores(&mut self) -> VulkanResult<()> {
-        trace!("create_semaphores");
-        let semaphore_info = vk::SemaphoreCreateInfo::builder();
+    /// The textured triangle, for the draw list.
+    pub fn triangle(&self) -> DrawCommand {
+        DrawCommand::new(self.graphics_pipeline, self.pipeline_layout, 3)
+            .descriptor_sets(&self.descriptor_sets)
+    }
+
+    /// Records the frame graph for the swapchain image `image_index`, drawing the draw list.
+    fn record_frame(&mut self, command_buffer: vk::CommandBuffer, image_index: usize, delta_time: f32, clear_color: [f32; 4]) -> VulkanResult<()> {
+        trace!("record_frame");
         let device = self.device.as_ref().unwrap();
-        self.image_available_semaphore = unsafe { device.create_semaphore(&semaphore_info, None) }?;
-        self.render_finished_semaphore = unsafe { device.create_semaphore(&semaphore_info, None) }?;
+        let clear_values = [vk::ClearValue {
+            color: vk::ClearColorValue { float32: clear_color },
+        }, vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+        }];
+        let particles = &self.particles;
+        let mut draws = self.draw_list.commands().to_vec();
+        if let Some(particles) = particles {
+            draws.push(particles.draw_command());
+        }
+        // every region draws with its own occlusion queries, a query can only be used once per frame
+        let occlusion_queries = self.occlusion_queries.as_mut().unwrap();
+        let regions: Vec<_> = self.viewport_layout.regions(self.swapchain_extent).into_iter().map(|region| {
+            let region_draws: Arc<[DrawCommand]> = draws.iter().map(|draw| DrawCommand {
+                occlusion_query: draw.occlusion_id.and_then(|object| occlusion_queries.allocate(object)),
+                ..draw.clone()
+            }).collect();
+            (region, region_draws)
+        }).collect();
+        let recorder = self.recorder.as_ref().unwrap();
+        let sprites = self.sprites.as_mut().unwrap();
+        sprites.prepare(device, &self.memory_properties, self.current_frame)?;
+        let sprites = &*sprites;
+        let text = self.text.as_mut().unwrap();
+        text.prepare(device, self.current_frame)?;
+        let text = &*text;
+        let overlay = self.overlay.as_mut().unwrap();
+        overlay.update(delta_time);
+        overlay.prepare(device, self.current_frame, &OverlayInfo {
+            device_name: &self.physical_device.name,
+            present_mode: self.present_mode,
+            extent: self.swapchain_extent,
+            profiler: self.profiler.as_ref().unwrap(),
+        })?;
+        let overlay = &*overlay;
+        let debug_ui = self.debug_ui.as_mut().unwrap();
+        debug_ui.prepare(&TextureLoader {
+            instance: &self.instance,
+            physical_device: self.physical_device.device,
+            device,
+            memory_properties: &self.memory_properties,
+            command_pool: self.command_pool,
+            queue: self.graphics_queue,
+        }, self.current_frame)?;
+        let debug_ui = &*debug_ui;
+        let mut profiler = self.profiler.as_mut();
+        let current_frame = self.current_frame;
+        let mut recording_result = Ok(());
+        let post_process = &self.post_process;
+        let extent = self.swapchain_extent;
+        let image = self.swapchain_images[image_index];
+        let view = self.swapchain_image_views[image_index];
+        let frame_graph = self.frame_graph.as_mut().unwrap();
+        frame_graph.graph.set_clear_value(frame_graph.scene, frame_graph.scene_color, clear_values[0]);
+        let post_process_passes = frame_graph.post_process.clone();
+        let blit_source = frame_graph.graph.transient_image(frame_graph.blit_source).unwrap().0;
+        let (particle_pass, scene_pass, blit_pass, sprite_pass, text_pass, overlay_pass, debug_ui_pass) = (frame_graph.particles, frame_graph.scene, frame_graph.blit, frame_graph.sprites, frame_graph.text, frame_graph.overlay, frame_graph.debug_ui);
+        let inheritance = Inheritance {
+            render_pass: frame_graph.graph.render_pass(scene_pass).unwrap(),
+            subpass: 0,
+            pipeline_statistics: profiler.as_ref().map_or(vk::QueryPipelineStatisticFlags::empty(), |profiler| profiler.inherited_statistics()),
+        };
+        frame_graph.graph.execute(device, command_buffer, &[(frame_graph.swapchain_image, image, view)], profiler.as_deref_mut(), |pass, command_buffer| {
+            if pass == particle_pass {
+                if let Some(particles) = particles {
+                    particles.record_update(device, command_buffer, delta_time);
+                }
+            } else if pass == scene_pass {
+                // the jobs are executed in order, each region's draws after the region before it
+                let mut jobs: Vec<RecordingJob> = Vec::new();
+                for (idx, (region, draws)) in regions.iter().enumerate() {
+                    for (part, range) in split_draws(draws.len(), recorder.thread_count()).into_iter().enumerate() {
+                        let (region, draws) = (*region, draws.clone());
+                        // regions can overlap, so every one after the first starts from a cleared area like the first one does
+                        let clear = idx > 0 && part == 0;
+                        jobs.push(Box::new(move |device, command_buffer| {
+                            region.record(device, command_buffer);
+                            if clear {
+                                region.clear(device, command_buffer, &clear_values);
+                            }
+                            record_draws(device, command_buffer, &draws[range]);
+                        }));
+                    }
+                }
+                match recorder.record(current_frame, inheritance, jobs) {
+                    Ok(secondary_command_buffers) => unsafe { device.cmd_execute_commands(command_buffer, &secondary_command_buffers) },
+                    Err(err) => recording_result = Err(err),
+                }
+            } else if let Some(idx) = post_process_passes.iter().position(|(post_process_pass, _)| *post_process_pass == pass) {
+                post_process[idx].record(device, command_buffer, extent);
+            } else if pass == blit_pass {
+                record_blit(device, command_buffer, blit_source, image, extent);
+            } else if pass == sprite_pass {
+                sprites.record(device, command_buffer, current_frame, extent);
+            } else if pass == text_pass {
+                text.record(device, command_buffer, current_frame, extent);
+            } else if pass == overlay_pass {
+                overlay.record(device, command_buffer, current_frame, extent);
+            } else if pass == debug_ui_pass {
+                debug_ui.record(device, command_buffer, current_frame);
+            }
+        })?;
+        recording_result?;
+        if let Some(profiler) = profiler {
+            profiler.end_frame(device, command_buffer);
+        }
+        unsafe { device.end_command_buffer(command_buffer) }?;
         Ok(())
     }
 
@@ -571,30 +1170,379 @@ impl VulkanExperiment {
         Ok(())
     }
 
-    pub fn draw_frame(&mut self) -> VulkanResult<()> {
+    /// Rebuilds the pipelines whose shader sources changed on disk. If compilation fails,
+    /// the error is logged and the previous pipeline stays in use. Rebuilding the particle
+    /// system restarts the simulation.
+    #[cfg(feature = "shaderc")]
+    pub fn reload_shaders(&mut self) -> VulkanResult<()> {
+        let changed = self.shader_compiler.loader.changed_files();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        info!("Shader sources changed: {}", changed.join(", "));
+        let loader = &self.shader_compiler.loader;
+        let affected = |filenames: &[&str]| filenames.iter().any(|filename| loader.is_affected(filename, &changed));
+        let mut effect_shaders = vec![FULLSCREEN_SHADER];
+        effect_shaders.extend(self.config.post_process.iter().map(|effect| effect.shader()));
+        let triangle = affected(&TRIANGLE_SHADERS);
+        let particles = affected(&PARTICLE_SHADERS);
+        let post_process = affected(&effect_shaders);
+        let sprites = affected(&SPRITE_SHADERS);
+        let text = affected(&TEXT_SHADERS);
+        let overlay = affected(&OVERLAY_SHADERS);
+        let debug_ui = affected(&DEBUG_UI_SHADERS);
+        if !(triangle || particles || post_process || sprites || text || overlay || debug_ui) {
+            info!("No pipeline uses the changed shaders, nothing to rebuild");
+            return Ok(());
+        }
+        // the previous pipelines are destroyed as soon as their replacements are built
+        unsafe { self.device.as_ref().unwrap().device_wait_idle() }?;
+
+        if triangle {
+            self.reload_graphics_pipeline();
+        }
+        if particles {
+            self.rebuild("Particle system", |app| &mut app.particles, Self::create_particle_system, ParticleSystem::destroy);
+        }
+        if post_process {
+            self.reload_post_process();
+        }
+        if sprites {
+            self.rebuild("Sprite batch", |app| &mut app.sprites, Self::create_sprites, SpriteBatch::destroy);
+        }
+        if text {
+            self.rebuild("Text renderer", |app| &mut app.text, Self::create_text, TextRenderer::destroy);
+        }
+        if overlay {
+            let visible = self.overlay.as_ref().is_some_and(|overlay| overlay.visible);
+            self.rebuild("Overlay", |app| &mut app.overlay, Self::create_overlay, Overlay::destroy);
+            if let Some(overlay) = &mut self.overlay {
+                overlay.visible = visible;
+            }
+        }
+        if debug_ui {
+            let (visible, pixels_per_point) = self.debug_ui.as_ref().map_or((false, 1.0), |debug_ui| (debug_ui.visible, debug_ui.pixels_per_point));
+            self.rebuild("Debug UI", |app| &mut app.debug_ui, |app| app.create_debug_ui(pixels_per_point.into()), DebugUi::destroy);
+            if let Some(debug_ui) = &mut self.debug_ui {
+                debug_ui.visible = visible;
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the object in `slot` again with `create`, keeping the previous one if that
+    /// fails. The GPU has to be done with the previous one.
+    #[cfg(feature = "shaderc")]
+    fn rebuild<T, S, C>(&mut self, name: &str, slot: S, create: C, destroy: fn(&T, &ash::Device))
+        where S: Fn(&mut Self) -> &mut Option<T>, C: FnOnce(&mut Self) -> VulkanResult<()> {
+        let previous = slot(self).take();
+        match create(self) {
+            Ok(()) => {
+                if let Some(previous) = previous {
+                    destroy(&previous, self.device.as_ref().unwrap());
+                }
+                info!("{} rebuilt", name);
+            }
+            Err(err) => {
+                error!("Shader reload failed, keeping the previous {}: {}", name.to_lowercase(), err);
+                *slot(self) = previous;
+            }
+        }
+    }
+
+    /// Rebuilds the triangle's pipeline, see `reload_shaders`. The GPU has to be idle.
+    #[cfg(feature = "shaderc")]
+    fn reload_graphics_pipeline(&mut self) {
+        let previous_pipeline = std::mem::take(&mut self.graphics_pipeline);
+        let previous_pipeline_layout = std::mem::take(&mut self.pipeline_layout);
+        let previous_descriptor_set_layouts = std::mem::take(&mut self.descriptor_set_layouts);
+        let previous_descriptor_pool = std::mem::take(&mut self.descriptor_pool);
+        let previous_descriptor_sets = std::mem::take(&mut self.descriptor_sets);
+        if let Err(err) = self.create_graphics_pipeline() {
+            error!("Shader reload failed, keeping the previous pipeline: {}", err);
+            self.graphics_pipeline = previous_pipeline;
+            self.pipeline_layout = previous_pipeline_layout;
+            self.descriptor_set_layouts = previous_descriptor_set_layouts;
+            self.descriptor_pool = previous_descriptor_pool;
+            self.descriptor_sets = previous_descriptor_sets;
+            return;
+        }
+
+        unsafe {
+            let device = self.device.as_ref().unwrap();
+            device.destroy_pipeline(previous_pipeline, None);
+            device.destroy_pipeline_layout(previous_pipeline_layout, None);
+            for descriptor_set_layout in previous_descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+            }
+            device.destroy_descriptor_pool(previous_descriptor_pool, None);
+        }
+        info!("Pipeline rebuilt");
+    }
+
+    /// Rebuilds the pipelines of the post-process effects, see `reload_shaders`. The GPU has to be idle.
+    #[cfg(feature = "shaderc")]
+    fn reload_post_process(&mut self) {
+        let previous = std::mem::take(&mut self.post_process);
+        match self.create_post_process() {
+            Ok(()) => {
+                let device = self.device.as_ref().unwrap();
+                for pass in previous {
+                    pass.destroy(device);
+                }
+                info!("Post-processing rebuilt");
+            }
+            Err(err) => {
+                error!("Shader reload failed, keeping the previous post-processing: {}", err);
+                // the passes created before the failure
+                self.destroy_post_process();
+                self.post_process = previous;
+            }
+        }
+    }
+
+    /// Destroys the triangle's pipeline, its layouts and descriptor pool, leaving null handles behind.
+    fn destroy_graphics_pipeline(&mut self) {
+        let device = self.device.as_ref().unwrap();
+        unsafe {
+            device.destroy_pipeline(std::mem::take(&mut self.graphics_pipeline), None);
+            device.destroy_pipeline_layout(std::mem::take(&mut self.pipeline_layout), None);
+            for descriptor_set_layout in self.descriptor_set_layouts.drain(..) {
+                device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+            }
+            device.destroy_descriptor_pool(std::mem::take(&mut self.descriptor_pool), None);
+        }
+        self.descriptor_sets.clear();
+    }
+
+    pub fn set_viewport_layout(&mut self, viewport_layout: ViewportLayout) {
+        trace!("set_viewport_layout");
+        info!("Viewport layout: {:?}", viewport_layout);
+        self.viewport_layout = viewport_layout;
+    }
+
+    /// Reads the config file, falling back to the defaults if it is invalid.
+    pub fn load_config(&mut self) {
+        trace!("load_config");
+        match self.config_watcher.load() {
+            Ok(config) => self.config = config,
+            Err(err) => error!("{}: {}, using the defaults", CONFIG_FILE, err),
+        }
+        self.tweakables.msaa_samples = self.config.msaa_samples;
+        debug!("{:?}", self.config);
+    }
+
+    /// Applies changes to the config file.
+    pub fn poll_config(&mut self) -> VulkanResult<()> {
+        let config = match self.config_watcher.poll() {
+            None => return Ok(()),
+            Some(Ok(config)) => config,
+            Some(Err(err)) => {
+                error!("{}: {}, keeping the previous settings", CONFIG_FILE, err);
+                return Ok(());
+            }
+        };
+        if config == self.config {
+            return Ok(());
+        }
+        info!("Config changed: {:?}", config);
+        let post_process_changed = config.post_process != self.config.post_process;
+        self.config = config;
+        self.tweakables.msaa_samples = self.config.msaa_samples;
+        if post_process_changed {
+            self.set_post_process()?;
+        }
+        let multisampling = Multisampling::from_config(&self.config, self.supported_sample_counts, self.sample_rate_shading);
+        if multisampling != self.multisampling {
+            self.set_multisampling(multisampling)?;
+        }
+        Ok(())
+    }
+
+    /// The sample count is baked into the render pass and every pipeline using it, so all of
+    /// them are created again. This includes the particle system, restarting the simulation.
+    /// If any of them can't be created, the previous ones are kept with the previous sample count.
+    pub fn set_multisampling(&mut self, multisampling: Multisampling) -> VulkanResult<()> {
+        trace!("set_multisampling");
+        info!("Multisampling: {:?}", multisampling);
+        unsafe { self.device.as_ref().unwrap().device_wait_idle() }?;
+        let previous_multisampling = std::mem::replace(&mut self.multisampling, multisampling);
+        let previous_frame_graph = self.frame_graph.take();
+        let previous_render_pass = std::mem::take(&mut self.render_pass);
+        let previous_particles = self.particles.take();
+        let previous_pipeline = std::mem::take(&mut self.graphics_pipeline);
+        let previous_pipeline_layout = std::mem::take(&mut self.pipeline_layout);
+        let previous_descriptor_set_layouts = std::mem::take(&mut self.descriptor_set_layouts);
+        let previous_descriptor_pool = std::mem::take(&mut self.descriptor_pool);
+        let previous_descriptor_sets = std::mem::take(&mut self.descriptor_sets);
+        let result = self.create_frame_graph()
+            .and_then(|()| self.create_graphics_pipeline())
+            .and_then(|()| self.create_particle_system());
+        if let Err(err) = result {
+            error!("Multisampling {:?} failed, keeping the previous settings: {}", multisampling, err);
+            // the objects created before the failure
+            self.destroy_frame_graph();
+            self.destroy_graphics_pipeline();
+            if let Some(particles) = self.particles.take() {
+                particles.destroy(self.device.as_ref().unwrap());
+            }
+            self.multisampling = previous_multisampling;
+            self.frame_graph = previous_frame_graph;
+            self.render_pass = previous_render_pass;
+            self.particles = previous_particles;
+            self.graphics_pipeline = previous_pipeline;
+            self.pipeline_layout = previous_pipeline_layout;
+            self.descriptor_set_layouts = previous_descriptor_set_layouts;
+            self.descriptor_pool = previous_descriptor_pool;
+            self.descriptor_sets = previous_descriptor_sets;
+            return Ok(());
+        }
+
+        let device = self.device.as_ref().unwrap();
+        if let Some(mut frame_graph) = previous_frame_graph {
+            frame_graph.graph.destroy(device);
+        }
+        if let Some(particles) = previous_particles {
+            particles.destroy(device);
+        }
+        unsafe {
+            device.destroy_pipeline(previous_pipeline, None);
+            device.destroy_pipeline_layout(previous_pipeline_layout, None);
+            for descriptor_set_layout in previous_descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(descriptor_set_layout, None);
+            }
+            device.destroy_descriptor_pool(previous_descriptor_pool, None);
+        }
+        self.bind_post_process_inputs()
+    }
+
+    /// Rebuilds the frame graph and the pipelines for the effects in the config.
+    pub fn set_post_process(&mut self) -> VulkanResult<()> {
+        trace!("set_post_process");
+        info!("Post-processing: {:?}", self.config.post_process);
+        unsafe { self.device.as_ref().unwrap().device_wait_idle() }?;
+        self.destroy_frame_graph();
+        self.destroy_post_process();
+        self.create_frame_graph()?;
+        self.create_post_process()
+    }
+
+    /// Destroys the frame graph with its render passes, framebuffers and transient images.
+    fn destroy_frame_graph(&mut self) {
+        trace!("destroy_frame_graph");
+        if let Some(mut frame_graph) = self.frame_graph.take() {
+            frame_graph.graph.destroy(self.device.as_ref().unwrap());
+        }
+        self.render_pass = vk::RenderPass::null();
+    }
+
+    fn destroy_swapchain(&mut self) {
+        trace!("destroy_swapchain");
+        self.destroy_frame_graph();
+        unsafe {
+            let device = self.device.as_ref().unwrap();
+            for image_view in self.swapchain_image_views.drain(..) {
+                device.destroy_image_view(image_view, None);
+            }
+            self.swapchain_ext.as_ref().unwrap().destroy_swapchain(self.swapchain, None);
+        }
+        self.swapchain = vk::SwapchainKHR::null();
+    }
+
+    /// Replaces the swapchain and everything sized after it when the window changed. The
+    /// pipelines stay, as viewport and scissor are dynamic state. A minimized window has
+    /// no valid extent, so recreation is put off until it is restored.
+    pub fn recreate_swapchain(&mut self, window: &winit::window::Window) -> VulkanResult<()> {
+        trace!("recreate_swapchain");
+        let size = window.inner_size();
+        if size.width < 1.0 || size.height < 1.0 {
+            self.swapchain_outdated = true;
+            return Ok(());
+        }
+        unsafe { self.device.as_ref().unwrap().device_wait_idle() }?;
+        self.physical_device.swap_chain_support_details = SwapChainSupportDetails::query(self.physical_device.device, &self.surface_ext, self.surface)?;
+        self.destroy_swapchain();
+        self.create_swapchain(window)?;
+        self.create_image_views()?;
+        self.create_frame_graph()?;
+        self.bind_post_process_inputs()?;
+        self.swapchain_outdated = false;
+        debug!("Swapchain recreated with extent {}x{}", self.swapchain_extent.width, self.swapchain_extent.height);
+        Ok(())
+    }
+
+    /// Records and submits a frame. `build` fills in what to draw, it's called once the frame
+    /// is sure to be drawn. Waits only for the frame that used the same resources
+    /// `MAX_FRAMES_IN_FLIGHT` frames ago.
+    pub fn draw_frame<F: FnOnce(&mut FrameContext)>(&mut self, build: F) -> VulkanResult<()> {
         trace!("draw_frame");
-        let (image_index, _) = unsafe { self.swapchain_ext.as_ref().unwrap().acquire_next_image(self.swapchain, std::u64::MAX, self.image_available_semaphore, vk::Fence::null()) }?;
+        if self.swapchain_outdated {
+            return Ok(());
+        }
+        let frame = self.frames[self.current_frame];
+        let device = self.device.as_ref().unwrap();
+        frame.wait(device)?;
+        let image_index = match unsafe { self.swapchain_ext.as_ref().unwrap().acquire_next_image(self.swapchain, u64::MAX, frame.image_available, vk::Fence::null()) } {
+            Ok((image_index, _)) => image_index,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.swapchain_outdated = true;
+                return Ok(());
+            }
+            Err(err) => return Err(Box::new(err)),
+        };
+        // with more swapchain images than frames in flight, the image can still be in use by another frame
+        let image_in_flight = self.images_in_flight[image_index as usize];
+        if image_in_flight != vk::Fence::null() && image_in_flight != frame.in_flight {
+            unsafe { device.wait_for_fences(&[image_in_flight], true, u64::MAX) }?;
+        }
+        self.images_in_flight[image_index as usize] = frame.in_flight;
+
+        let now = Instant::now();
+        let delta_time = self.last_frame.map_or(0.0, |last_frame| now.duration_since(last_frame).as_secs_f32()).min(MAX_TIME_STEP);
+        self.last_frame = Some(now);
+        let command_buffer = frame.begin(device)?;
+        self.recorder.as_ref().unwrap().reset(device, self.current_frame)?;
+        self.profiler.as_mut().unwrap().begin_frame(device, command_buffer, self.current_frame);
+        self.occlusion_queries.as_mut().unwrap().begin_frame(device, command_buffer, self.current_frame);
+        let present_modes = &self.physical_device.swap_chain_support_details.present_modes;
+        let sample_counts = msaa_sample_counts(self.supported_sample_counts);
+        let tweakables = &mut self.tweakables;
+        self.debug_ui.as_mut().unwrap().run(delta_time, self.swapchain_extent, |context| tweakables_window(context, tweakables, present_modes, &sample_counts));
+        let [red, green, blue] = self.tweakables.clear_color;
+        let mut context = FrameContext::new(self.swapchain_extent, [red, green, blue, 1.0], &mut self.draw_list, self.occlusion_queries.as_ref().unwrap(), self.text.as_mut().unwrap(), self.sprites.as_mut().unwrap());
+        build(&mut context);
+        let clear_color = context.clear_color;
+        self.record_frame(command_buffer, image_index as usize, delta_time, clear_color)?;
+
         let image_indices = [
             image_index,
         ];
 
         let wait_semaphores = [
-            self.image_available_semaphore,
+            frame.image_available,
         ];
         let wait_stages = [
             vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
         ];
         let signal_semaphores = [
-            self.render_finished_semaphore,
+            frame.render_finished,
+        ];
+        let command_buffers = [
+            command_buffer,
         ];
         let submit_info = [vk::SubmitInfo::builder()
             .wait_semaphores(&wait_semaphores)
             .wait_dst_stage_mask(&wait_stages)
-            .command_buffers(&self.command_buffers[image_index as usize..])
+            .command_buffers(&command_buffers)
             .signal_semaphores(&signal_semaphores).build()
         ];
         
-        unsafe { self.device.as_ref().unwrap().queue_submit(self.graphics_queue, &submit_info, vk::Fence::null()) }?;
+        unsafe {
+            let device = self.device.as_ref().unwrap();
+            device.reset_fences(&[frame.in_flight])?;
+            device.queue_submit(self.graphics_queue, &submit_info, frame.in_flight)?;
+        }
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
 
         let swapchains = [
             self.swapchain,
@@ -605,35 +1553,86 @@ impl VulkanExperiment {
             .swapchains(&swapchains)
             .image_indices(&image_indices);
 
-        if let Err(err) = unsafe { self.swapchain_ext.as_ref().unwrap().queue_present(self.present_queue, &present_info) } {
-            error!("vkQueuePresentKHR: {}", err);
+        match unsafe { self.swapchain_ext.as_ref().unwrap().queue_present(self.present_queue, &present_info) } {
+            Ok(false) => {}
+            Ok(true) | Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.swapchain_outdated = true,
+            Err(err) => error!("vkQueuePresentKHR: {}", err),
         }
 
-        unsafe { self.device.as_ref().unwrap().device_wait_idle().unwrap() };
+        self.apply_tweakables()
+    }
+
+    /// Applies what was changed in the debug UI. A new present mode takes a new swapchain,
+    /// which is left to the caller of `draw_frame` by marking it outdated.
+    fn apply_tweakables(&mut self) -> VulkanResult<()> {
+        if let Some(present_mode) = self.tweakables.present_mode.filter(|present_mode| *present_mode != self.present_mode) {
+            info!("Present mode: {:?}", present_mode);
+            self.swapchain_outdated = true;
+        }
+        if self.tweakables.msaa_samples != self.config.msaa_samples {
+            self.config.msaa_samples = self.tweakables.msaa_samples;
+            let multisampling = Multisampling::from_config(&self.config, self.supported_sample_counts, self.sample_rate_shading);
+            if multisampling != self.multisampling {
+                self.set_multisampling(multisampling)?;
+            }
+        }
         Ok(())
     }
 }
 
 impl Drop for VulkanExperiment {
     fn drop(&mut self) {
+        unsafe { self.device.as_ref().unwrap().device_wait_idle().unwrap() };
+        self.destroy_swapchain();
+        self.destroy_post_process();
         unsafe {
             let device = self.device.as_ref().unwrap();
-            device.device_wait_idle().unwrap();
-
-            device.destroy_semaphore(self.render_finished_semaphore, None);
-            device.destroy_semaphore(self.image_available_semaphore, None);
+            for frame in &self.frames {
+                frame.destroy(device);
+            }
+            if let Some(recorder) = &mut self.recorder {
+                recorder.destroy(device);
+            }
+            if let Some(profiler) = &self.profiler {
+                profiler.destroy(device);
+            }
+            if let Some(occlusion_queries) = &self.occlusion_queries {
+                occlusion_queries.destroy(device);
+            }
+            if let Some(sprites) = &self.sprites {
+                sprites.destroy(device);
+            }
+            if let Some(text) = &self.text {
+                text.destroy(device);
+            }
+            if let Some(overlay) = &self.overlay {
+                overlay.destroy(device);
+            }
+            if let Some(debug_ui) = &self.debug_ui {
+                debug_ui.destroy(device);
+            }
 
             device.destroy_command_pool(self.command_pool, None);
-            for framebuffer in &self.swapchain_framebuffers {
-                device.destroy_framebuffer(*framebuffer, None);
+            if let Some(particles) = &self.particles {
+                particles.destroy(device);
             }
             device.destroy_pipeline(self.graphics_pipeline, None);
             device.destroy_pipeline_layout(self.pipeline_layout, None);
-            device.destroy_render_pass(self.render_pass, None);
-            for image_view in &self.swapchain_image_views {
-                device.destroy_image_view(*image_view, None);
+            for descriptor_set_layout in &self.descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(*descriptor_set_layout, None);
             }
-            self.swapchain_ext.as_ref().unwrap().destroy_swapchain(self.swapchain, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.sampler_cache.destroy(device);
+            if let Some(texture) = &self.texture {
+                texture.destroy(device);
+            }
+            if let Some(lut) = &self.color_grading_lut {
+                lut.destroy(device);
+            }
+            if let Err(err) = save_pipeline_cache(device, self.pipeline_cache, PIPELINE_CACHE_FILE) {
+                warn!("Failed to save pipeline cache: {}", err);
+            }
+            device.destroy_pipeline_cache(self.pipeline_cache, None);
             self.surface_ext.destroy_surface(self.surface, None);
             device.destroy_device(None);
             self.debug_utils_ext.destroy_debug_utils_messenger(self.debug_utils_messenger, None);
@@ -646,9 +1645,47 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     log4rs::init_file("log.yaml", Default::default())?;
     info!("Startup");
 
+    // `--compile-shaders <dir>` writes the SPIR-V for builds without the shaderc feature and exits,
+    // by default to where those builds load it from.
+    // With `--json`, the compiler diagnostics are printed to stdout as JSON for editors and tools.
+    #[cfg(feature = "shaderc")]
+    {
+        let args: Vec<String> = std::env::args().collect();
+        if let Some(idx) = args.iter().position(|arg| arg == "--compile-shaders") {
+            let out_dir = match args.get(idx + 1).filter(|arg| !arg.starts_with("--")) {
+                Some(out_dir) => PathBuf::from(out_dir),
+                None => default_spirv_dir()?,
+            };
+            let json = args.iter().any(|arg| arg == "--json");
+            let mut compiler = ShaderCompiler::for_build().unwrap();
+            match compiler.compile_all(&out_dir) {
+                Ok(count) => {
+                    info!("Compiled {} shaders to {}", count, out_dir.display());
+                    if json {
+                        println!("[{}]", compiler.take_warnings().iter().map(|warning| warning.to_json()).collect::<Vec<_>>().join(","));
+                    }
+                    return Ok(());
+                }
+                Err(err) => {
+                    if let (true, Some(compile_error)) = (json, err.downcast_ref::<ShaderCompileError>()) {
+                        println!("{}", compile_error.to_json());
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    // `--compute-self-test` runs a kernel without opening a window and checks the result,
+    // to see whether compute works with the installed driver.
+    if std::env::args().any(|arg| arg == "--compute-self-test") {
+        return compute_self_test();
+    }
+
     let entry = Entry::new()?;
     let mut app = VulkanExperiment::new(&entry)?;
     app.setup_early_debug_logging()?;
+    app.load_config();
 
     // *** WINDOW CREATION ***
     let event_loop = EventLoop::new();
@@ -662,21 +1699,66 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     app.create_swapchain(&window)?;
     app.create_image_views()?;
     app.create_queues()?;
-    app.create_render_pass()?;
-    app.create_graphics_pipeline()?;
-    app.create_framebuffers()?;
+    app.create_frame_graph()?;
     app.create_command_pool()?;
-    app.create_command_buffers()?;
-    app.create_semaphores()?;
+    app.create_texture()?;
+    app.create_graphics_pipeline()?;
+    app.create_particle_system()?;
+    app.create_post_process()?;
+    app.create_sprites()?;
+    app.create_text()?;
+    app.create_overlay()?;
+    app.create_debug_ui(window.hidpi_factor())?;
+    app.create_frames()?;
+
+    // `--dump-frame-graph <file>` writes the frame graph for Graphviz, e.g. `dot -Tsvg <file>`.
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(path) = args.iter().position(|arg| arg == "--dump-frame-graph").and_then(|idx| args.get(idx + 1)) {
+        app.dump_frame_graph(path)?;
+    }
+
+    // `--profile-trace <file>` writes the profiled scopes of the last frames when closing, in
+    // Chrome's trace format.
+    let profile_trace = args.iter().position(|arg| arg == "--profile-trace").and_then(|idx| args.get(idx + 1)).map(PathBuf::from);
+
+    #[cfg(feature = "shaderc")]
+    {
+        if app.shader_compiler.loader.is_watching() {
+            info!("Shader hot reload enabled");
+        }
+    }
 
     let mut app = Some(app);
+    let start = Instant::now();
 
     // *** MAIN LOOP ***
     event_loop.run(move |event, _, control_flow| {
         match event {
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::F2), .. }, .. },
+                window_id,
+            } if window_id == window.id() => {
+                if let Some(debug_ui) = app.as_mut().and_then(|inner_app| inner_app.debug_ui.as_mut()) {
+                    debug_ui.visible = !debug_ui.visible;
+                }
+            }
+            // input the debug UI used doesn't reach the other handlers
+            Event::WindowEvent {
+                ref event,
+                window_id,
+            } if window_id == window.id() && app.as_mut().and_then(|inner_app| inner_app.debug_ui.as_mut()).map(|debug_ui| debug_ui.handle_event(event)) == Some(true) => {}
             Event::EventsCleared => {
                 trace!("Events cleared");
                 // update state here
+                if let Some(inner_app) = app.as_mut() {
+                    inner_app.poll_config().expect("Config reload error");
+                }
+                #[cfg(feature = "shaderc")]
+                {
+                    if let Some(inner_app) = app.as_mut() {
+                        inner_app.reload_shaders().expect("Shader reload error");
+                    }
+                }
                 window.request_redraw();
             }
             Event::WindowEvent {
@@ -685,22 +1767,65 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             } if window_id == window.id() => {
                 trace!("redraw");
                 if let Some(mut inner_app) = app.take() {
-                    inner_app.draw_frame().expect("Draw error");
+                    let triangle = inner_app.triangle().occlusion_query(TRIANGLE_OCCLUSION);
+                    let texture_sprite = inner_app.texture_sprite.unwrap();
+                    inner_app.draw_frame(|frame| {
+                        if frame.is_visible(TRIANGLE_OCCLUSION) {
+                            frame.draw(triangle);
+                        }
+                        let bottom = frame.extent.height as f32 - HELP_TEXT_SIZE * 2.0;
+                        frame.draw_text([HELP_TEXT_SIZE, bottom], HELP_TEXT_SIZE, HELP_TEXT_COLOR, HELP_TEXT);
+                        let extent = frame.extent;
+                        draw_sprite_panel(frame.sprites(), extent, texture_sprite, start.elapsed().as_secs_f32());
+                    }).expect("Draw error");
+                    if inner_app.swapchain_outdated {
+                        inner_app.recreate_swapchain(&window).expect("Swapchain recreation error");
+                    }
                     app.replace(inner_app);
                 }
             }
             Event::WindowEvent {
-                event: WindowEvent::HiDpiFactorChanged(_dpi),
+                event: WindowEvent::HiDpiFactorChanged(hidpi_factor),
                 window_id,
             } if window_id == window.id() => {
-                // TODO
+                // the UI keeps its size in points, so it's drawn with more pixels from the next frame on
+                if let Some(debug_ui) = app.as_mut().and_then(|inner_app| inner_app.debug_ui.as_mut()) {
+                    debug_ui.pixels_per_point = hidpi_factor as f32;
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::Resized(LogicalSize { width: _width, height: _height }),
                 window_id,
             } if window_id == window.id() => {
                 debug!("Window resized");
-                // TODO
+                if let Some(inner_app) = app.as_mut() {
+                    inner_app.recreate_swapchain(&window).expect("Swapchain recreation error");
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::V), .. }, .. },
+                window_id,
+            } if window_id == window.id() => {
+                if let Some(inner_app) = app.as_mut() {
+                    let viewport_layout = inner_app.viewport_layout.next();
+                    inner_app.set_viewport_layout(viewport_layout);
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::F1), .. }, .. },
+                window_id,
+            } if window_id == window.id() => {
+                if let Some(overlay) = app.as_mut().and_then(|inner_app| inner_app.overlay.as_mut()) {
+                    overlay.visible = !overlay.visible;
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::P), .. }, .. },
+                window_id,
+            } if window_id == window.id() => {
+                if let Some(profiler) = app.as_ref().and_then(|inner_app| inner_app.profiler.as_ref()) {
+                    profiler.log_timings();
+                }
             }
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
@@ -708,9 +1833,40 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             } if window_id == window.id() => {
                 info!("Close requested");
                 *control_flow = ControlFlow::Exit;
+                if let (Some(path), Some(profiler)) = (&profile_trace, app.as_ref().and_then(|inner_app| inner_app.profiler.as_ref())) {
+                    match profiler.write_chrome_trace(path) {
+                        Ok(()) => info!("Wrote the profile to {}", path.display()),
+                        Err(err) => error!("Writing the profile to {} failed: {}", path.display(), err),
+                    }
+                }
                 app.take();
             }
             _ => *control_flow = ControlFlow::Poll,
         }
     });
 }
+
+/// Shows the sprite batch in a panel in the top right corner: the triangle's texture spinning
+/// over a corner of it, circled by a ring with a dot going around.
+fn draw_sprite_panel(sprites: &mut SpriteBatch, extent: vk::Extent2D, texture: SpriteTexture, time: f32) {
+    let size = SPRITE_PANEL_SIZE;
+    let (left, top) = (extent.width as f32 - size - HELP_TEXT_SIZE, HELP_TEXT_SIZE);
+    let center = [left + size / 2.0, top + size / 2.0];
+    let radius = size * 0.45;
+    let (sin, cos) = (-2.0 * time).sin_cos();
+    let dot = [center[0] + radius * cos, center[1] + radius * sin];
+    sprites.fill_rect([left, top, size, size], [0.0, 0.0, 0.0, 0.6], 0);
+    sprites.stroke_rect([left, top, size, size], 2.0, [1.0, 1.0, 1.0, 0.8], 0);
+    sprites.draw_sprite(&Sprite::new(texture, [left, top], [size / 2.0, size / 2.0]).uv_rect([0.0, 0.0, 0.5, 0.5]).tint([1.0, 1.0, 1.0, 0.5]).layer(1));
+    sprites.draw_sprite(&Sprite::new(texture, center, [size / 2.0, size / 2.0]).origin([0.5, 0.5]).rotation(time).layer(2));
+    sprites.stroke_circle(center, radius, 3.0, [0.9, 0.5, 0.2, 1.0], 3);
+    sprites.draw_line(center, dot, 2.0, [0.3, 0.8, 0.3, 1.0], 3);
+    sprites.fill_circle(dot, 8.0, [0.3, 0.8, 0.3, 1.0], 3);
+}
+
+fn compute_self_test() -> Result<(), Box<dyn std::error::Error>> {
+    let compute = HeadlessCompute::new()?;
+    saxpy(&compute)?;
+    info!("Compute self test on {} passed", compute.device_name);
+    Ok(())
+}
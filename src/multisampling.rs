@@ -0,0 +1,68 @@
+use ash::vk;
+use log::warn;
+
+use crate::config::Config;
+
+const SAMPLE_COUNTS: [(u32, vk::SampleCountFlags); 7] = [
+    (64, vk::SampleCountFlags::TYPE_64),
+    (32, vk::SampleCountFlags::TYPE_32),
+    (16, vk::SampleCountFlags::TYPE_16),
+    (8, vk::SampleCountFlags::TYPE_8),
+    (4, vk::SampleCountFlags::TYPE_4),
+    (2, vk::SampleCountFlags::TYPE_2),
+    (1, vk::SampleCountFlags::TYPE_1),
+];
+
+/// Sample counts usable for framebuffers with both a color and a depth attachment.
+pub fn supported_sample_counts(limits: &vk::PhysicalDeviceLimits) -> vk::SampleCountFlags {
+    limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+}
+
+/// The values of `Config::msaa_samples` that are in `supported`, from low to high.
+pub fn msaa_sample_counts(supported: vk::SampleCountFlags) -> Vec<u32> {
+    // the config accepts up to 8 samples
+    SAMPLE_COUNTS.iter().rev()
+        .filter(|(count, samples)| *count <= 8 && supported.contains(*samples))
+        .map(|(count, _)| *count)
+        .collect()
+}
+
+/// How the color and depth attachments are sampled.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Multisampling {
+    pub samples: vk::SampleCountFlags,
+    pub min_sample_shading: Option<f32>,
+}
+
+impl Default for Multisampling {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+        }
+    }
+}
+
+impl Multisampling {
+    /// The highest supported sample count not above the requested one. Sample shading is
+    /// dropped without the `sampleRateShading` feature or when there's only one sample.
+    pub fn from_config(config: &Config, supported: vk::SampleCountFlags, sample_rate_shading: bool) -> Self {
+        let (count, samples) = SAMPLE_COUNTS.iter().cloned()
+            .find(|(count, samples)| *count <= config.msaa_samples && supported.contains(*samples))
+            .unwrap_or((1, vk::SampleCountFlags::TYPE_1));
+        if count < config.msaa_samples {
+            warn!("{}x MSAA is not supported, using {}x", config.msaa_samples, count);
+        }
+        if config.sample_shading.is_some() && !sample_rate_shading {
+            warn!("Sample shading is not supported");
+        }
+        Self {
+            samples,
+            min_sample_shading: config.sample_shading.filter(|_| sample_rate_shading && count > 1),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+}
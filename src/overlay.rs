@@ -0,0 +1,369 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+
+use crate::{
+    buffer::Buffer,
+    frame::MAX_FRAMES_IN_FLIGHT,
+    pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines},
+    profiler::{AVERAGE_SAMPLES, FRAME_SCOPE, Profiler, RollingAverage},
+};
+
+pub const OVERLAY_SHADERS: [&str; 2] = ["overlay.vs", "overlay.fs"];
+
+/// Rectangles per frame, the ones after that are dropped.
+const MAX_RECTS: usize = 8192;
+/// Font pixels are drawn as squares of this many window pixels.
+const FONT_SCALE: f32 = 2.0;
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+const LINE_HEIGHT: f32 = (GLYPH_HEIGHT as f32 + 2.0) * FONT_SCALE;
+const MARGIN: f32 = 8.0;
+const PADDING: f32 = 6.0;
+const GRAPH_HEIGHT: f32 = 48.0;
+const BAR_WIDTH: f32 = 2.0;
+/// Milliseconds the graphs show at least, two frames at 60 Hz.
+const GRAPH_MIN_RANGE: f32 = 33.3;
+/// Milliseconds of a frame at 60 Hz, marked in the graphs.
+const TARGET_FRAME_TIME: f32 = 1000.0 / 60.0;
+
+const PANEL_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const GRAPH_COLOR: [f32; 4] = [0.1, 0.1, 0.1, 0.8];
+const CPU_COLOR: [f32; 4] = [0.3, 0.8, 0.3, 1.0];
+const GPU_COLOR: [f32; 4] = [0.9, 0.5, 0.2, 1.0];
+const TARGET_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.4];
+
+/// Matches the instance inputs of overlay.vs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    /// Top left corner and size in normalized device coordinates.
+    rect: [f32; 4],
+    color: [f32; 4],
+}
+
+/// What the overlay shows besides the frame times.
+pub struct OverlayInfo<'a> {
+    pub device_name: &'a str,
+    pub present_mode: vk::PresentModeKHR,
+    pub extent: vk::Extent2D,
+    pub profiler: &'a Profiler,
+}
+
+/// Frame statistics drawn over the final image: FPS, graphs of the CPU and GPU time of the
+/// frames, the device and the swapchain. Text uses a built-in 5x7 bitmap font, everything is
+/// drawn as colored rectangles.
+pub struct Overlay {
+    pub visible: bool,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    /// Instance buffer of each frame in flight.
+    buffers: Vec<Buffer>,
+    delta_times: RollingAverage,
+    rects: Vec<Rect>,
+}
+
+impl Overlay {
+    /// The shader modules can be destroyed once this returns.
+    pub fn new(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut result = Self {
+            visible: false,
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            buffers: Vec::new(),
+            delta_times: RollingAverage::default(),
+            rects: Vec::new(),
+        };
+        if let Err(err) = result.create_resources(device, memory_properties, pipeline_cache, render_pass, vertex_shader, fragment_shader) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create_resources(&mut self, device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule) -> Result<(), Box<dyn std::error::Error>> {
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let size = (MAX_RECTS * std::mem::size_of::<Rect>()) as vk::DeviceSize;
+            self.buffers.push(Buffer::host_visible(device, memory_properties, size, vk::BufferUsageFlags::VERTEX_BUFFER)?);
+        }
+        self.pipeline_layout = unsafe { device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo::builder(), None) }?;
+        let bindings = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Rect>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()];
+        let attributes = [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(16)
+                .build(),
+        ];
+        let builder = GraphicsPipelineBuilder::new(self.pipeline_layout, render_pass)
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader)
+            .vertex_input(&bindings, &attributes)
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+            .color_attachments(&[GraphicsPipelineBuilder::alpha_blend_attachment()]);
+        self.pipeline = create_graphics_pipelines(device, pipeline_cache, &[builder])?[0];
+        Ok(())
+    }
+
+    /// Adds the frame time to the FPS, also while the overlay is hidden.
+    pub fn update(&mut self, delta_time: f32) {
+        if delta_time > 0.0 {
+            self.delta_times.push(delta_time);
+        }
+    }
+
+    /// Lays the overlay out over `info.extent` if it's visible and writes it to the instance
+    /// buffer of `frame`, which the GPU has to be done with.
+    pub fn prepare(&mut self, device: &ash::Device, frame: usize, info: &OverlayInfo) -> Result<(), vk::Result> {
+        self.rects.clear();
+        if !self.visible {
+            return Ok(());
+        }
+        self.layout(info);
+        self.rects.truncate(MAX_RECTS);
+        self.buffers[frame].write(device, &self.rects)
+    }
+
+    /// Draws what `prepare` laid out for `frame`. Has to be recorded inside the overlay's render pass.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, extent: vk::Extent2D) {
+        if self.rects.is_empty() {
+            return;
+        }
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D { offset: vk::Offset2D::default(), extent }];
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.buffers[frame].buffer], &[0]);
+            device.cmd_draw(command_buffer, 6, self.rects.len() as u32, 0, 0);
+        }
+    }
+
+    fn layout(&mut self, info: &OverlayInfo) {
+        let frame_timings = info.profiler.scope_timings(FRAME_SCOPE);
+        let cpu_samples: Vec<f32> = frame_timings.map_or_else(Vec::new, |timings| timings.cpu.samples().collect());
+        let gpu_samples: Vec<f32> = frame_timings.map_or_else(Vec::new, |timings| timings.gpu.samples().collect());
+        let format_time = |samples: &[f32]| if samples.is_empty() {
+            "-".to_owned()
+        } else {
+            format!("{:.2} ms", samples.iter().sum::<f32>() / samples.len() as f32)
+        };
+        let fps = self.delta_times.average().map_or_else(|| "-".to_owned(), |delta_time| format!("{:.1} ({:.2} ms)", 1.0 / delta_time, delta_time * 1e3));
+        let mut lines = vec![
+            format!("FPS {}", fps),
+            info.device_name.to_owned(),
+            format!("Present mode {:?}", info.present_mode),
+            format!("Extent {}x{}", info.extent.width, info.extent.height),
+        ];
+        if let Some(statistics) = info.profiler.pipeline_statistics() {
+            lines.push(format!("Vertices {}  Fragments {}", format_count(statistics.vertex_invocations), format_count(statistics.fragment_invocations)));
+            lines.push(format!("Primitives {} clipped to {}", format_count(statistics.clipping_invocations), format_count(statistics.clipping_primitives)));
+            lines.push(format!("Compute {}", format_count(statistics.compute_invocations)));
+        }
+        let graph_width = AVERAGE_SAMPLES as f32 * BAR_WIDTH;
+        let text_width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) as f32 * (GLYPH_WIDTH as f32 + 1.0) * FONT_SCALE;
+        let width = text_width.max(graph_width) + 2.0 * PADDING;
+        let height = (lines.len() + 2) as f32 * LINE_HEIGHT + 2.0 * GRAPH_HEIGHT + 2.0 * PADDING;
+        self.rect(info.extent, MARGIN, MARGIN, width, height, PANEL_COLOR);
+
+        let x = MARGIN + PADDING;
+        let mut y = MARGIN + PADDING;
+        for line in &lines {
+            self.text(info.extent, x, y, line, TEXT_COLOR);
+            y += LINE_HEIGHT;
+        }
+        for (label, samples, color) in &[("CPU", cpu_samples, CPU_COLOR), ("GPU", gpu_samples, GPU_COLOR)] {
+            self.text(info.extent, x, y, &format!("{} {}", label, format_time(samples)), *color);
+            y += LINE_HEIGHT;
+            self.graph(info.extent, x, y, graph_width, samples, *color);
+            y += GRAPH_HEIGHT;
+        }
+    }
+
+    /// Bars of the `samples` in milliseconds, the newest on the right.
+    fn graph(&mut self, extent: vk::Extent2D, x: f32, y: f32, width: f32, samples: &[f32], color: [f32; 4]) {
+        self.rect(extent, x, y, width, GRAPH_HEIGHT, GRAPH_COLOR);
+        let range = samples.iter().cloned().fold(GRAPH_MIN_RANGE, f32::max);
+        let bottom = y + GRAPH_HEIGHT;
+        let left = x + width - samples.len() as f32 * BAR_WIDTH;
+        for (idx, sample) in samples.iter().enumerate() {
+            let height = sample / range * GRAPH_HEIGHT;
+            self.rect(extent, left + idx as f32 * BAR_WIDTH, bottom - height, BAR_WIDTH, height, color);
+        }
+        self.rect(extent, x, bottom - TARGET_FRAME_TIME / range * GRAPH_HEIGHT, width, 1.0, TARGET_COLOR);
+    }
+
+    /// Draws `text` with its top left corner at `x`, `y`, every run of set pixels in a glyph
+    /// row as one rectangle.
+    fn text(&mut self, extent: vk::Extent2D, x: f32, y: f32, text: &str, color: [f32; 4]) {
+        for (idx, c) in text.chars().enumerate() {
+            let left = x + idx as f32 * (GLYPH_WIDTH + 1) as f32 * FONT_SCALE;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                let top = y + row as f32 * FONT_SCALE;
+                let mut column = 0;
+                while column < GLYPH_WIDTH {
+                    let set = |column: usize| bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0;
+                    if !set(column) {
+                        column += 1;
+                        continue;
+                    }
+                    let start = column;
+                    while column < GLYPH_WIDTH && set(column) {
+                        column += 1;
+                    }
+                    self.rect(extent, left + start as f32 * FONT_SCALE, top, (column - start) as f32 * FONT_SCALE, FONT_SCALE, color);
+                }
+            }
+        }
+    }
+
+    /// Adds a rectangle given in window pixels.
+    fn rect(&mut self, extent: vk::Extent2D, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+        let (scale_x, scale_y) = (2.0 / extent.width as f32, 2.0 / extent.height as f32);
+        self.rects.push(Rect {
+            rect: [x * scale_x - 1.0, y * scale_y - 1.0, width * scale_x, height * scale_y],
+            color,
+        });
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        for buffer in &self.buffers {
+            buffer.destroy(device);
+        }
+    }
+}
+
+/// `count` with a K, M or G suffix and three significant digits, so the panel doesn't resize
+/// every frame.
+fn format_count(count: u64) -> String {
+    let suffixes = [(1e9, "G"), (1e6, "M"), (1e3, "K")];
+    match suffixes.iter().find(|(scale, _)| count as f64 >= *scale) {
+        Some((scale, suffix)) => {
+            let value = count as f64 / scale;
+            let decimals = if value >= 100.0 { 0 } else if value >= 10.0 { 1 } else { 2 };
+            format!("{:.*}{}", decimals, value, suffix)
+        }
+        None => count.to_string(),
+    }
+}
+
+/// Rows of the 5x7 glyph of `c` from the top, the most significant of the 5 bits on the left.
+/// Lowercase letters other than `x` use the uppercase glyphs, unknown characters a `?`.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        _ if c == 'x' => [0b00000, 0b00000, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001],
+        ' ' => [0; GLYPH_HEIGHT],
+        '0' => [0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110],
+        '1' => [0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        '2' => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111],
+        '3' => [0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110],
+        '4' => [0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010],
+        '5' => [0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110],
+        '6' => [0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110],
+        '7' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000],
+        '8' => [0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110],
+        '9' => [0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100],
+        'A' => [0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'B' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110],
+        'C' => [0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110],
+        'D' => [0b11100, 0b10010, 0b10001, 0b10001, 0b10001, 0b10010, 0b11100],
+        'E' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111],
+        'F' => [0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000],
+        'G' => [0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111],
+        'H' => [0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001],
+        'I' => [0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110],
+        'J' => [0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100],
+        'K' => [0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001],
+        'L' => [0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111],
+        'M' => [0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001],
+        'N' => [0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001],
+        'O' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'P' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000],
+        'Q' => [0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101],
+        'R' => [0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001],
+        'S' => [0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110],
+        'T' => [0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100],
+        'U' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110],
+        'V' => [0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100],
+        'W' => [0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010],
+        'X' => [0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001],
+        'Y' => [0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100],
+        'Z' => [0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111],
+        '.' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b01100],
+        ',' => [0b00000, 0b00000, 0b00000, 0b00000, 0b01100, 0b00100, 0b01000],
+        ':' => [0b00000, 0b01100, 0b01100, 0b00000, 0b01100, 0b01100, 0b00000],
+        '-' => [0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000],
+        '+' => [0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000],
+        '=' => [0b00000, 0b00000, 0b11111, 0b00000, 0b11111, 0b00000, 0b00000],
+        '*' => [0b00000, 0b00100, 0b10101, 0b01110, 0b10101, 0b00100, 0b00000],
+        '/' => [0b00000, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b00000],
+        '(' => [0b00010, 0b00100, 0b01000, 0b01000, 0b01000, 0b00100, 0b00010],
+        ')' => [0b01000, 0b00100, 0b00010, 0b00010, 0b00010, 0b00100, 0b01000],
+        '[' => [0b01110, 0b01000, 0b01000, 0b01000, 0b01000, 0b01000, 0b01110],
+        ']' => [0b01110, 0b00010, 0b00010, 0b00010, 0b00010, 0b00010, 0b01110],
+        '<' => [0b00010, 0b00100, 0b01000, 0b10000, 0b01000, 0b00100, 0b00010],
+        '>' => [0b01000, 0b00100, 0b00010, 0b00001, 0b00010, 0b00100, 0b01000],
+        '%' => [0b11000, 0b11001, 0b00010, 0b00100, 0b01000, 0b10011, 0b00011],
+        '#' => [0b01010, 0b01010, 0b11111, 0b01010, 0b11111, 0b01010, 0b01010],
+        '_' => [0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b00000, 0b11111],
+        '!' => [0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00000, 0b00100],
+        '\'' => [0b01100, 0b00100, 0b01000, 0b00000, 0b00000, 0b00000, 0b00000],
+        '"' => [0b01010, 0b01010, 0b01010, 0b00000, 0b00000, 0b00000, 0b00000],
+        _ => [0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b00000, 0b00100],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_keep_three_significant_digits() {
+        let cases = [
+            (0, "0"),
+            (999, "999"),
+            (1000, "1.00K"),
+            (12_345, "12.3K"),
+            (999_499, "999K"),
+            (2_073_600, "2.07M"),
+            (123_456_789, "123M"),
+            (4_000_000_000, "4.00G"),
+        ];
+        for &(count, expected) in &cases {
+            assert_eq!(format_count(count), expected);
+        }
+    }
+
+    #[test]
+    fn every_overlay_character_has_a_glyph() {
+        let unknown = glyph('\u{7f}');
+        for c in "FPS Present mode Extent Vertices Fragments Primitives clipped to Compute CPU GPU 0123456789.KMG()x-".chars() {
+            assert!(c == '?' || glyph(c) != unknown, "no glyph for {:?}", c);
+        }
+    }
+}
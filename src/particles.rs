@@ -0,0 +1,158 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+
+use crate::{
+    buffer::Buffer,
+    compute::ComputePipeline,
+    descriptors::{allocate_descriptor_sets, create_descriptor_pool, write_storage_buffer},
+    frame::DrawCommand,
+    multisampling::Multisampling,
+    pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines},
+    reflection::ShaderReflection,
+    specialization::SpecializationConstants,
+};
+
+pub const PARTICLE_SHADERS: [&str; 3] = ["particles.cs", "particles.vs", "particles.fs"];
+
+// specialization constants of the particle shaders
+const WORKGROUP_SIZE: u32 = 128;
+/// Half the edge length of a particle quad in normalized device coordinates.
+const PARTICLE_SIZE: f32 = 0.006;
+
+/// Matches `Particle` in particles.cs with std430 layout.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Particle {
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    pub color: [f32; 4],
+}
+
+/// Matches the push constant block in particles.cs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct Simulation {
+    delta_time: f32,
+    count: u32,
+}
+
+/// Particles fanning out from the center, spread with the golden angle so no random numbers are needed.
+pub fn fountain(count: u32) -> Vec<Particle> {
+    const GOLDEN_ANGLE: f32 = 2.399_963;
+    (0..count).map(|idx| {
+        let t = idx as f32 / count as f32;
+        let angle = idx as f32 * GOLDEN_ANGLE;
+        let speed = 0.3 + 0.7 * t;
+        Particle {
+            position: [0.0, 0.0],
+            velocity: [angle.cos() * speed, angle.sin() * speed - 0.5],
+            color: [0.5 + 0.5 * angle.cos(), 0.5 + 0.5 * (angle + 2.1).cos(), 0.5 + 0.5 * (angle + 4.2).cos(), 1.0],
+        }
+    }).collect()
+}
+
+/// Particles simulated by a compute shader, drawn as one instanced quad each straight
+/// from the storage buffer the simulation writes to.
+pub struct ParticleSystem {
+    count: u32,
+    buffer: Buffer,
+    simulation: ComputePipeline,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl ParticleSystem {
+    /// The shader modules can be destroyed once this returns.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, command_pool: vk::CommandPool, queue: vk::Queue, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, multisampling: &Multisampling, compute_shader: (vk::ShaderModule, &ShaderReflection), vertex_shader: (vk::ShaderModule, &ShaderReflection), fragment_shader: vk::ShaderModule, particles: &[Particle]) -> Result<Self, Box<dyn std::error::Error>> {
+        let buffer = Buffer::device_local_with_data(device, memory_properties, command_pool, queue, vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::VERTEX_BUFFER, particles)?;
+        let simulation = match ComputePipeline::new(device, pipeline_cache, compute_shader.0, compute_shader.1, &SpecializationConstants::new().set(0, WORKGROUP_SIZE)) {
+            Ok(simulation) => simulation,
+            Err(err) => {
+                buffer.destroy(device);
+                return Err(err);
+            }
+        };
+        let mut result = Self {
+            count: particles.len() as u32,
+            buffer,
+            simulation,
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        };
+        if let Err(err) = result.create_resources(device, pipeline_cache, render_pass, multisampling, vertex_shader, fragment_shader) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create_resources(&mut self, device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, multisampling: &Multisampling, vertex_shader: (vk::ShaderModule, &ShaderReflection), fragment_shader: vk::ShaderModule) -> Result<(), Box<dyn std::error::Error>> {
+        self.descriptor_pool = create_descriptor_pool(device, &self.simulation.reflection.descriptor_bindings, 1)?;
+        self.descriptor_set = allocate_descriptor_sets(device, self.descriptor_pool, &self.simulation.descriptor_set_layouts)?[0];
+        write_storage_buffer(device, self.descriptor_set, 0, self.buffer.buffer, self.buffer.size);
+
+        self.pipeline_layout = unsafe { device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo::builder(), None) }?;
+        let bindings = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<Particle>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()];
+        let attributes = [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(16)
+                .build(),
+        ];
+        let builder = GraphicsPipelineBuilder::new(self.pipeline_layout, render_pass)
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader.0)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader)
+            .specialize(vertex_shader.1, SpecializationConstants::new().set(0, PARTICLE_SIZE))?
+            .vertex_input(&bindings, &attributes)
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+            .multisampling(multisampling)
+            // everything is at depth 0, the particles go on top of what was drawn before
+            .depth_test(true, false, vk::CompareOp::LESS_OR_EQUAL);
+        self.pipeline = create_graphics_pipelines(device, pipeline_cache, &[builder])?[0];
+        Ok(())
+    }
+
+    /// Advances the simulation by `delta_time` seconds. Has to be recorded outside of a render pass,
+    /// ordered with the draws reading the buffer by the caller.
+    pub fn record_update(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, delta_time: f32) {
+        self.simulation.bind(device, command_buffer, &[self.descriptor_set]);
+        self.simulation.push_constants(device, command_buffer, &Simulation { delta_time, count: self.count });
+        self.simulation.dispatch_items(device, command_buffer, [self.count, 1, 1]);
+    }
+
+    /// Draws the particles, one quad per particle.
+    pub fn draw_command(&self) -> DrawCommand {
+        DrawCommand::new(self.pipeline, self.pipeline_layout, 6)
+            .vertex_buffers(&[self.buffer.buffer])
+            .instances(self.count)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+        self.simulation.destroy(device);
+        self.buffer.destroy(device);
+    }
+}
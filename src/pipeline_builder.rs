@@ -0,0 +1,647 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use std::ffi::CStr;
+
+use crate::{
+    multisampling::Multisampling,
+    reflection::{PipelineReflection, ShaderReflection},
+    specialization::{SpecializationConstants, SpecializationMismatch},
+};
+
+const ENTRY_POINT: &[u8] = b"main\0";
+
+/// Describes one graphics pipeline. Every state block starts out with the defaults
+/// below and can be changed with the chainable setters, then any number of builders
+/// are turned into pipelines with a single `create_graphics_pipelines` call.
+///
+/// Defaults: triangle list, one dynamic viewport and scissor, filled polygons, back
+/// face culling with clockwise front faces, no multisampling, no depth or stencil
+/// test and one color attachment without blending.
+#[derive(Clone)]
+pub struct GraphicsPipelineBuilder {
+    stages: Vec<(vk::ShaderStageFlags, vk::ShaderModule)>,
+    specializations: Vec<(vk::ShaderStageFlags, SpecializationConstants)>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    viewports: Vec<vk::Viewport>,
+    scissors: Vec<vk::Rect2D>,
+    /// Number of viewports set while recording instead of the static ones above, 0 if static.
+    dynamic_viewport_count: u32,
+    depth_clamp: bool,
+    rasterizer_discard: bool,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    line_width: f32,
+    depth_bias: Option<(f32, f32, f32)>,
+    samples: vk::SampleCountFlags,
+    min_sample_shading: Option<f32>,
+    alpha_to_coverage: bool,
+    alpha_to_one: bool,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    depth_bounds: Option<(f32, f32)>,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    color_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    logic_op: Option<vk::LogicOp>,
+    blend_constants: [f32; 4],
+    dynamic_states: Vec<vk::DynamicState>,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    subpass: u32,
+}
+
+// Setters for state blocks the app doesn't change yet are kept for completeness, the tests cover them.
+#[allow(dead_code)]
+impl GraphicsPipelineBuilder {
+    pub fn new(layout: vk::PipelineLayout, render_pass: vk::RenderPass) -> Self {
+        Self {
+            stages: Vec::new(),
+            specializations: Vec::new(),
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            viewports: Vec::new(),
+            scissors: Vec::new(),
+            dynamic_viewport_count: 1,
+            depth_clamp: false,
+            rasterizer_discard: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::CLOCKWISE,
+            line_width: 1.0,
+            depth_bias: None,
+            samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+            alpha_to_coverage: false,
+            alpha_to_one: false,
+            depth_test: false,
+            depth_write: false,
+            depth_compare_op: vk::CompareOp::LESS,
+            depth_bounds: None,
+            stencil: None,
+            color_attachments: vec![Self::opaque_attachment()],
+            logic_op: None,
+            blend_constants: [0.0; 4],
+            dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
+            layout,
+            render_pass,
+            subpass: 0,
+        }
+    }
+
+    /// Color attachment state that overwrites the destination.
+    pub fn opaque_attachment() -> vk::PipelineColorBlendAttachmentState {
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A)
+            .blend_enable(false)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ZERO)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()
+    }
+
+    /// Color attachment state for regular (non-premultiplied) alpha blending.
+    pub fn alpha_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A)
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()
+    }
+
+    /// Color attachment state for alpha blending colors that are already multiplied by their alpha.
+    pub fn premultiplied_alpha_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+        vk::PipelineColorBlendAttachmentState::builder()
+            .color_write_mask(vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A)
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .color_blend_op(vk::BlendOp::ADD)
+            .src_alpha_blend_factor(vk::BlendFactor::ONE)
+            .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+            .alpha_blend_op(vk::BlendOp::ADD)
+            .build()
+    }
+
+    pub fn stage(mut self, stage: vk::ShaderStageFlags, module: vk::ShaderModule) -> Self {
+        self.stages.retain(|(existing, _)| *existing != stage);
+        self.stages.push((stage, module));
+        self
+    }
+
+    /// Values for the specialization constants of `shader`, checked against the ones it
+    /// declares like `ComputePipeline::new` does. They apply to the module used for its stage.
+    pub fn specialize(mut self, shader: &ShaderReflection, constants: SpecializationConstants) -> Result<Self, SpecializationMismatch> {
+        constants.check(shader)?;
+        self.specializations.retain(|(existing, _)| *existing != shader.stage);
+        self.specializations.push((shader.stage, constants));
+        Ok(self)
+    }
+
+    pub fn vertex_input(mut self, bindings: &[vk::VertexInputBindingDescription], attributes: &[vk::VertexInputAttributeDescription]) -> Self {
+        self.vertex_bindings = bindings.to_vec();
+        self.vertex_attributes = attributes.to_vec();
+        self
+    }
+
+    /// Takes the vertex input from the reflected vertex shader inputs.
+    pub fn reflected_vertex_input(self, reflection: &PipelineReflection) -> Self {
+        let (bindings, attributes) = reflection.vertex_input_descriptions();
+        self.vertex_input(&bindings, &attributes)
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn primitive_restart(mut self, enable: bool) -> Self {
+        self.primitive_restart = enable;
+        self
+    }
+
+    /// A single viewport and scissor covering `extent`.
+    pub fn viewport(self, extent: vk::Extent2D) -> Self {
+        self.viewports(
+            &[vk::Viewport::builder()
+                .x(0.0)
+                .y(0.0)
+                .width(extent.width as f32)
+                .height(extent.height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build()],
+            &[vk::Rect2D::builder()
+                .offset(vk::Offset2D::builder().x(0).y(0).build())
+                .extent(extent)
+                .build()],
+        )
+    }
+
+    /// Bakes the viewports and scissors into the pipeline, so it has to be rebuilt when they change.
+    pub fn viewports(mut self, viewports: &[vk::Viewport], scissors: &[vk::Rect2D]) -> Self {
+        self.viewports = viewports.to_vec();
+        self.scissors = scissors.to_vec();
+        self.dynamic_viewport_count = 0;
+        self.dynamic_states.retain(|state| *state != vk::DynamicState::VIEWPORT && *state != vk::DynamicState::SCISSOR);
+        self
+    }
+
+    /// Leaves `count` viewports and scissors to be set with `cmd_set_viewport`/`cmd_set_scissor`
+    /// while recording. More than one needs the `multiViewport` feature.
+    pub fn dynamic_viewports(mut self, count: u32) -> Self {
+        self.viewports.clear();
+        self.scissors.clear();
+        self.dynamic_viewport_count = count;
+        self = self.dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        self
+    }
+
+    pub fn depth_clamp(mut self, enable: bool) -> Self {
+        self.depth_clamp = enable;
+        self
+    }
+
+    pub fn rasterizer_discard(mut self, enable: bool) -> Self {
+        self.rasterizer_discard = enable;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+
+    pub fn depth_bias(mut self, constant_factor: f32, clamp: f32, slope_factor: f32) -> Self {
+        self.depth_bias = Some((constant_factor, clamp, slope_factor));
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    /// Enables sample shading, with `min_sample_shading` as the fraction of samples shaded individually.
+    pub fn sample_shading(mut self, min_sample_shading: f32) -> Self {
+        self.min_sample_shading = Some(min_sample_shading);
+        self
+    }
+
+    pub fn multisampling(mut self, multisampling: &Multisampling) -> Self {
+        self.samples = multisampling.samples;
+        self.min_sample_shading = multisampling.min_sample_shading;
+        self
+    }
+
+    pub fn alpha_to_coverage(mut self, alpha_to_coverage: bool, alpha_to_one: bool) -> Self {
+        self.alpha_to_coverage = alpha_to_coverage;
+        self.alpha_to_one = alpha_to_one;
+        self
+    }
+
+    pub fn depth_test(mut self, test: bool, write: bool, compare_op: vk::CompareOp) -> Self {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn depth_bounds(mut self, min: f32, max: f32) -> Self {
+        self.depth_bounds = Some((min, max));
+        self
+    }
+
+    pub fn stencil_test(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// Replaces the color attachments, one entry per color attachment of the subpass.
+    pub fn color_attachments(mut self, attachments: &[vk::PipelineColorBlendAttachmentState]) -> Self {
+        self.color_attachments = attachments.to_vec();
+        self
+    }
+
+    pub fn logic_op(mut self, logic_op: vk::LogicOp) -> Self {
+        self.logic_op = Some(logic_op);
+        self
+    }
+
+    pub fn blend_constants(mut self, blend_constants: [f32; 4]) -> Self {
+        self.blend_constants = blend_constants;
+        self
+    }
+
+    /// Adds to the dynamic states, viewport and scissor are managed by the viewport setters.
+    pub fn dynamic_states(mut self, dynamic_states: &[vk::DynamicState]) -> Self {
+        for state in dynamic_states {
+            if !self.dynamic_states.contains(state) {
+                self.dynamic_states.push(*state);
+            }
+        }
+        self
+    }
+
+    pub fn subpass(mut self, render_pass: vk::RenderPass, subpass: u32) -> Self {
+        self.render_pass = render_pass;
+        self.subpass = subpass;
+        self
+    }
+}
+
+/// The create info structs of one pipeline. They reference the builder's vectors,
+/// so the builder has to outlive them.
+struct PipelineState {
+    /// Only kept alive for the pointers in `stages`.
+    _specialization_infos: Vec<Option<vk::SpecializationInfo>>,
+    stages: Vec<vk::PipelineShaderStageCreateInfo>,
+    vertex_input: vk::PipelineVertexInputStateCreateInfo,
+    input_assembly: vk::PipelineInputAssemblyStateCreateInfo,
+    viewport: vk::PipelineViewportStateCreateInfo,
+    rasterization: vk::PipelineRasterizationStateCreateInfo,
+    multisample: vk::PipelineMultisampleStateCreateInfo,
+    depth_stencil: vk::PipelineDepthStencilStateCreateInfo,
+    color_blend: vk::PipelineColorBlendStateCreateInfo,
+    dynamic: vk::PipelineDynamicStateCreateInfo,
+}
+
+impl PipelineState {
+    fn new(builder: &GraphicsPipelineBuilder) -> Self {
+        let main = unsafe { CStr::from_bytes_with_nul_unchecked(ENTRY_POINT) };
+        let (stencil_test, front, back) = match builder.stencil {
+            Some((front, back)) => (true, front, back),
+            None => (false, vk::StencilOpState::default(), vk::StencilOpState::default()),
+        };
+        let (depth_bias, (constant_factor, clamp, slope_factor)) = match builder.depth_bias {
+            Some(depth_bias) => (true, depth_bias),
+            None => (false, (0.0, 0.0, 0.0)),
+        };
+        let (depth_bounds, (min_depth, max_depth)) = match builder.depth_bounds {
+            Some(depth_bounds) => (true, depth_bounds),
+            None => (false, (0.0, 1.0)),
+        };
+        let specialization_infos: Vec<Option<vk::SpecializationInfo>> = builder.stages.iter().map(|(stage, _)| {
+            builder.specializations.iter()
+                .find(|(specialized, constants)| specialized == stage && !constants.is_empty())
+                .map(|(_, constants)| constants.info())
+        }).collect();
+
+        Self {
+            stages: builder.stages.iter().zip(&specialization_infos).map(|((stage, module), specialization_info)| {
+                let mut stage_info = vk::PipelineShaderStageCreateInfo::builder()
+                    .stage(*stage)
+                    .module(*module)
+                    .name(main)
+                    .build();
+                if let Some(specialization_info) = specialization_info {
+                    stage_info.p_specialization_info = specialization_info;
+                }
+                stage_info
+            }).collect(),
+            _specialization_infos: specialization_infos,
+            vertex_input: vk::PipelineVertexInputStateCreateInfo::builder()
+                .vertex_binding_descriptions(&builder.vertex_bindings)
+                .vertex_attribute_descriptions(&builder.vertex_attributes)
+                .build(),
+            input_assembly: vk::PipelineInputAssemblyStateCreateInfo::builder()
+                .topology(builder.topology)
+                .primitive_restart_enable(builder.primitive_restart)
+                .build(),
+            viewport: if builder.dynamic_viewport_count > 0 {
+                vk::PipelineViewportStateCreateInfo::builder()
+                    .viewport_count(builder.dynamic_viewport_count)
+                    .scissor_count(builder.dynamic_viewport_count)
+                    .build()
+            } else {
+                vk::PipelineViewportStateCreateInfo::builder()
+                    .viewports(&builder.viewports)
+                    .scissors(&builder.scissors)
+                    .build()
+            },
+            rasterization: vk::PipelineRasterizationStateCreateInfo::builder()
+                .depth_clamp_enable(builder.depth_clamp)
+                .rasterizer_discard_enable(builder.rasterizer_discard)
+                .polygon_mode(builder.polygon_mode)
+                .line_width(builder.line_width)
+                .cull_mode(builder.cull_mode)
+                .front_face(builder.front_face)
+                .depth_bias_enable(depth_bias)
+                .depth_bias_constant_factor(constant_factor)
+                .depth_bias_clamp(clamp)
+                .depth_bias_slope_factor(slope_factor)
+                .build(),
+            multisample: vk::PipelineMultisampleStateCreateInfo::builder()
+                .sample_shading_enable(builder.min_sample_shading.is_some())
+                .rasterization_samples(builder.samples)
+                .min_sample_shading(builder.min_sample_shading.unwrap_or(1.0))
+                .alpha_to_coverage_enable(builder.alpha_to_coverage)
+                .alpha_to_one_enable(builder.alpha_to_one)
+                .build(),
+            depth_stencil: vk::PipelineDepthStencilStateCreateInfo::builder()
+                .depth_test_enable(builder.depth_test)
+                .depth_write_enable(builder.depth_write)
+                .depth_compare_op(builder.depth_compare_op)
+                .depth_bounds_test_enable(depth_bounds)
+                .min_depth_bounds(min_depth)
+                .max_depth_bounds(max_depth)
+                .stencil_test_enable(stencil_test)
+                .front(front)
+                .back(back)
+                .build(),
+            color_blend: vk::PipelineColorBlendStateCreateInfo::builder()
+                .logic_op_enable(builder.logic_op.is_some())
+                .logic_op(builder.logic_op.unwrap_or(vk::LogicOp::COPY))
+                .attachments(&builder.color_attachments)
+                .blend_constants(builder.blend_constants)
+                .build(),
+            dynamic: vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&builder.dynamic_states)
+                .build(),
+        }
+    }
+}
+
+/// Creates one pipeline per builder in a single call.
+pub fn create_graphics_pipelines(device: &ash::Device, pipeline_cache: vk::PipelineCache, builders: &[GraphicsPipelineBuilder]) -> Result<Vec<vk::Pipeline>, vk::Result> {
+    let states: Vec<PipelineState> = builders.iter().map(PipelineState::new).collect();
+    let pipeline_infos: Vec<vk::GraphicsPipelineCreateInfo> = builders.iter().zip(states.iter()).map(|(builder, state)| {
+        let mut pipeline_info = vk::GraphicsPipelineCreateInfo::builder()
+            .stages(&state.stages)
+            .vertex_input_state(&state.vertex_input)
+            .input_assembly_state(&state.input_assembly)
+            .viewport_state(&state.viewport)
+            .rasterization_state(&state.rasterization)
+            .multisample_state(&state.multisample)
+            .depth_stencil_state(&state.depth_stencil)
+            .color_blend_state(&state.color_blend)
+            .dynamic_state(&state.dynamic)
+            .layout(builder.layout)
+            .render_pass(builder.render_pass)
+            .subpass(builder.subpass)
+            .base_pipeline_index(-1)
+            .build();
+        if state.dynamic.dynamic_state_count == 0 {
+            pipeline_info.p_dynamic_state = std::ptr::null();
+        }
+        pipeline_info
+    }).collect();
+
+    unsafe { device.create_graphics_pipelines(pipeline_cache, &pipeline_infos, None) }.map_err(|(pipelines, err)| {
+        for pipeline in pipelines {
+            if pipeline != vk::Pipeline::null() {
+                unsafe { device.destroy_pipeline(pipeline, None) };
+            }
+        }
+        err
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    use crate::reflection::{ScalarType, SpecializationConstant};
+
+    fn default_builder() -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder::new(vk::PipelineLayout::from_raw(1), vk::RenderPass::from_raw(2))
+    }
+
+    fn extent() -> vk::Extent2D {
+        vk::Extent2D { width: 640, height: 480 }
+    }
+
+    /// The dynamic states of `state`.
+    fn dynamic_states(state: &PipelineState) -> Vec<vk::DynamicState> {
+        (0..state.dynamic.dynamic_state_count as usize).map(|idx| unsafe { *state.dynamic.p_dynamic_states.add(idx) }).collect()
+    }
+
+    #[test]
+    fn defaults() {
+        let builder = default_builder();
+        let state = PipelineState::new(&builder);
+        assert!(state.stages.is_empty());
+        assert_eq!(state.input_assembly.topology, vk::PrimitiveTopology::TRIANGLE_LIST);
+        assert_eq!((state.viewport.viewport_count, state.viewport.scissor_count), (1, 1));
+        assert!(state.viewport.p_viewports.is_null());
+        assert_eq!(state.rasterization.polygon_mode, vk::PolygonMode::FILL);
+        assert_eq!(state.rasterization.cull_mode, vk::CullModeFlags::BACK);
+        assert_eq!(state.rasterization.front_face, vk::FrontFace::CLOCKWISE);
+        assert_eq!(state.rasterization.line_width, 1.0);
+        assert_eq!(state.rasterization.depth_bias_enable, vk::FALSE);
+        assert_eq!(state.multisample.rasterization_samples, vk::SampleCountFlags::TYPE_1);
+        assert_eq!(state.multisample.sample_shading_enable, vk::FALSE);
+        assert_eq!(state.depth_stencil.depth_test_enable, vk::FALSE);
+        assert_eq!(state.depth_stencil.stencil_test_enable, vk::FALSE);
+        assert_eq!(state.color_blend.attachment_count, 1);
+        assert_eq!(unsafe { *state.color_blend.p_attachments }.blend_enable, vk::FALSE);
+        assert_eq!(state.color_blend.logic_op_enable, vk::FALSE);
+        assert_eq!(dynamic_states(&state), vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        assert_eq!(builder.subpass, 0);
+    }
+
+    #[test]
+    fn stages_and_specialization() {
+        let vertex = vk::ShaderModule::from_raw(3);
+        let fragment = vk::ShaderModule::from_raw(4);
+        let shader = ShaderReflection {
+            stage: vk::ShaderStageFlags::VERTEX,
+            entry_point: "main".to_owned(),
+            descriptor_bindings: Vec::new(),
+            push_constants: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            local_size: None,
+            local_size_ids: [None; 3],
+            specialization_constants: vec![SpecializationConstant { constant_id: 0, name: "SIZE".to_owned(), scalar_type: ScalarType::Float }],
+        };
+        let builder = default_builder()
+            .stage(vk::ShaderStageFlags::VERTEX, vk::ShaderModule::from_raw(5))
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment)
+            // replaces the first vertex shader
+            .stage(vk::ShaderStageFlags::VERTEX, vertex)
+            .specialize(&shader, SpecializationConstants::new().set(0, 2.0f32)).unwrap();
+        let state = PipelineState::new(&builder);
+        assert_eq!(state.stages.len(), 2);
+        assert_eq!((state.stages[0].stage, state.stages[0].module), (vk::ShaderStageFlags::FRAGMENT, fragment));
+        assert!(state.stages[0].p_specialization_info.is_null());
+        assert_eq!((state.stages[1].stage, state.stages[1].module), (vk::ShaderStageFlags::VERTEX, vertex));
+        let specialization_info = unsafe { &*state.stages[1].p_specialization_info };
+        assert_eq!((specialization_info.map_entry_count, specialization_info.data_size), (1, 4));
+
+        assert!(builder.clone().specialize(&shader, SpecializationConstants::new().set(0, 2u32)).is_err());
+        assert!(builder.specialize(&shader, SpecializationConstants::new().set(1, 2.0f32)).is_err());
+    }
+
+    #[test]
+    fn input_assembly_and_rasterization() {
+        let builder = default_builder()
+            .topology(vk::PrimitiveTopology::LINE_STRIP)
+            .primitive_restart(true)
+            .depth_clamp(true)
+            .rasterizer_discard(true)
+            .polygon_mode(vk::PolygonMode::LINE)
+            .line_width(2.0)
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_bias(1.0, 0.5, 2.0);
+        let state = PipelineState::new(&builder);
+        assert_eq!(state.input_assembly.topology, vk::PrimitiveTopology::LINE_STRIP);
+        assert_eq!(state.input_assembly.primitive_restart_enable, vk::TRUE);
+        let rasterization = &state.rasterization;
+        assert_eq!((rasterization.depth_clamp_enable, rasterization.rasterizer_discard_enable), (vk::TRUE, vk::TRUE));
+        assert_eq!((rasterization.polygon_mode, rasterization.line_width), (vk::PolygonMode::LINE, 2.0));
+        assert_eq!((rasterization.cull_mode, rasterization.front_face), (vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE));
+        assert_eq!(rasterization.depth_bias_enable, vk::TRUE);
+        assert_eq!((rasterization.depth_bias_constant_factor, rasterization.depth_bias_clamp, rasterization.depth_bias_slope_factor), (1.0, 0.5, 2.0));
+    }
+
+    #[test]
+    fn static_and_dynamic_viewports() {
+        let builder = default_builder().dynamic_states(&[vk::DynamicState::LINE_WIDTH]).viewport(extent());
+        let state = PipelineState::new(&builder);
+        assert_eq!((state.viewport.viewport_count, state.viewport.scissor_count), (1, 1));
+        let viewport = unsafe { *state.viewport.p_viewports };
+        assert_eq!((viewport.width, viewport.height, viewport.max_depth), (640.0, 480.0, 1.0));
+        let scissor = unsafe { *state.viewport.p_scissors };
+        assert_eq!((scissor.extent.width, scissor.extent.height), (640, 480));
+        // baked viewports aren't dynamic anymore, the other dynamic states stay
+        assert_eq!(dynamic_states(&state), vec![vk::DynamicState::LINE_WIDTH]);
+
+        let builder = builder.dynamic_viewports(2).dynamic_states(&[vk::DynamicState::SCISSOR]);
+        let state = PipelineState::new(&builder);
+        assert_eq!((state.viewport.viewport_count, state.viewport.scissor_count), (2, 2));
+        assert!(state.viewport.p_viewports.is_null() && state.viewport.p_scissors.is_null());
+        assert_eq!(dynamic_states(&state), vec![vk::DynamicState::LINE_WIDTH, vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+    }
+
+    #[test]
+    fn multisampling() {
+        let builder = default_builder().samples(vk::SampleCountFlags::TYPE_4).sample_shading(0.25).alpha_to_coverage(true, false);
+        let state = PipelineState::new(&builder);
+        assert_eq!(state.multisample.rasterization_samples, vk::SampleCountFlags::TYPE_4);
+        assert_eq!((state.multisample.sample_shading_enable, state.multisample.min_sample_shading), (vk::TRUE, 0.25));
+        assert_eq!((state.multisample.alpha_to_coverage_enable, state.multisample.alpha_to_one_enable), (vk::TRUE, vk::FALSE));
+
+        let multisampling = Multisampling { samples: vk::SampleCountFlags::TYPE_8, min_sample_shading: None };
+        let builder = default_builder().sample_shading(0.5).multisampling(&multisampling);
+        let state = PipelineState::new(&builder);
+        assert_eq!(state.multisample.rasterization_samples, vk::SampleCountFlags::TYPE_8);
+        assert_eq!((state.multisample.sample_shading_enable, state.multisample.min_sample_shading), (vk::FALSE, 1.0));
+    }
+
+    #[test]
+    fn depth_and_stencil() {
+        let front = vk::StencilOpState { fail_op: vk::StencilOp::KEEP, pass_op: vk::StencilOp::REPLACE, compare_op: vk::CompareOp::ALWAYS, reference: 1, ..Default::default() };
+        let back = vk::StencilOpState { pass_op: vk::StencilOp::INVERT, ..front };
+        let builder = default_builder()
+            .depth_test(true, false, vk::CompareOp::GREATER_OR_EQUAL)
+            .depth_bounds(0.25, 0.75)
+            .stencil_test(front, back);
+        let state = PipelineState::new(&builder);
+        let depth_stencil = &state.depth_stencil;
+        assert_eq!((depth_stencil.depth_test_enable, depth_stencil.depth_write_enable), (vk::TRUE, vk::FALSE));
+        assert_eq!(depth_stencil.depth_compare_op, vk::CompareOp::GREATER_OR_EQUAL);
+        assert_eq!(depth_stencil.depth_bounds_test_enable, vk::TRUE);
+        assert_eq!((depth_stencil.min_depth_bounds, depth_stencil.max_depth_bounds), (0.25, 0.75));
+        assert_eq!(depth_stencil.stencil_test_enable, vk::TRUE);
+        assert_eq!((depth_stencil.front.pass_op, depth_stencil.front.reference), (vk::StencilOp::REPLACE, 1));
+        assert_eq!(depth_stencil.back.pass_op, vk::StencilOp::INVERT);
+    }
+
+    #[test]
+    fn color_blending() {
+        let attachments = [GraphicsPipelineBuilder::alpha_blend_attachment(), GraphicsPipelineBuilder::premultiplied_alpha_blend_attachment()];
+        let builder = default_builder()
+            .color_attachments(&attachments)
+            .logic_op(vk::LogicOp::XOR)
+            .blend_constants([0.1, 0.2, 0.3, 0.4]);
+        let state = PipelineState::new(&builder);
+        let color_blend = &state.color_blend;
+        assert_eq!(color_blend.attachment_count, 2);
+        let second = unsafe { *color_blend.p_attachments.add(1) };
+        assert_eq!((second.blend_enable, second.src_color_blend_factor), (vk::TRUE, vk::BlendFactor::ONE));
+        assert_eq!((color_blend.logic_op_enable, color_blend.logic_op), (vk::TRUE, vk::LogicOp::XOR));
+        assert_eq!(color_blend.blend_constants, [0.1, 0.2, 0.3, 0.4]);
+    }
+
+    #[test]
+    fn dynamic_states_are_added_once() {
+        let builder = default_builder().dynamic_states(&[vk::DynamicState::DEPTH_BIAS, vk::DynamicState::VIEWPORT, vk::DynamicState::DEPTH_BIAS]);
+        let state = PipelineState::new(&builder);
+        assert_eq!(dynamic_states(&state), vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR, vk::DynamicState::DEPTH_BIAS]);
+    }
+
+    #[test]
+    fn subpass() {
+        let render_pass = vk::RenderPass::from_raw(7);
+        let builder = default_builder().subpass(render_pass, 1);
+        assert_eq!((builder.render_pass, builder.subpass), (render_pass, 1));
+    }
+}
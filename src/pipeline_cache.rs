@@ -0,0 +1,93 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::{info, warn};
+use std::{
+    convert::TryInto,
+    path::Path,
+};
+
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// Why a pipeline cache file was discarded.
+#[derive(Debug)]
+pub enum StaleCache {
+    Truncated(usize),
+    HeaderVersion(u32),
+    Vendor(u32),
+    Device(u32),
+    Uuid,
+}
+impl std::error::Error for StaleCache {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for StaleCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StaleCache::Truncated(len) => write!(f, "{} bytes are too short for a pipeline cache header", len),
+            StaleCache::HeaderVersion(version) => write!(f, "unknown header version {}", version),
+            StaleCache::Vendor(vendor_id) => write!(f, "written for vendor {:#06x}", vendor_id),
+            StaleCache::Device(device_id) => write!(f, "written for device {:#06x}", device_id),
+            StaleCache::Uuid => write!(f, "written by a different driver version"),
+        }
+    }
+}
+
+/// The header fields are stored least significant byte first on every host.
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+/// Checks the `VK_PIPELINE_CACHE_HEADER_VERSION_ONE` header against the device the cache is about to be used with.
+pub fn validate_header(data: &[u8], properties: &vk::PhysicalDeviceProperties) -> Result<(), StaleCache> {
+    if data.len() < HEADER_SIZE || (read_u32(data, 0) as usize) < HEADER_SIZE {
+        return Err(StaleCache::Truncated(data.len()));
+    }
+    let version = read_u32(data, 4);
+    if version != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err(StaleCache::HeaderVersion(version));
+    }
+    let vendor_id = read_u32(data, 8);
+    if vendor_id != properties.vendor_id {
+        return Err(StaleCache::Vendor(vendor_id));
+    }
+    let device_id = read_u32(data, 12);
+    if device_id != properties.device_id {
+        return Err(StaleCache::Device(device_id));
+    }
+    if data[16..HEADER_SIZE] != properties.pipeline_cache_uuid {
+        return Err(StaleCache::Uuid);
+    }
+    Ok(())
+}
+
+/// Creates a pipeline cache, seeded from `path` if that file exists and was written for this device and driver.
+pub fn load_pipeline_cache<P: AsRef<Path>>(device: &ash::Device, properties: &vk::PhysicalDeviceProperties, path: P) -> Result<vk::PipelineCache, vk::Result> {
+    let path = path.as_ref();
+    let data = match std::fs::read(path) {
+        Ok(data) => match validate_header(&data, properties) {
+            Ok(()) => {
+                info!("Loaded pipeline cache {} ({} bytes)", path.display(), data.len());
+                data
+            }
+            Err(err) => {
+                warn!("Discarding pipeline cache {}: {}", path.display(), err);
+                Vec::new()
+            }
+        },
+        Err(_) => Vec::new(),
+    };
+    let create_info = vk::PipelineCacheCreateInfo::builder()
+        .initial_data(&data);
+    unsafe { device.create_pipeline_cache(&create_info, None) }
+}
+
+pub fn save_pipeline_cache<P: AsRef<Path>>(device: &ash::Device, pipeline_cache: vk::PipelineCache, path: P) -> Result<(), Box<dyn std::error::Error>> {
+    let data = unsafe { device.get_pipeline_cache_data(pipeline_cache) }?;
+    std::fs::write(path.as_ref(), &data)?;
+    info!("Saved pipeline cache {} ({} bytes)", path.as_ref().display(), data.len());
+    Ok(())
+}
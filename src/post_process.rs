@@ -0,0 +1,181 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::warn;
+
+use crate::{
+    descriptors::{allocate_descriptor_sets, create_descriptor_pool, create_descriptor_set_layouts, write_combined_image_sampler},
+    pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines},
+    reflection::{DescriptorBinding, PipelineReflection},
+};
+
+/// Vertex shader of every effect, a triangle covering the screen.
+pub const FULLSCREEN_SHADER: &str = "fullscreen.vs";
+
+/// Format the scene is rendered to, and the effects before tonemapping write.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Format written by tonemapping and the effects after it, linear values in [0, 1].
+pub const LDR_FORMAT: vk::Format = vk::Format::A2B10G10R10_UNORM_PACK32;
+
+/// A full-screen pass in the post-processing chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Maps HDR colors to [0, 1] with the ACES filmic curve.
+    Tonemap,
+    /// Fast approximate anti-aliasing, smoothing edges by their luminance.
+    Fxaa,
+    /// Darkens the corners.
+    Vignette,
+    /// Looks colors up in a 16x16x16 LUT, laid out as 16 slices side by side.
+    ColorGrading,
+}
+
+impl Effect {
+    /// The name in the config file and the render graph.
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Tonemap => "tonemap",
+            Effect::Fxaa => "fxaa",
+            Effect::Vignette => "vignette",
+            Effect::ColorGrading => "color_grading",
+        }
+    }
+
+    pub fn shader(self) -> &'static str {
+        match self {
+            Effect::Tonemap => "tonemap.fs",
+            Effect::Fxaa => "fxaa.fs",
+            Effect::Vignette => "vignette.fs",
+            Effect::ColorGrading => "color_grading.fs",
+        }
+    }
+}
+
+impl std::str::FromStr for Effect {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [Effect::Tonemap, Effect::Fxaa, Effect::Vignette, Effect::ColorGrading].iter().cloned()
+            .find(|effect| effect.name() == name)
+            .ok_or_else(|| format!("unknown effect `{}`", name))
+    }
+}
+
+/// Format of the image effect `idx` of `effects` writes, HDR until the chain is tonemapped.
+pub fn output_format(effects: &[Effect], idx: usize) -> vk::Format {
+    if effects[..=idx].contains(&Effect::Tonemap) { LDR_FORMAT } else { HDR_FORMAT }
+}
+
+/// The pipeline of one effect. Its fragment shader samples the previous image as `inputImage`,
+/// color grading also samples the LUT as `lut`.
+pub struct PostProcessPass {
+    pub effect: Effect,
+    descriptor_bindings: Vec<DescriptorBinding>,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+}
+
+impl PostProcessPass {
+    /// The shader modules can be destroyed once this returns.
+    pub fn new(device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, effect: Effect, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut result = Self {
+            effect,
+            descriptor_bindings: reflection.descriptor_bindings.clone(),
+            descriptor_set_layouts: Vec::new(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+        };
+        if let Err(err) = result.create_resources(device, pipeline_cache, render_pass, vertex_shader, fragment_shader, reflection) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create_resources(&mut self, device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection) -> Result<(), Box<dyn std::error::Error>> {
+        if reflection.descriptor_bindings.iter().any(|binding| binding.set != 0) {
+            return Err(format!("{}: all inputs have to be in descriptor set 0", self.effect.shader()).into());
+        }
+        self.descriptor_set_layouts = create_descriptor_set_layouts(device, reflection)?;
+        self.descriptor_pool = create_descriptor_pool(device, &reflection.descriptor_bindings, 1)?;
+        self.descriptor_set = allocate_descriptor_sets(device, self.descriptor_pool, &self.descriptor_set_layouts)?[0];
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        self.pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None) }?;
+        let builder = GraphicsPipelineBuilder::new(self.pipeline_layout, render_pass)
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader)
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE);
+        self.pipeline = create_graphics_pipelines(device, pipeline_cache, &[builder])?[0];
+        Ok(())
+    }
+
+    /// Binds the image written by the previous pass, and the LUT where the shader samples it.
+    /// Has to be done again whenever the images are recreated.
+    pub fn bind_inputs(&self, device: &ash::Device, input: vk::ImageView, lut: vk::ImageView, sampler: vk::Sampler) {
+        for binding in &self.descriptor_bindings {
+            match (binding.name.as_str(), binding.descriptor_type) {
+                ("inputImage", vk::DescriptorType::COMBINED_IMAGE_SAMPLER) => write_combined_image_sampler(device, self.descriptor_set, binding.binding, input, sampler),
+                ("lut", vk::DescriptorType::COMBINED_IMAGE_SAMPLER) => write_combined_image_sampler(device, self.descriptor_set, binding.binding, lut, sampler),
+                (name, descriptor_type) => warn!("{}: nothing to bind to {} ({:?})", self.effect.shader(), name, descriptor_type),
+            }
+        }
+    }
+
+    /// Draws the effect over `extent`. Has to be recorded inside the effect's render pass.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, extent: vk::Extent2D) {
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D { offset: vk::Offset2D::default(), extent }];
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, &[self.descriptor_set], &[]);
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            for descriptor_set_layout in &self.descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(*descriptor_set_layout, None);
+            }
+        }
+    }
+}
+
+/// Copies `source` to `destination`, both of `extent`, converting between their formats.
+/// The images have to be in `TRANSFER_SRC_OPTIMAL` and `TRANSFER_DST_OPTIMAL` layout.
+pub fn record_blit(device: &ash::Device, command_buffer: vk::CommandBuffer, source: vk::Image, destination: vk::Image, extent: vk::Extent2D) {
+    let subresource = vk::ImageSubresourceLayers::builder()
+        .aspect_mask(vk::ImageAspectFlags::COLOR)
+        .mip_level(0)
+        .base_array_layer(0)
+        .layer_count(1)
+        .build();
+    let offsets = [vk::Offset3D::default(), vk::Offset3D { x: extent.width as i32, y: extent.height as i32, z: 1 }];
+    let regions = [vk::ImageBlit::builder()
+        .src_subresource(subresource)
+        .src_offsets(offsets)
+        .dst_subresource(subresource)
+        .dst_offsets(offsets)
+        .build()];
+    unsafe { device.cmd_blit_image(command_buffer, source, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, destination, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions, vk::Filter::NEAREST) };
+}
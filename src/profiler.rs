@@ -0,0 +1,318 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use ash::{
+    vk,
+    version::{DeviceV1_0, InstanceV1_0},
+};
+use log::{info, warn};
+
+use crate::{
+    frame::MAX_FRAMES_IN_FLIGHT,
+    queries::FrameQueryPools,
+};
+
+/// Samples in the rolling averages, about two seconds at 60 frames per second.
+pub const AVERAGE_SAMPLES: usize = 120;
+/// Scopes per frame that get GPU timestamps, the ones after that are only timed on the CPU.
+const MAX_SCOPES: u32 = 64;
+/// Frames kept for the Chrome trace.
+const TRACE_FRAMES: usize = 600;
+/// Name of the scope around the whole frame.
+pub const FRAME_SCOPE: &str = "frame";
+
+/// The average of the last `AVERAGE_SAMPLES` values.
+#[derive(Debug, Default)]
+pub struct RollingAverage {
+    samples: VecDeque<f32>,
+    sum: f32,
+}
+
+impl RollingAverage {
+    pub fn push(&mut self, value: f32) {
+        if self.samples.len() == AVERAGE_SAMPLES {
+            self.sum -= self.samples.pop_front().unwrap();
+        }
+        self.samples.push_back(value);
+        self.sum += value;
+    }
+
+    /// `None` until there is a sample.
+    pub fn average(&self) -> Option<f32> {
+        Some(self.sum / self.samples.len() as f32).filter(|_| !self.samples.is_empty())
+    }
+
+    /// The samples from the oldest to the newest.
+    pub fn samples(&self) -> impl Iterator<Item = f32> + '_ {
+        self.samples.iter().cloned()
+    }
+}
+
+/// Rolling averages of one scope in milliseconds. The CPU time is the time spent recording it.
+#[derive(Debug)]
+pub struct ScopeTimings {
+    pub name: String,
+    pub cpu: RollingAverage,
+    pub gpu: RollingAverage,
+}
+
+/// What the GPU did in a frame.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PipelineStatistics {
+    pub vertex_invocations: u64,
+    /// Primitives that reached clipping.
+    pub clipping_invocations: u64,
+    /// Primitives that came out of clipping, more than went in if some were split.
+    pub clipping_primitives: u64,
+    pub fragment_invocations: u64,
+    pub compute_invocations: u64,
+}
+
+/// The statistics counted over the whole frame, the results are in the order of the bits.
+fn pipeline_statistics_flags() -> vk::QueryPipelineStatisticFlags {
+    vk::QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES
+        | vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS
+        | vk::QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS
+}
+
+/// Returned by `begin_scope`, to be handed to `end_scope`.
+#[derive(Debug, Clone, Copy)]
+pub struct Scope(usize);
+
+struct PendingScope {
+    name: String,
+    cpu_start: Instant,
+    cpu_duration: Duration,
+    /// The timestamps at the start and end are written to this query and the next one.
+    query: Option<u32>,
+}
+
+/// A complete event in the Chrome trace, times in microseconds.
+struct TraceEvent {
+    name: String,
+    gpu: bool,
+    start: f64,
+    duration: f64,
+}
+
+/// Times scopes of the recorded frames on the CPU and, where timestamp queries are supported,
+/// on the GPU, and counts pipeline statistics over the frames where the device can. The
+/// queries are read back through `FrameQueryPools`, so reading them never stalls.
+pub struct Profiler {
+    /// `None` without timestamp support.
+    timestamps: Option<FrameQueryPools>,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    /// The bits of the timestamps that are valid, the others are garbage.
+    timestamp_mask: u64,
+    /// `None` without support for pipeline statistics queries.
+    statistics: Option<FrameQueryPools>,
+    latest_statistics: Option<PipelineStatistics>,
+    /// The scopes of each frame in flight, waiting for the GPU to run them.
+    scopes: Vec<Vec<PendingScope>>,
+    current_frame: usize,
+    timings: Vec<ScopeTimings>,
+    epoch: Instant,
+    /// Microseconds to add to GPU times to put them on the CPU timeline.
+    gpu_offset: Option<f64>,
+    trace: VecDeque<Vec<TraceEvent>>,
+    warned_scopes: bool,
+}
+
+impl Profiler {
+    /// `pipeline_statistics` tells whether the device was created with the features needed for
+    /// pipeline statistics queries around secondary command buffers.
+    pub fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: &ash::Device, queue_family_index: u32, pipeline_statistics: bool) -> Result<Self, vk::Result> {
+        let limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let valid_bits = unsafe { instance.get_physical_device_queue_family_properties(physical_device) }[queue_family_index as usize].timestamp_valid_bits;
+        let supported = limits.timestamp_compute_and_graphics == vk::TRUE && valid_bits > 0;
+        if !supported {
+            warn!("Timestamp queries are not supported, only timing the CPU");
+        }
+        let mut result = Self {
+            timestamps: None,
+            timestamp_period: limits.timestamp_period,
+            timestamp_mask: if valid_bits >= 64 { u64::MAX } else { (1 << valid_bits) - 1 },
+            statistics: None,
+            latest_statistics: None,
+            scopes: (0..MAX_FRAMES_IN_FLIGHT).map(|_| Vec::new()).collect(),
+            current_frame: 0,
+            timings: Vec::new(),
+            epoch: Instant::now(),
+            gpu_offset: None,
+            trace: VecDeque::new(),
+            warned_scopes: false,
+        };
+        if let Err(err) = result.create_query_pools(device, supported, pipeline_statistics) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    fn create_query_pools(&mut self, device: &ash::Device, timestamps: bool, pipeline_statistics: bool) -> Result<(), vk::Result> {
+        if timestamps {
+            self.timestamps = Some(FrameQueryPools::new(device, vk::QueryType::TIMESTAMP, vk::QueryPipelineStatisticFlags::empty(), MAX_SCOPES * 2)?);
+        }
+        if pipeline_statistics {
+            self.statistics = Some(FrameQueryPools::new(device, vk::QueryType::PIPELINE_STATISTICS, pipeline_statistics_flags(), 1)?);
+        }
+        Ok(())
+    }
+
+    /// Reads back the results of the previous use of `frame`, resets its queries and begins
+    /// the frame scope. Has to be recorded first, outside of a render pass, once the frame's
+    /// fence has been waited for.
+    pub fn begin_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        let scopes = std::mem::take(&mut self.scopes[frame]);
+        let timestamps = self.timestamps.as_mut().and_then(|timestamps| timestamps.begin_frame(device, command_buffer, frame));
+        self.resolve(scopes, timestamps);
+        if let Some(statistics) = self.statistics.as_mut() {
+            if let Some(values) = statistics.begin_frame(device, command_buffer, frame).filter(|values| !values.is_empty()) {
+                self.latest_statistics = Some(PipelineStatistics {
+                    vertex_invocations: values[0],
+                    clipping_invocations: values[1],
+                    clipping_primitives: values[2],
+                    fragment_invocations: values[3],
+                    compute_invocations: values[4],
+                });
+            }
+            let query = statistics.allocate(1).unwrap();
+            unsafe { device.cmd_begin_query(command_buffer, statistics.query_pool(), query, vk::QueryControlFlags::empty()) };
+        }
+        self.current_frame = frame;
+        self.begin_scope(device, command_buffer, FRAME_SCOPE);
+    }
+
+    /// Ends the frame scope and the statistics, recorded last outside of a render pass.
+    pub fn end_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if let Some(statistics) = &self.statistics {
+            unsafe { device.cmd_end_query(command_buffer, statistics.query_pool(), 0) };
+        }
+        self.end_scope(device, command_buffer, Scope(0));
+    }
+
+    /// The statistics the secondary command buffers executed during the frame have to inherit.
+    pub fn inherited_statistics(&self) -> vk::QueryPipelineStatisticFlags {
+        if self.statistics.is_some() { pipeline_statistics_flags() } else { vk::QueryPipelineStatisticFlags::empty() }
+    }
+
+    /// The statistics of the frame read back last, `None` without support or before the first.
+    pub fn pipeline_statistics(&self) -> Option<PipelineStatistics> {
+        self.latest_statistics
+    }
+
+    /// Starts timing `name`. Scopes can nest, but have to end in the same command buffer.
+    pub fn begin_scope(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, name: &str) -> Scope {
+        let query = match self.timestamps.as_mut() {
+            Some(timestamps) => {
+                let query = timestamps.allocate(2);
+                match query {
+                    Some(query) => unsafe { device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, timestamps.query_pool(), query) },
+                    None if !self.warned_scopes => {
+                        warn!("More than {} profiler scopes in a frame, the rest are only timed on the CPU", MAX_SCOPES);
+                        self.warned_scopes = true;
+                    }
+                    None => {}
+                }
+                query
+            }
+            None => None,
+        };
+        let scopes = &mut self.scopes[self.current_frame];
+        scopes.push(PendingScope { name: name.to_owned(), cpu_start: Instant::now(), cpu_duration: Duration::default(), query });
+        Scope(scopes.len() - 1)
+    }
+
+    pub fn end_scope(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, scope: Scope) {
+        let pending = &mut self.scopes[self.current_frame][scope.0];
+        pending.cpu_duration = pending.cpu_start.elapsed();
+        if let (Some(query), Some(timestamps)) = (pending.query, &self.timestamps) {
+            unsafe { device.cmd_write_timestamp(command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, timestamps.query_pool(), query + 1) };
+        }
+    }
+
+    /// Adds the timings of the scopes of a frame, `timestamps` are its GPU results if available.
+    fn resolve(&mut self, scopes: Vec<PendingScope>, timestamps: Option<Vec<u64>>) {
+        if scopes.is_empty() {
+            return;
+        }
+
+        let mut events = Vec::new();
+        for scope in scopes {
+            let cpu_start = scope.cpu_start.duration_since(self.epoch).as_secs_f64() * 1e6;
+            let cpu_duration = scope.cpu_duration.as_secs_f64() * 1e6;
+            let timings = match self.timings.iter().position(|timings| timings.name == scope.name) {
+                Some(idx) => &mut self.timings[idx],
+                None => {
+                    self.timings.push(ScopeTimings { name: scope.name.clone(), cpu: Default::default(), gpu: Default::default() });
+                    self.timings.last_mut().unwrap()
+                }
+            };
+            timings.cpu.push((cpu_duration / 1e3) as f32);
+            if let (Some(query), Some(timestamps)) = (scope.query, &timestamps) {
+                let period = self.timestamp_period;
+                let begin = timestamps[query as usize] & self.timestamp_mask;
+                let end = timestamps[query as usize + 1] & self.timestamp_mask;
+                // the counter can wrap around when it has less than 64 valid bits
+                let gpu_duration = (end.wrapping_sub(begin) & self.timestamp_mask) as f64 * period as f64 / 1e3;
+                let gpu_start = begin as f64 * period as f64 / 1e3;
+                // GPU and CPU clocks are unrelated, the first frame scope is aligned with the CPU
+                let gpu_offset = *self.gpu_offset.get_or_insert(cpu_start - gpu_start);
+                timings.gpu.push((gpu_duration / 1e3) as f32);
+                events.push(TraceEvent { name: scope.name.clone(), gpu: true, start: gpu_start + gpu_offset, duration: gpu_duration });
+            }
+            events.push(TraceEvent { name: scope.name, gpu: false, start: cpu_start, duration: cpu_duration });
+        }
+        if self.trace.len() == TRACE_FRAMES {
+            self.trace.pop_front();
+        }
+        self.trace.push_back(events);
+    }
+
+    /// The timings of the scope named `name`, if it was seen.
+    pub fn scope_timings(&self, name: &str) -> Option<&ScopeTimings> {
+        self.timings.iter().find(|timings| timings.name == name)
+    }
+
+    pub fn log_timings(&self) {
+        let format = |average: &RollingAverage| average.average().map_or_else(|| "-".to_owned(), |average| format!("{:.3} ms", average));
+        for timings in &self.timings {
+            info!("{:<24} CPU {:>10}   GPU {:>10}", timings.name, format(&timings.cpu), format(&timings.gpu));
+        }
+        if let Some(statistics) = self.latest_statistics {
+            info!("Vertex shader invocations: {}, clipping: {} primitives in, {} out, fragment shader invocations: {}, compute shader invocations: {}",
+                statistics.vertex_invocations, statistics.clipping_invocations, statistics.clipping_primitives, statistics.fragment_invocations, statistics.compute_invocations);
+        }
+    }
+
+    /// Writes the scopes of the last frames in Chrome's trace event format, to be opened in
+    /// `chrome://tracing` or Perfetto. CPU and GPU scopes are on separate tracks.
+    pub fn write_chrome_trace(&self, path: &Path) -> std::io::Result<()> {
+        let mut json = String::from("{\"traceEvents\":[\n");
+        json.push_str("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"CPU\"}},\n");
+        json.push_str("{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"GPU\"}}");
+        for event in self.trace.iter().flatten() {
+            let name = event.name.replace('\\', "\\\\").replace('"', "\\\"");
+            write!(json, ",\n{{\"name\":\"{}\",\"cat\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{:.3},\"dur\":{:.3}}}",
+                name, if event.gpu { "gpu" } else { "cpu" }, if event.gpu { 2 } else { 1 }, event.start, event.duration).unwrap();
+        }
+        json.push_str("\n]}\n");
+        std::fs::write(path, json)
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        if let Some(timestamps) = &self.timestamps {
+            timestamps.destroy(device);
+        }
+        if let Some(statistics) = &self.statistics {
+            statistics.destroy(device);
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::warn;
+
+use crate::frame::MAX_FRAMES_IN_FLIGHT;
+
+/// Occlusion queries per frame, objects after that are drawn without one and count as visible.
+const MAX_OCCLUSION_QUERIES: u32 = 256;
+
+/// A query pool per frame in flight. The queries of a frame are reset when it's recorded and
+/// read back when it's recorded again, after its fence has been waited for, so the results
+/// are `MAX_FRAMES_IN_FLIGHT` frames old but reading them never stalls.
+pub struct FrameQueryPools {
+    query_pools: Vec<vk::QueryPool>,
+    /// Queries allocated in each frame.
+    used: Vec<u32>,
+    capacity: u32,
+    /// Results of one query, one per statistic for pipeline statistics.
+    values_per_query: usize,
+    current_frame: usize,
+}
+
+impl FrameQueryPools {
+    pub fn new(device: &ash::Device, query_type: vk::QueryType, pipeline_statistics: vk::QueryPipelineStatisticFlags, capacity: u32) -> Result<Self, vk::Result> {
+        let mut result = Self {
+            query_pools: Vec::new(),
+            used: vec![0; MAX_FRAMES_IN_FLIGHT],
+            capacity,
+            values_per_query: if query_type == vk::QueryType::PIPELINE_STATISTICS { pipeline_statistics.as_raw().count_ones() as usize } else { 1 },
+            current_frame: 0,
+        };
+        let query_pool_info = vk::QueryPoolCreateInfo::builder()
+            .query_type(query_type)
+            .query_count(capacity)
+            .pipeline_statistics(pipeline_statistics);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            match unsafe { device.create_query_pool(&query_pool_info, None) } {
+                Ok(query_pool) => result.query_pools.push(query_pool),
+                Err(err) => {
+                    result.destroy(device);
+                    return Err(err);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Reads back the queries of the previous use of `frame` and resets them, which has to be
+    /// recorded outside of a render pass before any of them is used again. Returns
+    /// `values_per_query` values for every query that was allocated, or `None` if they aren't
+    /// available, which only happens if the frame wasn't submitted.
+    pub fn begin_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize) -> Option<Vec<u64>> {
+        let query_pool = self.query_pools[frame];
+        let used = std::mem::replace(&mut self.used[frame], 0);
+        let mut results = vec![0u64; used as usize * self.values_per_query];
+        let available = used == 0 || {
+            let stride = (self.values_per_query * std::mem::size_of::<u64>()) as vk::DeviceSize;
+            // not through `get_query_pool_results`, which only handles one value per query
+            let result = unsafe {
+                device.fp_v1_0().get_query_pool_results(device.handle(), query_pool, 0, used, results.len() * std::mem::size_of::<u64>(), results.as_mut_ptr() as *mut _, stride, vk::QueryResultFlags::TYPE_64)
+            };
+            match result {
+                vk::Result::SUCCESS => true,
+                vk::Result::NOT_READY => false,
+                err => {
+                    warn!("Reading query results failed: {}", err);
+                    false
+                }
+            }
+        };
+        unsafe { device.cmd_reset_query_pool(command_buffer, query_pool, 0, self.capacity) };
+        self.current_frame = frame;
+        Some(results).filter(|_| available)
+    }
+
+    /// The first of `count` consecutive queries in the current frame, `None` when they don't fit.
+    pub fn allocate(&mut self, count: u32) -> Option<u32> {
+        let used = &mut self.used[self.current_frame];
+        if *used + count > self.capacity {
+            return None;
+        }
+        *used += count;
+        Some(*used - count)
+    }
+
+    /// The query pool of the current frame.
+    pub fn query_pool(&self) -> vk::QueryPool {
+        self.query_pools[self.current_frame]
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        for query_pool in &self.query_pools {
+            unsafe { device.destroy_query_pool(*query_pool, None) };
+        }
+    }
+}
+
+/// Identifies an object tested by an occlusion query, chosen by the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OcclusionId(pub u32);
+
+/// An occlusion query around one draw.
+#[derive(Debug, Clone, Copy)]
+pub struct OcclusionQuery {
+    pub query_pool: vk::QueryPool,
+    pub query: u32,
+}
+
+impl OcclusionQuery {
+    pub fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe { device.cmd_begin_query(command_buffer, self.query_pool, self.query, vk::QueryControlFlags::empty()) };
+    }
+
+    pub fn end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe { device.cmd_end_query(command_buffer, self.query_pool, self.query) };
+    }
+}
+
+/// Occlusion queries around draws, for culling objects that were hidden in an earlier frame.
+/// An object counts as visible unless it was drawn with a query in the frame read back last
+/// and no sample of it passed the depth test. So a culled object is drawn and tested again
+/// once the frame it was skipped in is read back.
+pub struct OcclusionQueries {
+    query_pools: FrameQueryPools,
+    /// The object of every query in each frame, in query order.
+    objects: Vec<Vec<OcclusionId>>,
+    hidden: HashMap<OcclusionId, bool>,
+}
+
+impl OcclusionQueries {
+    pub fn new(device: &ash::Device) -> Result<Self, vk::Result> {
+        Ok(Self {
+            query_pools: FrameQueryPools::new(device, vk::QueryType::OCCLUSION, vk::QueryPipelineStatisticFlags::empty(), MAX_OCCLUSION_QUERIES)?,
+            objects: vec![Vec::new(); MAX_FRAMES_IN_FLIGHT],
+            hidden: HashMap::new(),
+        })
+    }
+
+    /// Reads back the previous use of `frame` and resets its queries. Has to be recorded
+    /// outside of a render pass, before `allocate`.
+    pub fn begin_frame(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize) {
+        let objects = std::mem::take(&mut self.objects[frame]);
+        if let Some(samples) = self.query_pools.begin_frame(device, command_buffer, frame) {
+            self.hidden.clear();
+            // an object drawn more than once, e.g. in several viewports, is hidden if all its draws are
+            for (object, samples) in objects.into_iter().zip(samples) {
+                *self.hidden.entry(object).or_insert(true) &= samples == 0;
+            }
+        }
+    }
+
+    /// Whether any sample of `object` passed the depth test when it was last read back.
+    pub fn is_visible(&self, object: OcclusionId) -> bool {
+        !self.hidden.get(&object).cloned().unwrap_or(false)
+    }
+
+    /// A query for a draw of `object` in the current frame, `None` when they ran out.
+    pub fn allocate(&mut self, object: OcclusionId) -> Option<OcclusionQuery> {
+        let query = self.query_pools.allocate(1)?;
+        self.objects[self.query_pools.current_frame].push(object);
+        Some(OcclusionQuery { query_pool: self.query_pools.query_pool(), query })
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        self.query_pools.destroy(device);
+    }
+}
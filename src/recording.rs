@@ -0,0 +1,273 @@
+use std::{
+    ops::Range,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex, mpsc},
+    thread::{self, JoinHandle},
+};
+
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::error;
+
+use crate::frame::MAX_FRAMES_IN_FLIGHT;
+
+/// Upper bound of recording threads, more rarely pay off for a handful of draws.
+const MAX_RECORDING_THREADS: usize = 4;
+/// Draws below this are recorded by one job, splitting them costs more than it saves.
+const MIN_DRAWS_PER_JOB: usize = 64;
+
+/// Records commands into a secondary command buffer that is already begun, and gets the device
+/// of the worker thread to do it.
+pub type RecordingJob = Box<dyn FnOnce(&ash::Device, vk::CommandBuffer) + Send>;
+
+/// Secondary command buffers of one thread for one frame in flight. The buffers are kept when
+/// the pool is reset and handed out again the next time the frame is recorded.
+struct SecondaryPool {
+    command_pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    used: usize,
+}
+
+impl SecondaryPool {
+    fn new(device: &ash::Device, queue_family_index: u32) -> Result<Self, vk::Result> {
+        let pool_info = vk::CommandPoolCreateInfo::builder()
+            .flags(vk::CommandPoolCreateFlags::TRANSIENT)
+            .queue_family_index(queue_family_index);
+        Ok(Self {
+            command_pool: unsafe { device.create_command_pool(&pool_info, None) }?,
+            command_buffers: Vec::new(),
+            used: 0,
+        })
+    }
+
+    fn next(&mut self, device: &ash::Device) -> Result<vk::CommandBuffer, vk::Result> {
+        if self.used == self.command_buffers.len() {
+            let alloc_info = vk::CommandBufferAllocateInfo::builder()
+                .command_pool(self.command_pool)
+                .level(vk::CommandBufferLevel::SECONDARY)
+                .command_buffer_count(1);
+            self.command_buffers.push(unsafe { device.allocate_command_buffers(&alloc_info) }?[0]);
+        }
+        self.used += 1;
+        Ok(self.command_buffers[self.used - 1])
+    }
+
+    fn reset(&mut self, device: &ash::Device) -> Result<(), vk::Result> {
+        self.used = 0;
+        unsafe { device.reset_command_pool(self.command_pool, vk::CommandPoolResetFlags::empty()) }
+    }
+
+    fn destroy(&self, device: &ash::Device) {
+        unsafe { device.destroy_command_pool(self.command_pool, None) };
+    }
+}
+
+/// The render pass the secondary command buffers continue, and the statistics counted by the
+/// pipeline statistics query active in the primary command buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct Inheritance {
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    pub pipeline_statistics: vk::QueryPipelineStatisticFlags,
+}
+
+struct Job {
+    index: usize,
+    frame: usize,
+    inheritance: Inheritance,
+    record: RecordingJob,
+}
+
+/// `Err` if the job panicked, with the panic's payload.
+type JobResult = (usize, thread::Result<Result<vk::CommandBuffer, vk::Result>>);
+
+struct Worker {
+    /// Dropped to let the thread finish.
+    jobs: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+    /// One pool per frame in flight. Only locked by the thread while it records, and by the
+    /// owner between frames.
+    pools: Arc<Mutex<Vec<SecondaryPool>>>,
+}
+
+/// Worker threads recording secondary command buffers in parallel. Every thread has its own
+/// command pools, since a pool can't be used by two threads at the same time.
+pub struct Recorder {
+    workers: Vec<Worker>,
+    results: mpsc::Receiver<JobResult>,
+}
+
+impl Recorder {
+    /// Starts a thread per core, up to `MAX_RECORDING_THREADS`.
+    pub fn new(device: &ash::Device, queue_family_index: u32) -> Result<Self, vk::Result> {
+        let thread_count = thread::available_parallelism().map_or(1, |count| count.get()).min(MAX_RECORDING_THREADS);
+        let (result_sender, results) = mpsc::channel();
+        let mut result = Self {
+            workers: Vec::new(),
+            results,
+        };
+        for idx in 0..thread_count {
+            let mut pools = Vec::new();
+            for _ in 0..MAX_FRAMES_IN_FLIGHT {
+                match SecondaryPool::new(device, queue_family_index) {
+                    Ok(pool) => pools.push(pool),
+                    Err(err) => {
+                        pools.iter().for_each(|pool| pool.destroy(device));
+                        result.destroy(device);
+                        return Err(err);
+                    }
+                }
+            }
+            let pools = Arc::new(Mutex::new(pools));
+            let (jobs, receiver) = mpsc::channel();
+            let thread = {
+                let device = device.clone();
+                let pools = pools.clone();
+                let results = result_sender.clone();
+                thread::Builder::new()
+                    .name(format!("recorder {}", idx))
+                    .spawn(move || run_worker(&device, &pools, &receiver, &results))
+                    .expect("Failed to start a recording thread")
+            };
+            result.workers.push(Worker { jobs: Some(jobs), thread: Some(thread), pools });
+        }
+        Ok(result)
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Makes the command buffers of `frame` available again. The GPU has to be done with them.
+    pub fn reset(&self, device: &ash::Device, frame: usize) -> Result<(), vk::Result> {
+        for worker in &self.workers {
+            worker.pools.lock().unwrap()[frame].reset(device)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the jobs on the worker threads, each recording its own secondary command buffer
+    /// that continues `inheritance`. Returns the command buffers in the order of the jobs, to
+    /// be executed by the primary command buffer. A panic in a job is resumed here once all
+    /// jobs are done.
+    pub fn record(&self, frame: usize, inheritance: Inheritance, jobs: Vec<RecordingJob>) -> Result<Vec<vk::CommandBuffer>, vk::Result> {
+        let job_count = jobs.len();
+        for (index, record) in jobs.into_iter().enumerate() {
+            let worker = &self.workers[index % self.workers.len()];
+            worker.jobs.as_ref().unwrap().send(Job { index, frame, inheritance, record })
+                .expect("A recording thread stopped");
+        }
+        let mut command_buffers = vec![vk::CommandBuffer::null(); job_count];
+        let mut result = Ok(());
+        let mut panic = None;
+        for _ in 0..job_count {
+            let (index, command_buffer) = self.results.recv().expect("A recording thread stopped");
+            match command_buffer {
+                Ok(Ok(command_buffer)) => command_buffers[index] = command_buffer,
+                // keep receiving, so no result is left over for the next call
+                Ok(Err(err)) => result = Err(err),
+                Err(payload) => panic = Some(payload),
+            }
+        }
+        if let Some(payload) = panic {
+            panic::resume_unwind(payload);
+        }
+        result.map(|_| command_buffers)
+    }
+
+    /// Stops the threads and destroys their command pools.
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for worker in &mut self.workers {
+            worker.jobs.take();
+            if let Some(thread) = worker.thread.take() {
+                if thread.join().is_err() {
+                    error!("A recording thread panicked");
+                }
+            }
+            for pool in worker.pools.lock().unwrap_or_else(|err| err.into_inner()).iter() {
+                pool.destroy(device);
+            }
+        }
+        self.workers.clear();
+    }
+}
+
+fn run_worker(device: &ash::Device, pools: &Mutex<Vec<SecondaryPool>>, jobs: &mpsc::Receiver<Job>, results: &mpsc::Sender<JobResult>) {
+    for Job { index, frame, inheritance, record } in jobs {
+        // the lock is taken outside of catch_unwind, so a panicking job doesn't poison it
+        let mut pools = pools.lock().unwrap_or_else(|err| err.into_inner());
+        let pool = &mut pools[frame];
+        let command_buffer = panic::catch_unwind(AssertUnwindSafe(|| record_job(device, pool, inheritance, record)));
+        drop(pools);
+        if results.send((index, command_buffer)).is_err() {
+            break;
+        }
+    }
+}
+
+fn record_job(device: &ash::Device, pool: &mut SecondaryPool, inheritance: Inheritance, record: RecordingJob) -> Result<vk::CommandBuffer, vk::Result> {
+    let command_buffer = pool.next(device)?;
+    let inheritance_info = vk::CommandBufferInheritanceInfo::builder()
+        .render_pass(inheritance.render_pass)
+        .subpass(inheritance.subpass)
+        .pipeline_statistics(inheritance.pipeline_statistics);
+    let begin_info = vk::CommandBufferBeginInfo::builder()
+        .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT | vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE)
+        .inheritance_info(&inheritance_info);
+    unsafe { device.begin_command_buffer(command_buffer, &begin_info) }?;
+    record(device, command_buffer);
+    unsafe { device.end_command_buffer(command_buffer) }?;
+    Ok(command_buffer)
+}
+
+/// Splits `count` draws into at most `max_jobs` contiguous ranges of similar size, fewer if
+/// the jobs would get less than `MIN_DRAWS_PER_JOB` draws. Always returns at least one range.
+pub fn split_draws(count: usize, max_jobs: usize) -> Vec<Range<usize>> {
+    let jobs = count.div_ceil(MIN_DRAWS_PER_JOB).clamp(1, max_jobs.max(1));
+    (0..jobs).map(|job| job * count / jobs..(job + 1) * count / jobs).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_are_split_into_contiguous_ranges() {
+        // draw count, max jobs and the (start, end) of the expected ranges
+        let cases = [
+            (0, 4, vec![(0, 0)]),
+            (1, 4, vec![(0, 1)]),
+            (MIN_DRAWS_PER_JOB, 4, vec![(0, 64)]),
+            // too few draws for more jobs
+            (MIN_DRAWS_PER_JOB + 1, 4, vec![(0, 32), (32, 65)]),
+            (130, 4, vec![(0, 43), (43, 86), (86, 130)]),
+            (1000, 4, vec![(0, 250), (250, 500), (500, 750), (750, 1000)]),
+            (1001, 3, vec![(0, 333), (333, 667), (667, 1001)]),
+            (1000, 1, vec![(0, 1000)]),
+            (1000, 0, vec![(0, 1000)]),
+        ];
+        for (count, max_jobs, expected) in &cases {
+            let ranges: Vec<_> = split_draws(*count, *max_jobs).iter().map(|range| (range.start, range.end)).collect();
+            assert_eq!(ranges, *expected, "{} draws on {} jobs", count, max_jobs);
+        }
+    }
+
+    #[test]
+    fn ranges_cover_every_draw_once() {
+        for count in 0..300 {
+            for max_jobs in 1..6 {
+                let ranges = split_draws(count, max_jobs);
+                assert!(!ranges.is_empty() && ranges.len() <= max_jobs);
+                assert_eq!(ranges.first().unwrap().start, 0);
+                assert_eq!(ranges.last().unwrap().end, count);
+                for pair in ranges.windows(2) {
+                    assert_eq!(pair[0].end, pair[1].start);
+                }
+                let (smallest, largest) = (ranges.iter().map(|range| range.len()).min().unwrap(), ranges.iter().map(|range| range.len()).max().unwrap());
+                assert!(largest - smallest <= 1, "{} draws on {} jobs: {:?}", count, max_jobs, ranges);
+            }
+        }
+    }
+}
@@ -0,0 +1,912 @@
+use ash::vk;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+// Opcodes
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT_TRUE: u32 = 48;
+const OP_SPEC_CONSTANT_FALSE: u32 = 49;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_SPEC_CONSTANT_COMPOSITE: u32 = 51;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+// Execution modes
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+
+// Decorations
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const BUILT_IN_WORKGROUP_SIZE: u32 = 25;
+
+// Storage classes
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_OUTPUT: u32 = 3;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+// Image dimensions
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Debug)]
+pub enum ReflectionError {
+    InvalidHeader,
+    Truncated(usize),
+    UnsupportedExecutionModel(u32),
+    NoEntryPoint,
+    DescriptorConflict { set: u32, binding: u32 },
+}
+impl std::error::Error for ReflectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for ReflectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectionError::InvalidHeader => write!(f, "Invalid SPIR-V header"),
+            ReflectionError::Truncated(offset) => write!(f, "SPIR-V instruction at word {} is truncated", offset),
+            ReflectionError::UnsupportedExecutionModel(model) => write!(f, "Unsupported SPIR-V execution model {}", model),
+            ReflectionError::NoEntryPoint => write!(f, "SPIR-V module has no entry point"),
+            ReflectionError::DescriptorConflict { set, binding } => write!(f, "Stages disagree on the type of descriptor set {} binding {}", set, binding),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct PushConstantBlock {
+    pub name: String,
+    pub offset: u32,
+    pub size: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+}
+
+/// Types a specialization constant can have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScalarType {
+    Bool,
+    Int,
+    UInt,
+    Float,
+    Double,
+}
+
+/// A `layout(constant_id = N) const` declaration.
+#[derive(Debug, Clone)]
+pub struct SpecializationConstant {
+    pub constant_id: u32,
+    pub name: String,
+    pub scalar_type: ScalarType,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterfaceVariable {
+    pub location: u32,
+    pub name: String,
+    pub format: vk::Format,
+    /// Matrices take up one location per column.
+    pub location_count: u32,
+}
+
+/// Everything a single shader stage exposes to the pipeline.
+#[derive(Debug, Clone)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: String,
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+    pub push_constants: Vec<PushConstantBlock>,
+    pub inputs: Vec<InterfaceVariable>,
+    pub outputs: Vec<InterfaceVariable>,
+    /// Workgroup size of a compute shader, `None` for the other stages.
+    pub local_size: Option<[u32; 3]>,
+    /// Specialization constant ids that override the workgroup size (`local_size_x_id` etc.).
+    pub local_size_ids: [Option<u32>; 3],
+    pub specialization_constants: Vec<SpecializationConstant>,
+}
+
+#[derive(Debug, Clone)]
+pub enum InterfaceMismatch {
+    MissingOutput { stage: vk::ShaderStageFlags, location: u32, name: String },
+    FormatMismatch { stage: vk::ShaderStageFlags, location: u32, name: String, output_format: vk::Format, input_format: vk::Format },
+}
+impl std::fmt::Display for InterfaceMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterfaceMismatch::MissingOutput { stage, location, name } =>
+                write!(f, "{:?} input `{}` at location {} has no matching output in the previous stage", stage, name, location),
+            InterfaceMismatch::FormatMismatch { stage, location, name, output_format, input_format } =>
+                write!(f, "{:?} input `{}` at location {} is {:?}, but the previous stage writes {:?}", stage, name, location, input_format, output_format),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InterfaceMismatches(pub Vec<InterfaceMismatch>);
+impl std::error::Error for InterfaceMismatches {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for InterfaceMismatches {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} shader interface mismatch(es)", self.0.len())?;
+        for mismatch in &self.0 {
+            write!(f, "\n  {}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+/// The merged view of all stages of a pipeline.
+#[derive(Debug, Clone, Default)]
+pub struct PipelineReflection {
+    pub stages: vk::ShaderStageFlags,
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+    pub vertex_inputs: Vec<InterfaceVariable>,
+    pub fragment_outputs: Vec<InterfaceVariable>,
+    pub mismatches: Vec<InterfaceMismatch>,
+}
+
+struct Module<'a> {
+    words: &'a [u32],
+    names: HashMap<u32, String>,
+    decorations: HashMap<u32, HashMap<u32, u32>>,
+    flags: HashMap<u32, HashSet<u32>>,
+    member_decorations: HashMap<(u32, u32), HashMap<u32, u32>>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32, u32)>,
+    local_size: Option<[u32; 3]>,
+    /// Result type and id of every specialization constant.
+    spec_constants: Vec<(u32, u32)>,
+    spec_composites: HashMap<u32, Vec<u32>>,
+}
+
+fn decode_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes().to_vec()).take_while(|byte| *byte != 0).collect();
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// Operand words the instructions `Module::parse` reads need at least.
+fn min_operands(opcode: u32) -> usize {
+    match opcode {
+        OP_TYPE_BOOL | OP_TYPE_SAMPLER | OP_TYPE_STRUCT => 1,
+        OP_NAME | OP_EXECUTION_MODE | OP_DECORATE | OP_TYPE_FLOAT | OP_TYPE_SAMPLED_IMAGE | OP_TYPE_RUNTIME_ARRAY | OP_CONSTANT
+            | OP_SPEC_CONSTANT_TRUE | OP_SPEC_CONSTANT_FALSE | OP_SPEC_CONSTANT | OP_SPEC_CONSTANT_COMPOSITE => 2,
+        OP_ENTRY_POINT | OP_MEMBER_DECORATE | OP_TYPE_INT | OP_TYPE_VECTOR | OP_TYPE_MATRIX | OP_TYPE_ARRAY | OP_TYPE_POINTER | OP_VARIABLE => 3,
+        OP_TYPE_IMAGE => 8,
+        _ => 0,
+    }
+}
+
+impl<'a> Module<'a> {
+    fn parse(words: &'a [u32]) -> Result<(Self, u32, String), ReflectionError> {
+        if words.len() < 5 || words[0] != SPIRV_MAGIC {
+            return Err(ReflectionError::InvalidHeader);
+        }
+        let mut module = Module {
+            words,
+            names: HashMap::new(),
+            decorations: HashMap::new(),
+            flags: HashMap::new(),
+            member_decorations: HashMap::new(),
+            types: HashMap::new(),
+            constants: HashMap::new(),
+            variables: Vec::new(),
+            local_size: None,
+            spec_constants: Vec::new(),
+            spec_composites: HashMap::new(),
+        };
+        let mut entry_point = None;
+
+        let mut offset = 5;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xffff;
+            if word_count == 0 || offset + word_count > words.len() {
+                return Err(ReflectionError::Truncated(offset));
+            }
+            let operands = &module.words[offset + 1..offset + word_count];
+            if operands.len() < min_operands(opcode) {
+                return Err(ReflectionError::Truncated(offset));
+            }
+            match opcode {
+                OP_NAME => {
+                    module.names.insert(operands[0], decode_string(&operands[1..]));
+                }
+                OP_ENTRY_POINT if entry_point.is_none() => {
+                    entry_point = Some((operands[0], decode_string(&operands[2..])));
+                }
+                OP_EXECUTION_MODE if operands.len() >= 5 && operands[1] == EXECUTION_MODE_LOCAL_SIZE => {
+                    module.local_size = Some([operands[2], operands[3], operands[4]]);
+                }
+                OP_DECORATE => {
+                    if operands.len() > 2 {
+                        module.decorations.entry(operands[0]).or_default().insert(operands[1], operands[2]);
+                    } else {
+                        module.flags.entry(operands[0]).or_default().insert(operands[1]);
+                    }
+                }
+                OP_MEMBER_DECORATE => {
+                    let value = operands.get(3).cloned().unwrap_or(0);
+                    module.member_decorations.entry((operands[0], operands[1])).or_default().insert(operands[2], value);
+                }
+                OP_TYPE_BOOL => { module.types.insert(operands[0], Type::Bool); }
+                OP_TYPE_INT => { module.types.insert(operands[0], Type::Int { width: operands[1], signed: operands[2] != 0 }); }
+                OP_TYPE_FLOAT => { module.types.insert(operands[0], Type::Float { width: operands[1] }); }
+                OP_TYPE_VECTOR => { module.types.insert(operands[0], Type::Vector { component: operands[1], count: operands[2] }); }
+                OP_TYPE_MATRIX => { module.types.insert(operands[0], Type::Matrix { column: operands[1], count: operands[2] }); }
+                OP_TYPE_IMAGE => { module.types.insert(operands[0], Type::Image { dim: operands[2], sampled: operands[6] }); }
+                OP_TYPE_SAMPLER => { module.types.insert(operands[0], Type::Sampler); }
+                OP_TYPE_SAMPLED_IMAGE => { module.types.insert(operands[0], Type::SampledImage); }
+                OP_TYPE_ARRAY => { module.types.insert(operands[0], Type::Array { element: operands[1], length: operands[2] }); }
+                OP_TYPE_RUNTIME_ARRAY => { module.types.insert(operands[0], Type::RuntimeArray { element: operands[1] }); }
+                OP_TYPE_STRUCT => { module.types.insert(operands[0], Type::Struct { members: operands[1..].to_vec() }); }
+                OP_TYPE_POINTER => { module.types.insert(operands[0], Type::Pointer { pointee: operands[2] }); }
+                OP_CONSTANT => {
+                    if let Some(value) = operands.get(2) {
+                        module.constants.insert(operands[1], *value);
+                    }
+                }
+                OP_SPEC_CONSTANT_TRUE | OP_SPEC_CONSTANT_FALSE | OP_SPEC_CONSTANT => {
+                    module.spec_constants.push((operands[0], operands[1]));
+                }
+                OP_SPEC_CONSTANT_COMPOSITE => {
+                    module.spec_composites.insert(operands[1], operands[2..].to_vec());
+                }
+                OP_VARIABLE => {
+                    module.variables.push((operands[0], operands[1], operands[2]));
+                }
+                _ => {}
+            }
+            offset += word_count;
+        }
+
+        let (execution_model, name) = entry_point.ok_or(ReflectionError::NoEntryPoint)?;
+        Ok((module, execution_model, name))
+    }
+
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&id).and_then(|decorations| decorations.get(&decoration)).cloned()
+    }
+    fn has_flag(&self, id: u32, decoration: u32) -> bool {
+        self.flags.get(&id).map(|flags| flags.contains(&decoration)).unwrap_or(false)
+    }
+    fn member_decoration(&self, id: u32, member: u32, decoration: u32) -> Option<u32> {
+        self.member_decorations.get(&(id, member)).and_then(|decorations| decorations.get(&decoration)).cloned()
+    }
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn array_length(&self, length_id: u32) -> u32 {
+        self.constants.get(&length_id).cloned().unwrap_or(1)
+    }
+
+    /// The type of `type_id`, added to `visiting` until the caller pops it. `None` if the type
+    /// is unknown or already in `visiting`, the types the caller is inside of. Only malformed
+    /// modules have types that contain themselves, but following them would recurse until
+    /// the stack overflows.
+    fn enter(&self, type_id: u32, visiting: &mut Vec<u32>) -> Option<&Type> {
+        if visiting.contains(&type_id) {
+            return None;
+        }
+        let ty = self.types.get(&type_id)?;
+        visiting.push(type_id);
+        Some(ty)
+    }
+
+    /// Size in bytes of a type as laid out in a uniform or push constant block.
+    fn size_of(&self, type_id: u32, visiting: &mut Vec<u32>) -> u32 {
+        let size = match self.enter(type_id, visiting) {
+            None => return 0,
+            Some(Type::Bool) => 4,
+            Some(Type::Int { width, .. }) | Some(Type::Float { width }) => width / 8,
+            Some(Type::Vector { component, count }) => self.size_of(*component, visiting) * count,
+            Some(Type::Matrix { column, count }) => self.size_of(*column, visiting) * count,
+            Some(Type::Array { element, length }) => {
+                let stride = self.decoration(type_id, DECORATION_ARRAY_STRIDE).unwrap_or_else(|| self.size_of(*element, visiting));
+                stride * self.array_length(*length)
+            }
+            Some(Type::Struct { members }) => members.iter().enumerate().map(|(idx, member)| {
+                let offset = self.member_decoration(type_id, idx as u32, DECORATION_OFFSET).unwrap_or(0);
+                let size = match self.types.get(member) {
+                    Some(Type::Matrix { count, .. }) => self.member_decoration(type_id, idx as u32, DECORATION_MATRIX_STRIDE)
+                        .map(|stride| stride * count)
+                        .unwrap_or_else(|| self.size_of(*member, visiting)),
+                    _ => self.size_of(*member, visiting),
+                };
+                offset + size
+            }).max().unwrap_or(0),
+            Some(_) => 0,
+        };
+        visiting.pop();
+        size
+    }
+
+    fn format_of(&self, type_id: u32, visiting: &mut Vec<u32>) -> (vk::Format, u32) {
+        let format = match self.enter(type_id, visiting) {
+            None => return (vk::Format::UNDEFINED, 1),
+            Some(Type::Matrix { column, count }) => (self.format_of(*column, visiting).0, *count),
+            Some(Type::Array { element, length }) => {
+                let (format, location_count) = self.format_of(*element, visiting);
+                (format, location_count * self.array_length(*length))
+            }
+            Some(Type::Vector { component, count }) => (scalar_format(self.types.get(component), *count), 1),
+            scalar => (scalar_format(scalar, 1), 1),
+        };
+        visiting.pop();
+        format
+    }
+
+    fn descriptor_type(&self, storage_class: u32, type_id: u32, visiting: &mut Vec<u32>) -> Option<(vk::DescriptorType, u32)> {
+        let descriptor_type = match self.enter(type_id, visiting)? {
+            Type::Array { element, length } => self.descriptor_type(storage_class, *element, visiting).map(|(ty, count)| (ty, count * self.array_length(*length))),
+            Type::RuntimeArray { element } => self.descriptor_type(storage_class, *element, visiting),
+            Type::Struct { .. } => match storage_class {
+                STORAGE_CLASS_UNIFORM if self.has_flag(type_id, DECORATION_BUFFER_BLOCK) => Some((vk::DescriptorType::STORAGE_BUFFER, 1)),
+                STORAGE_CLASS_UNIFORM => Some((vk::DescriptorType::UNIFORM_BUFFER, 1)),
+                STORAGE_CLASS_STORAGE_BUFFER => Some((vk::DescriptorType::STORAGE_BUFFER, 1)),
+                _ => None,
+            },
+            Type::SampledImage => Some((vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1)),
+            Type::Sampler => Some((vk::DescriptorType::SAMPLER, 1)),
+            Type::Image { dim, sampled } => Some((match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            }, 1)),
+            _ => None,
+        };
+        visiting.pop();
+        descriptor_type
+    }
+
+    /// Builtins are either decorated directly or wrapped in a block like gl_PerVertex.
+    fn is_builtin(&self, variable: u32, type_id: u32, visiting: &mut Vec<u32>) -> bool {
+        if self.decoration(variable, DECORATION_BUILT_IN).is_some() {
+            return true;
+        }
+        let builtin = match self.enter(type_id, visiting) {
+            None => return false,
+            Some(Type::Struct { members }) => (0..members.len() as u32).any(|idx| self.member_decoration(type_id, idx, DECORATION_BUILT_IN).is_some()),
+            Some(Type::Array { element, .. }) => self.is_builtin(variable, *element, visiting),
+            Some(_) => false,
+        };
+        visiting.pop();
+        builtin
+    }
+}
+
+fn scalar_format(scalar: Option<&Type>, count: u32) -> vk::Format {
+    const FLOAT32: [vk::Format; 4] = [vk::Format::R32_SFLOAT, vk::Format::R32G32_SFLOAT, vk::Format::R32G32B32_SFLOAT, vk::Format::R32G32B32A32_SFLOAT];
+    const FLOAT64: [vk::Format; 4] = [vk::Format::R64_SFLOAT, vk::Format::R64G64_SFLOAT, vk::Format::R64G64B64_SFLOAT, vk::Format::R64G64B64A64_SFLOAT];
+    const SINT32: [vk::Format; 4] = [vk::Format::R32_SINT, vk::Format::R32G32_SINT, vk::Format::R32G32B32_SINT, vk::Format::R32G32B32A32_SINT];
+    const UINT32: [vk::Format; 4] = [vk::Format::R32_UINT, vk::Format::R32G32_UINT, vk::Format::R32G32B32_UINT, vk::Format::R32G32B32A32_UINT];
+    let formats = match scalar {
+        Some(Type::Float { width: 64 }) => &FLOAT64,
+        Some(Type::Float { .. }) => &FLOAT32,
+        Some(Type::Int { signed: true, .. }) => &SINT32,
+        Some(Type::Int { signed: false, .. }) | Some(Type::Bool) => &UINT32,
+        _ => return vk::Format::UNDEFINED,
+    };
+    formats[(count.clamp(1, 4) - 1) as usize]
+}
+
+/// Size in bytes of the vertex formats reflection can produce.
+pub fn format_size(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R32_SFLOAT | vk::Format::R32_SINT | vk::Format::R32_UINT => 4,
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_SINT | vk::Format::R32G32_UINT | vk::Format::R64_SFLOAT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_UINT => 12,
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_SINT | vk::Format::R32G32B32A32_UINT | vk::Format::R64G64_SFLOAT => 16,
+        vk::Format::R64G64B64_SFLOAT => 24,
+        vk::Format::R64G64B64A64_SFLOAT => 32,
+        _ => 0,
+    }
+}
+
+fn execution_model_stage(model: u32) -> Result<vk::ShaderStageFlags, ReflectionError> {
+    Ok(match model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => return Err(ReflectionError::UnsupportedExecutionModel(model)),
+    })
+}
+
+impl ShaderReflection {
+    pub fn reflect(code: &[u32]) -> Result<Self, ReflectionError> {
+        let (module, execution_model, entry_point) = Module::parse(code)?;
+        let stage = execution_model_stage(execution_model)?;
+        let mut result = ShaderReflection {
+            stage,
+            entry_point,
+            descriptor_bindings: Vec::new(),
+            push_constants: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            local_size: module.local_size,
+            local_size_ids: [None; 3],
+            specialization_constants: Vec::new(),
+        };
+
+        for (type_id, id) in &module.spec_constants {
+            let constant_id = match module.decoration(*id, DECORATION_SPEC_ID) {
+                Some(constant_id) => constant_id,
+                None => continue,
+            };
+            let scalar_type = match module.types.get(type_id) {
+                Some(Type::Bool) => ScalarType::Bool,
+                Some(Type::Int { signed: true, .. }) => ScalarType::Int,
+                Some(Type::Int { signed: false, .. }) => ScalarType::UInt,
+                Some(Type::Float { width: 64 }) => ScalarType::Double,
+                Some(Type::Float { .. }) => ScalarType::Float,
+                _ => continue,
+            };
+            result.specialization_constants.push(SpecializationConstant { constant_id, name: module.name(*id), scalar_type });
+        }
+        result.specialization_constants.sort_by_key(|constant| constant.constant_id);
+        for (id, constituents) in &module.spec_composites {
+            if module.decoration(*id, DECORATION_BUILT_IN) == Some(BUILT_IN_WORKGROUP_SIZE) {
+                for (axis, constituent) in constituents.iter().take(3).enumerate() {
+                    result.local_size_ids[axis] = module.decoration(*constituent, DECORATION_SPEC_ID);
+                }
+            }
+        }
+
+        for (pointer_type, variable, storage_class) in &module.variables {
+            let type_id = match module.types.get(pointer_type) {
+                Some(Type::Pointer { pointee, .. }) => *pointee,
+                _ => continue,
+            };
+            match *storage_class {
+                STORAGE_CLASS_INPUT | STORAGE_CLASS_OUTPUT => {
+                    if module.is_builtin(*variable, type_id, &mut Vec::new()) {
+                        continue;
+                    }
+                    if let Some(location) = module.decoration(*variable, DECORATION_LOCATION) {
+                        let (format, location_count) = module.format_of(type_id, &mut Vec::new());
+                        let interface_variable = InterfaceVariable { location, name: module.name(*variable), format, location_count };
+                        if *storage_class == STORAGE_CLASS_INPUT {
+                            result.inputs.push(interface_variable);
+                        } else {
+                            result.outputs.push(interface_variable);
+                        }
+                    }
+                }
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    let members = match module.types.get(&type_id) {
+                        Some(Type::Struct { members }) => members.len() as u32,
+                        _ => 0,
+                    };
+                    let offset = (0..members).filter_map(|idx| module.member_decoration(type_id, idx, DECORATION_OFFSET)).min().unwrap_or(0);
+                    let mut name = module.name(*variable);
+                    if name.is_empty() {
+                        name = module.name(type_id);
+                    }
+                    result.push_constants.push(PushConstantBlock { name, offset, size: module.size_of(type_id, &mut Vec::new()) - offset, stage_flags: stage });
+                }
+                STORAGE_CLASS_UNIFORM_CONSTANT | STORAGE_CLASS_UNIFORM | STORAGE_CLASS_STORAGE_BUFFER => {
+                    if let Some((descriptor_type, count)) = module.descriptor_type(*storage_class, type_id, &mut Vec::new()) {
+                        let mut name = module.name(*variable);
+                        if name.is_empty() {
+                            name = module.name(type_id);
+                        }
+                        result.descriptor_bindings.push(DescriptorBinding {
+                            set: module.decoration(*variable, DECORATION_DESCRIPTOR_SET).unwrap_or(0),
+                            binding: module.decoration(*variable, DECORATION_BINDING).unwrap_or(0),
+                            descriptor_type,
+                            count,
+                            stage_flags: stage,
+                            name,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        result.inputs.sort_by_key(|variable| variable.location);
+        result.outputs.sort_by_key(|variable| variable.location);
+        result.descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(result)
+    }
+}
+
+fn stage_order(stage: vk::ShaderStageFlags) -> u32 {
+    match stage {
+        vk::ShaderStageFlags::VERTEX => 0,
+        vk::ShaderStageFlags::TESSELLATION_CONTROL => 1,
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => 2,
+        vk::ShaderStageFlags::GEOMETRY => 3,
+        vk::ShaderStageFlags::FRAGMENT => 4,
+        _ => 5,
+    }
+}
+
+impl PipelineReflection {
+    /// Combines the reflection of all stages of one pipeline, checking that the outputs
+    /// of every stage feed the inputs of the next one.
+    pub fn merge(stages: &[&ShaderReflection]) -> Result<Self, ReflectionError> {
+        let mut stages = stages.to_vec();
+        stages.sort_by_key(|stage| stage_order(stage.stage));
+
+        let mut result = Self::default();
+        let mut bindings: BTreeMap<(u32, u32), DescriptorBinding> = BTreeMap::new();
+        for stage in &stages {
+            result.stages |= stage.stage;
+            for binding in &stage.descriptor_bindings {
+                if let Some(existing) = bindings.get_mut(&(binding.set, binding.binding)) {
+                    if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count {
+                        return Err(ReflectionError::DescriptorConflict { set: binding.set, binding: binding.binding });
+                    }
+                    existing.stage_flags |= binding.stage_flags;
+                } else {
+                    bindings.insert((binding.set, binding.binding), binding.clone());
+                }
+            }
+            for block in &stage.push_constants {
+                if let Some(range) = result.push_constant_ranges.iter_mut().find(|range| range.offset == block.offset && range.size == block.size) {
+                    range.stage_flags |= block.stage_flags;
+                } else {
+                    result.push_constant_ranges.push(vk::PushConstantRange::builder()
+                        .stage_flags(block.stage_flags)
+                        .offset(block.offset)
+                        .size(block.size)
+                        .build());
+                }
+            }
+        }
+        result.descriptor_bindings = bindings.into_values().collect();
+
+        for pair in stages.windows(2) {
+            let (producer, consumer) = (pair[0], pair[1]);
+            for input in &consumer.inputs {
+                match producer.outputs.iter().find(|output| output.location == input.location) {
+                    None => result.mismatches.push(InterfaceMismatch::MissingOutput { stage: consumer.stage, location: input.location, name: input.name.clone() }),
+                    Some(output) if output.format != input.format => result.mismatches.push(InterfaceMismatch::FormatMismatch {
+                        stage: consumer.stage,
+                        location: input.location,
+                        name: input.name.clone(),
+                        output_format: output.format,
+                        input_format: input.format,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(first) = stages.first() {
+            if first.stage == vk::ShaderStageFlags::VERTEX {
+                result.vertex_inputs = first.inputs.clone();
+            }
+        }
+        if let Some(fragment) = stages.iter().find(|stage| stage.stage == vk::ShaderStageFlags::FRAGMENT) {
+            result.fragment_outputs = fragment.outputs.clone();
+        }
+
+        Ok(result)
+    }
+
+    /// One entry per descriptor set index from 0 up to the highest set used, so the result
+    /// can be turned into the `set_layouts` of a pipeline layout directly.
+    pub fn descriptor_set_layout_bindings(&self) -> Vec<Vec<vk::DescriptorSetLayoutBinding>> {
+        let set_count = self.descriptor_bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0);
+        (0..set_count).map(|set| {
+            self.descriptor_bindings.iter().filter(|binding| binding.set == set).map(|binding| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+                    .stage_flags(binding.stage_flags)
+                    .build()
+            }).collect()
+        }).collect()
+    }
+
+    /// Vertex inputs as a single interleaved binding 0, in location order.
+    pub fn vertex_input_descriptions(&self) -> (Vec<vk::VertexInputBindingDescription>, Vec<vk::VertexInputAttributeDescription>) {
+        let mut attributes = Vec::new();
+        let mut offset = 0;
+        for input in &self.vertex_inputs {
+            for column in 0..input.location_count {
+                attributes.push(vk::VertexInputAttributeDescription::builder()
+                    .binding(0)
+                    .location(input.location + column)
+                    .format(input.format)
+                    .offset(offset)
+                    .build());
+                offset += format_size(input.format);
+            }
+        }
+        let bindings = if attributes.is_empty() {
+            vec![]
+        } else {
+            vec![vk::VertexInputBindingDescription::builder()
+                .binding(0)
+                .stride(offset)
+                .input_rate(vk::VertexInputRate::VERTEX)
+                .build()]
+        };
+        (bindings, attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module header followed by `instructions`.
+    fn module(instructions: &[u32]) -> Vec<u32> {
+        let mut words = vec![SPIRV_MAGIC, 0x0001_0000, 0, 16, 0];
+        words.extend_from_slice(instructions);
+        words
+    }
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    const VERTEX: u32 = 0;
+    const FRAGMENT: u32 = 4;
+    // ids of the types `types` declares
+    const FLOAT: u32 = 1;
+    const VEC2: u32 = 2;
+    const VEC3: u32 = 3;
+    const VEC4: u32 = 4;
+    const MAT4: u32 = 5;
+    const INT: u32 = 6;
+    const SAMPLED_IMAGE: u32 = 8;
+    const FOUR: u32 = 9;
+
+    /// A module of `model` with an entry point called "main".
+    fn shader(model: u32, instructions: &[Vec<u32>]) -> ShaderReflection {
+        let mut words = instruction(OP_ENTRY_POINT, &[model, 100, u32::from_le_bytes(*b"main"), 0]);
+        words.extend(types());
+        words.extend(instructions.concat());
+        ShaderReflection::reflect(&module(&words)).unwrap()
+    }
+
+    fn types() -> Vec<u32> {
+        [
+            instruction(OP_TYPE_FLOAT, &[FLOAT, 32]),
+            instruction(OP_TYPE_VECTOR, &[VEC2, FLOAT, 2]),
+            instruction(OP_TYPE_VECTOR, &[VEC3, FLOAT, 3]),
+            instruction(OP_TYPE_VECTOR, &[VEC4, FLOAT, 4]),
+            instruction(OP_TYPE_MATRIX, &[MAT4, VEC4, 4]),
+            instruction(OP_TYPE_INT, &[INT, 32, 1]),
+            instruction(OP_TYPE_IMAGE, &[7, FLOAT, 1, 0, 0, 0, 1, 0]),
+            instruction(OP_TYPE_SAMPLED_IMAGE, &[SAMPLED_IMAGE, 7]),
+            instruction(OP_CONSTANT, &[INT, FOUR, 4]),
+        ].concat()
+    }
+
+    /// A variable `id` of `type_id`, through a pointer type with the id `id + 1000`.
+    fn variable(id: u32, storage_class: u32, type_id: u32) -> Vec<u32> {
+        [
+            instruction(OP_TYPE_POINTER, &[id + 1000, storage_class, type_id]),
+            instruction(OP_VARIABLE, &[id + 1000, id, storage_class]),
+        ].concat()
+    }
+
+    fn interface(id: u32, storage_class: u32, type_id: u32, location: u32) -> Vec<u32> {
+        [variable(id, storage_class, type_id), instruction(OP_DECORATE, &[id, DECORATION_LOCATION, location])].concat()
+    }
+
+    fn descriptor(id: u32, storage_class: u32, type_id: u32, set: u32, binding: u32) -> Vec<u32> {
+        [
+            variable(id, storage_class, type_id),
+            instruction(OP_DECORATE, &[id, DECORATION_DESCRIPTOR_SET, set]),
+            instruction(OP_DECORATE, &[id, DECORATION_BINDING, binding]),
+        ].concat()
+    }
+
+    /// A struct `id` with members at the given offsets.
+    fn block(id: u32, members: &[(u32, u32)]) -> Vec<u32> {
+        let mut operands = vec![id];
+        operands.extend(members.iter().map(|(type_id, _)| *type_id));
+        let mut words = instruction(OP_TYPE_STRUCT, &operands);
+        for (idx, (_, offset)) in members.iter().enumerate() {
+            words.extend(instruction(OP_MEMBER_DECORATE, &[id, idx as u32, DECORATION_OFFSET, *offset]));
+        }
+        words
+    }
+
+    #[test]
+    fn instructions_with_too_few_operands_are_truncated() {
+        for (opcode, operands) in &[(OP_NAME, vec![1]), (OP_DECORATE, vec![1]), (OP_MEMBER_DECORATE, vec![1, 0]), (OP_TYPE_IMAGE, vec![1, 2, 1]), (OP_VARIABLE, vec![])] {
+            let words = module(&instruction(*opcode, operands));
+            assert!(matches!(ShaderReflection::reflect(&words), Err(ReflectionError::Truncated(5))), "opcode {}", opcode);
+        }
+    }
+
+    #[test]
+    fn instruction_past_the_end_is_truncated() {
+        let mut words = module(&instruction(OP_NAME, &[1, 0]));
+        words.pop();
+        assert!(matches!(ShaderReflection::reflect(&words), Err(ReflectionError::Truncated(5))));
+    }
+
+    #[test]
+    fn missing_and_mismatched_inputs() {
+        let vertex = shader(VERTEX, &[
+            interface(20, STORAGE_CLASS_OUTPUT, VEC3, 0),
+            interface(21, STORAGE_CLASS_OUTPUT, VEC2, 1),
+        ]);
+        let fragment = shader(FRAGMENT, &[
+            interface(30, STORAGE_CLASS_INPUT, VEC4, 0),
+            interface(31, STORAGE_CLASS_INPUT, VEC2, 1),
+            interface(32, STORAGE_CLASS_INPUT, FLOAT, 2),
+            instruction(OP_NAME, &[32, u32::from_le_bytes(*b"fog\0")]),
+        ]);
+        // the order of the stages doesn't matter
+        let merged = PipelineReflection::merge(&[&fragment, &vertex]).unwrap();
+        assert_eq!(merged.stages, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(merged.mismatches.len(), 2);
+        assert!(matches!(&merged.mismatches[0], InterfaceMismatch::FormatMismatch {
+            stage: vk::ShaderStageFlags::FRAGMENT,
+            location: 0,
+            output_format: vk::Format::R32G32B32_SFLOAT,
+            input_format: vk::Format::R32G32B32A32_SFLOAT,
+            ..
+        }));
+        assert!(matches!(&merged.mismatches[1], InterfaceMismatch::MissingOutput { location: 2, name, .. } if name == "fog"));
+    }
+
+    #[test]
+    fn shared_descriptors_have_to_agree() {
+        let uniforms = block(40, &[(MAT4, 0)]);
+        let vertex = shader(VERTEX, &[uniforms.clone(), descriptor(20, STORAGE_CLASS_UNIFORM, 40, 0, 0)]);
+        let fragment = shader(FRAGMENT, &[uniforms, descriptor(30, STORAGE_CLASS_UNIFORM, 40, 0, 0)]);
+        let merged = PipelineReflection::merge(&[&vertex, &fragment]).unwrap();
+        assert_eq!(merged.descriptor_bindings.len(), 1);
+        assert_eq!(merged.descriptor_bindings[0].descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+        assert_eq!(merged.descriptor_bindings[0].stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+
+        let sampler = shader(FRAGMENT, &[descriptor(30, STORAGE_CLASS_UNIFORM_CONSTANT, SAMPLED_IMAGE, 0, 0)]);
+        let result = PipelineReflection::merge(&[&vertex, &sampler]);
+        assert!(matches!(result, Err(ReflectionError::DescriptorConflict { set: 0, binding: 0 })));
+        // the same type, but a different count
+        let textures = shader(FRAGMENT, &[instruction(OP_TYPE_ARRAY, &[41, SAMPLED_IMAGE, FOUR]), descriptor(30, STORAGE_CLASS_UNIFORM_CONSTANT, 41, 0, 0)]);
+        let result = PipelineReflection::merge(&[&sampler, &textures]);
+        assert!(matches!(result, Err(ReflectionError::DescriptorConflict { set: 0, binding: 0 })));
+    }
+
+    #[test]
+    fn push_constant_ranges_are_merged() {
+        let matrix = block(40, &[(MAT4, 0)]);
+        let vertex = shader(VERTEX, &[matrix.clone(), variable(20, STORAGE_CLASS_PUSH_CONSTANT, 40)]);
+        let fragment = shader(FRAGMENT, &[matrix, variable(30, STORAGE_CLASS_PUSH_CONSTANT, 40)]);
+        assert_eq!((vertex.push_constants[0].offset, vertex.push_constants[0].size), (0, 64));
+        let merged = PipelineReflection::merge(&[&vertex, &fragment]).unwrap();
+        let ranges: Vec<_> = merged.push_constant_ranges.iter().map(|range| (range.stage_flags, range.offset, range.size)).collect();
+        assert_eq!(ranges, vec![(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 0, 64)]);
+
+        // a block that only declares the members after the vertex shader's
+        let color = shader(FRAGMENT, &[block(40, &[(VEC4, 64)]), variable(30, STORAGE_CLASS_PUSH_CONSTANT, 40)]);
+        let merged = PipelineReflection::merge(&[&vertex, &color]).unwrap();
+        let ranges: Vec<_> = merged.push_constant_ranges.iter().map(|range| (range.stage_flags, range.offset, range.size)).collect();
+        assert_eq!(ranges, vec![(vk::ShaderStageFlags::VERTEX, 0, 64), (vk::ShaderStageFlags::FRAGMENT, 64, 16)]);
+    }
+
+    #[test]
+    fn vertex_inputs_are_interleaved() {
+        let vertex = shader(VERTEX, &[
+            interface(22, STORAGE_CLASS_INPUT, VEC2, 5),
+            interface(20, STORAGE_CLASS_INPUT, VEC3, 0),
+            // a matrix takes one location per column
+            interface(21, STORAGE_CLASS_INPUT, MAT4, 1),
+        ]);
+        let (bindings, attributes) = PipelineReflection::merge(&[&vertex]).unwrap().vertex_input_descriptions();
+        assert_eq!(bindings.len(), 1);
+        assert_eq!((bindings[0].binding, bindings[0].stride, bindings[0].input_rate), (0, 84, vk::VertexInputRate::VERTEX));
+        let attributes: Vec<_> = attributes.iter().map(|attribute| (attribute.location, attribute.format, attribute.offset)).collect();
+        assert_eq!(attributes, vec![
+            (0, vk::Format::R32G32B32_SFLOAT, 0),
+            (1, vk::Format::R32G32B32A32_SFLOAT, 12),
+            (2, vk::Format::R32G32B32A32_SFLOAT, 28),
+            (3, vk::Format::R32G32B32A32_SFLOAT, 44),
+            (4, vk::Format::R32G32B32A32_SFLOAT, 60),
+            (5, vk::Format::R32G32_SFLOAT, 76),
+        ]);
+
+        let (bindings, attributes) = PipelineReflection::merge(&[&shader(VERTEX, &[])]).unwrap().vertex_input_descriptions();
+        assert!(bindings.is_empty() && attributes.is_empty());
+    }
+
+    #[test]
+    fn set_layouts_fill_unused_sets() {
+        let fragment = shader(FRAGMENT, &[
+            instruction(OP_TYPE_ARRAY, &[41, SAMPLED_IMAGE, FOUR]),
+            descriptor(30, STORAGE_CLASS_UNIFORM_CONSTANT, 41, 2, 0),
+            descriptor(31, STORAGE_CLASS_UNIFORM_CONSTANT, SAMPLED_IMAGE, 0, 1),
+        ]);
+        let sets = PipelineReflection::merge(&[&fragment]).unwrap().descriptor_set_layout_bindings();
+        let sets: Vec<Vec<_>> = sets.iter().map(|bindings| {
+            bindings.iter().map(|binding| (binding.binding, binding.descriptor_type, binding.descriptor_count, binding.stage_flags)).collect()
+        }).collect();
+        assert_eq!(sets, vec![
+            vec![(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1, vk::ShaderStageFlags::FRAGMENT)],
+            vec![],
+            vec![(0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4, vk::ShaderStageFlags::FRAGMENT)],
+        ]);
+        assert!(PipelineReflection::default().descriptor_set_layout_bindings().is_empty());
+    }
+
+    #[test]
+    fn types_containing_themselves_dont_overflow() {
+        let vertex = shader(VERTEX, &[
+            instruction(OP_TYPE_STRUCT, &[40, VEC4, 40]),
+            instruction(OP_TYPE_ARRAY, &[41, 41, FOUR]),
+            variable(20, STORAGE_CLASS_PUSH_CONSTANT, 40),
+            interface(21, STORAGE_CLASS_INPUT, 41, 0),
+            descriptor(22, STORAGE_CLASS_UNIFORM_CONSTANT, 41, 0, 0),
+        ]);
+        assert_eq!(vertex.push_constants[0].size, 16);
+        assert_eq!(vertex.inputs[0].format, vk::Format::UNDEFINED);
+        assert!(vertex.descriptor_bindings.is_empty());
+    }
+}
@@ -0,0 +1,1056 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::debug;
+use std::collections::HashMap;
+
+use crate::{
+    buffer::find_memory_type,
+    image::aspect_flags,
+    profiler::Profiler,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PassHandle(usize);
+
+#[derive(Debug)]
+pub enum RenderGraphError {
+    /// The attachments of a pass differ in size.
+    AttachmentExtents { pass: String },
+    /// A pass has more resolve than color attachments.
+    ResolveAttachments { pass: String },
+}
+impl std::error::Error for RenderGraphError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for RenderGraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderGraphError::AttachmentExtents { pass } => write!(f, "Pass {}: attachments of different sizes", pass),
+            RenderGraphError::ResolveAttachments { pass } => write!(f, "Pass {}: more resolve than color attachments", pass),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImageInfo {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+}
+
+impl ImageInfo {
+    pub fn new(format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self {
+            format,
+            extent,
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+}
+
+/// What happens to the contents of an attachment at the start of a pass.
+#[derive(Clone, Copy)]
+pub enum AttachmentLoad {
+    Clear(vk::ClearValue),
+    Load,
+    DontCare,
+}
+
+struct ImageResource {
+    name: String,
+    info: ImageInfo,
+    /// Initial and final layout of images owned by someone else, like the swapchain images.
+    imported: Option<(vk::ImageLayout, vk::ImageLayout)>,
+    usage: vk::ImageUsageFlags,
+}
+
+#[derive(Clone, Copy)]
+struct Access {
+    stage: vk::PipelineStageFlags,
+    access: vk::AccessFlags,
+    reads: bool,
+    writes: bool,
+}
+
+struct ImageUse {
+    image: ImageHandle,
+    layout: vk::ImageLayout,
+    access: Access,
+    /// Label in the Graphviz output.
+    kind: &'static str,
+}
+
+struct BufferUse {
+    buffer: BufferHandle,
+    access: Access,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AttachmentKind {
+    Color,
+    Depth,
+    Resolve,
+}
+
+struct Attachment {
+    image: ImageHandle,
+    kind: AttachmentKind,
+    load: AttachmentLoad,
+}
+
+struct Pass {
+    name: String,
+    images: Vec<ImageUse>,
+    buffers: Vec<BufferUse>,
+    attachments: Vec<Attachment>,
+    side_effects: bool,
+    secondary_command_buffers: bool,
+}
+
+/// Synchronization state of a resource between passes.
+#[derive(Clone, Copy)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    /// Stage and access of the last write, or of the last layout transition.
+    write: Option<(vk::PipelineStageFlags, vk::AccessFlags)>,
+    /// Stages that read since the last write, a following write has to wait for them.
+    read_stages: vk::PipelineStageFlags,
+    /// Stages that already waited for the last write.
+    synced_stages: vk::PipelineStageFlags,
+}
+
+impl ResourceState {
+    /// Imported resources may have been used by earlier submissions, so their first use waits for all earlier commands.
+    fn imported(layout: vk::ImageLayout) -> Self {
+        Self {
+            layout,
+            write: Some((vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::MEMORY_WRITE)),
+            read_stages: vk::PipelineStageFlags::empty(),
+            synced_stages: vk::PipelineStageFlags::empty(),
+        }
+    }
+
+    /// Transient images are shared by the frames in flight, so the first use waits for the previous frame.
+    fn transient() -> Self {
+        Self::imported(vk::ImageLayout::UNDEFINED)
+    }
+
+    /// Stages and accesses a write or layout transition has to wait for.
+    fn pending(&self) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+        let (stage, access) = self.write.unwrap_or((vk::PipelineStageFlags::empty(), vk::AccessFlags::empty()));
+        (stage | self.read_stages, access)
+    }
+}
+
+struct ImageBarrier {
+    image: ImageHandle,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+}
+
+/// All barriers before one pass, recorded as a single `vkCmdPipelineBarrier`.
+#[derive(Default)]
+struct Barriers {
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    /// Buffers are synchronized with a global memory barrier, so they don't have to be bound.
+    memory_src_access: vk::AccessFlags,
+    memory_dst_access: vk::AccessFlags,
+    images: Vec<ImageBarrier>,
+}
+
+impl Barriers {
+    fn is_empty(&self) -> bool {
+        self.dst_stage.is_empty()
+    }
+
+    fn add(&mut self, (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags), access: &Access) {
+        self.src_stage |= if src_stage.is_empty() { vk::PipelineStageFlags::TOP_OF_PIPE } else { src_stage };
+        self.dst_stage |= access.stage;
+        self.memory_src_access |= src_access;
+        self.memory_dst_access |= access.access;
+    }
+
+    fn add_image(&mut self, image: ImageHandle, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout, (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags), access: &Access) {
+        self.src_stage |= if src_stage.is_empty() { vk::PipelineStageFlags::TOP_OF_PIPE } else { src_stage };
+        self.dst_stage |= access.stage;
+        self.images.push(ImageBarrier { image, old_layout, new_layout, src_access, dst_access: access.access });
+    }
+
+    fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, resolve: &dyn Fn(ImageHandle) -> (vk::Image, vk::ImageSubresourceRange)) {
+        if self.is_empty() {
+            return;
+        }
+        let memory_barriers: Vec<vk::MemoryBarrier> = if self.memory_dst_access.is_empty() && self.memory_src_access.is_empty() {
+            vec![]
+        } else {
+            vec![vk::MemoryBarrier::builder()
+                .src_access_mask(self.memory_src_access)
+                .dst_access_mask(self.memory_dst_access)
+                .build()]
+        };
+        let image_barriers: Vec<vk::ImageMemoryBarrier> = self.images.iter().map(|barrier| {
+            let (image, subresource_range) = resolve(barrier.image);
+            vk::ImageMemoryBarrier::builder()
+                .old_layout(barrier.old_layout)
+                .new_layout(barrier.new_layout)
+                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                .image(image)
+                .subresource_range(subresource_range)
+                .src_access_mask(barrier.src_access)
+                .dst_access_mask(barrier.dst_access)
+                .build()
+        }).collect();
+        unsafe { device.cmd_pipeline_barrier(command_buffer, self.src_stage, self.dst_stage, vk::DependencyFlags::empty(), &memory_barriers, &[], &image_barriers) };
+    }
+}
+
+/// A pass as it is executed.
+struct CompiledPass {
+    pass: usize,
+    barriers: Barriers,
+    render_pass: vk::RenderPass,
+    clear_values: Vec<vk::ClearValue>,
+}
+
+/// A frame described as passes and the images and buffers they read and write.
+/// Compiling it
+/// - orders the passes so every pass runs after the ones whose results it uses,
+///   keeping the declaration order for independent ones,
+/// - culls passes whose results aren't used by an imported resource,
+/// - derives the barriers and layout transitions between the passes,
+/// - creates the transient images, where ones that are never in use at the same
+///   time share memory, and
+/// - creates a render pass for every pass with attachments.
+///
+/// Passes don't own their commands: `execute` calls back with the handle of each pass to
+/// record it. Buffers and imported images are owned by the caller, imported images are
+/// bound to the graph when it is executed.
+#[derive(Default)]
+pub struct RenderGraph {
+    images: Vec<ImageResource>,
+    buffers: Vec<String>,
+    passes: Vec<Pass>,
+    // compiled state
+    culled: Vec<bool>,
+    compiled: Vec<CompiledPass>,
+    final_barriers: Barriers,
+    transient_images: HashMap<ImageHandle, (vk::Image, vk::ImageView)>,
+    memory: Vec<vk::DeviceMemory>,
+    framebuffers: HashMap<(usize, Vec<vk::ImageView>), vk::Framebuffer>,
+}
+
+/// Declares what a pass accesses, see `RenderGraph::add_pass`.
+pub struct PassBuilder<'a> {
+    graph: &'a mut RenderGraph,
+    pass: usize,
+}
+
+impl<'a> PassBuilder<'a> {
+    fn image(self, image: ImageHandle, layout: vk::ImageLayout, usage: vk::ImageUsageFlags, kind: &'static str, access: Access) -> Self {
+        self.graph.images[image.0].usage |= usage;
+        self.graph.passes[self.pass].images.push(ImageUse { image, layout, access, kind });
+        self
+    }
+
+    fn attachment(self, image: ImageHandle, kind: AttachmentKind, load: AttachmentLoad) -> Self {
+        self.graph.passes[self.pass].attachments.push(Attachment { image, kind, load });
+        self
+    }
+
+    pub fn color_attachment(self, image: ImageHandle, load: AttachmentLoad) -> Self {
+        let reads = matches!(load, AttachmentLoad::Load);
+        self.image(image, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT, "color", Access {
+            stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access: vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            reads,
+            writes: true,
+        }).attachment(image, AttachmentKind::Color, load)
+    }
+
+    pub fn depth_attachment(self, image: ImageHandle, load: AttachmentLoad) -> Self {
+        let reads = matches!(load, AttachmentLoad::Load);
+        self.image(image, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL, vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, "depth", Access {
+            stage: vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            access: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            reads,
+            writes: true,
+        }).attachment(image, AttachmentKind::Depth, load)
+    }
+
+    /// The color attachments are resolved to the resolve attachments in the order both are added.
+    pub fn resolve_attachment(self, image: ImageHandle) -> Self {
+        self.image(image, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL, vk::ImageUsageFlags::COLOR_ATTACHMENT, "resolve", Access {
+            stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            access: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            reads: false,
+            writes: true,
+        }).attachment(image, AttachmentKind::Resolve, AttachmentLoad::DontCare)
+    }
+
+    pub fn sampled_image(self, image: ImageHandle, stage: vk::PipelineStageFlags) -> Self {
+        self.image(image, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageUsageFlags::SAMPLED, "sampled", Access {
+            stage,
+            access: vk::AccessFlags::SHADER_READ,
+            reads: true,
+            writes: false,
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn storage_image(self, image: ImageHandle, stage: vk::PipelineStageFlags, writes: bool) -> Self {
+        self.image(image, vk::ImageLayout::GENERAL, vk::ImageUsageFlags::STORAGE, "storage", Access {
+            stage,
+            access: if writes { vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE } else { vk::AccessFlags::SHADER_READ },
+            reads: true,
+            writes,
+        })
+    }
+
+    pub fn transfer_source(self, image: ImageHandle) -> Self {
+        self.image(image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageUsageFlags::TRANSFER_SRC, "transfer", Access {
+            stage: vk::PipelineStageFlags::TRANSFER,
+            access: vk::AccessFlags::TRANSFER_READ,
+            reads: true,
+            writes: false,
+        })
+    }
+
+    /// The whole image is overwritten, its previous contents are discarded.
+    pub fn transfer_destination(self, image: ImageHandle) -> Self {
+        self.image(image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageUsageFlags::TRANSFER_DST, "transfer", Access {
+            stage: vk::PipelineStageFlags::TRANSFER,
+            access: vk::AccessFlags::TRANSFER_WRITE,
+            reads: false,
+            writes: true,
+        })
+    }
+
+    fn buffer(self, buffer: BufferHandle, access: Access) -> Self {
+        let uses = &mut self.graph.passes[self.pass].buffers;
+        // reading and writing the same buffer is one access
+        match uses.iter_mut().find(|buffer_use| buffer_use.buffer == buffer) {
+            Some(buffer_use) => {
+                buffer_use.access.stage |= access.stage;
+                buffer_use.access.access |= access.access;
+                buffer_use.access.reads |= access.reads;
+                buffer_use.access.writes |= access.writes;
+            }
+            None => uses.push(BufferUse { buffer, access }),
+        }
+        self
+    }
+
+    pub fn read_buffer(self, buffer: BufferHandle, stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        self.buffer(buffer, Access { stage, access, reads: true, writes: false })
+    }
+
+    pub fn write_buffer(self, buffer: BufferHandle, stage: vk::PipelineStageFlags, access: vk::AccessFlags) -> Self {
+        self.buffer(buffer, Access { stage, access, reads: false, writes: true })
+    }
+
+    /// Keeps the pass even if nothing uses its results.
+    #[allow(dead_code)]
+    pub fn side_effects(self) -> Self {
+        self.graph.passes[self.pass].side_effects = true;
+        self
+    }
+
+    /// Begins the render pass with `SECONDARY_COMMAND_BUFFERS` contents, so everything recorded
+    /// inside it has to be in secondary command buffers executed by the primary one.
+    pub fn secondary_command_buffers(self) -> Self {
+        self.graph.passes[self.pass].secondary_command_buffers = true;
+        self
+    }
+
+    pub fn handle(&self) -> PassHandle {
+        PassHandle(self.pass)
+    }
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An image created and owned by the graph, which only lives for the frame.
+    pub fn create_image(&mut self, name: &str, info: ImageInfo) -> ImageHandle {
+        self.images.push(ImageResource { name: name.to_owned(), info, imported: None, usage: vk::ImageUsageFlags::empty() });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    /// An image owned by the caller. It is expected in `initial_layout` and left in `final_layout`.
+    pub fn import_image(&mut self, name: &str, info: ImageInfo, initial_layout: vk::ImageLayout, final_layout: vk::ImageLayout) -> ImageHandle {
+        self.images.push(ImageResource { name: name.to_owned(), info, imported: Some((initial_layout, final_layout)), usage: vk::ImageUsageFlags::empty() });
+        ImageHandle(self.images.len() - 1)
+    }
+
+    pub fn import_buffer(&mut self, name: &str) -> BufferHandle {
+        self.buffers.push(name.to_owned());
+        BufferHandle(self.buffers.len() - 1)
+    }
+
+    /// Adds a pass, in the order it would run without dependencies between the passes.
+    pub fn add_pass(&mut self, name: &str) -> PassBuilder<'_> {
+        self.passes.push(Pass { name: name.to_owned(), images: Vec::new(), buffers: Vec::new(), attachments: Vec::new(), side_effects: false, secondary_command_buffers: false });
+        PassBuilder { pass: self.passes.len() - 1, graph: self }
+    }
+
+    /// The render pass of a pass with attachments, for creating pipelines. `None` for passes
+    /// without attachments and culled ones. Only valid after compiling.
+    pub fn render_pass(&self, pass: PassHandle) -> Option<vk::RenderPass> {
+        self.compiled.iter().find(|compiled| compiled.pass == pass.0).map(|compiled| compiled.render_pass).filter(|render_pass| *render_pass != vk::RenderPass::null())
+    }
+
+    /// Changes what an attachment of a pass is cleared to, for the following executions.
+    /// Does nothing if the attachment isn't cleared.
+    pub fn set_clear_value(&mut self, pass: PassHandle, image: ImageHandle, clear_value: vk::ClearValue) {
+        let attachments = &self.passes[pass.0].attachments;
+        if let Some(compiled) = self.compiled.iter_mut().find(|compiled| compiled.pass == pass.0) {
+            for (idx, attachment) in attachments.iter().enumerate() {
+                if attachment.image == image && matches!(attachment.load, AttachmentLoad::Clear(_)) {
+                    compiled.clear_values[idx] = clear_value;
+                }
+            }
+        }
+    }
+
+    /// Image and view of a transient image. `None` for imported images and ones no pass uses.
+    /// Only valid after compiling.
+    pub fn transient_image(&self, image: ImageHandle) -> Option<(vk::Image, vk::ImageView)> {
+        self.transient_images.get(&image).cloned()
+    }
+
+    /// Dependencies between the passes: `(from, to, needed)`, where `needed` means `to`
+    /// uses the results of `from`, otherwise it only must not run before it.
+    fn dependencies(&self) -> Vec<(usize, usize, bool)> {
+        #[derive(Default, Clone)]
+        struct Tracker {
+            writer: Option<usize>,
+            readers: Vec<usize>,
+        }
+        // images first, followed by the buffers
+        let mut trackers = vec![Tracker::default(); self.images.len() + self.buffers.len()];
+        let mut dependencies = Vec::new();
+        for (idx, pass) in self.passes.iter().enumerate() {
+            let uses = pass.images.iter().map(|image_use| (image_use.image.0, image_use.access))
+                .chain(pass.buffers.iter().map(|buffer_use| (self.images.len() + buffer_use.buffer.0, buffer_use.access)));
+            for (resource, access) in uses {
+                let tracker = &mut trackers[resource];
+                if access.reads {
+                    if let Some(writer) = tracker.writer {
+                        dependencies.push((writer, idx, true));
+                    }
+                }
+                if access.writes {
+                    for reader in tracker.readers.drain(..).filter(|reader| *reader != idx) {
+                        dependencies.push((reader, idx, false));
+                    }
+                    if let Some(writer) = tracker.writer.filter(|writer| *writer != idx) {
+                        dependencies.push((writer, idx, false));
+                    }
+                    tracker.writer = Some(idx);
+                } else {
+                    tracker.readers.push(idx);
+                }
+            }
+        }
+        dependencies
+    }
+
+    /// Passes that write imported resources or have side effects, and everything they use.
+    fn cull(&self, dependencies: &[(usize, usize, bool)]) -> Vec<bool> {
+        let mut needed: Vec<bool> = self.passes.iter().map(|pass| {
+            pass.side_effects
+                || pass.buffers.iter().any(|buffer_use| buffer_use.access.writes)
+                || pass.images.iter().any(|image_use| image_use.access.writes && self.images[image_use.image.0].imported.is_some())
+        }).collect();
+        // dependencies always point forward, so one backward sweep reaches everything
+        for idx in (0..self.passes.len()).rev() {
+            if needed[idx] {
+                for (from, _, _) in dependencies.iter().filter(|(_, to, needed)| *to == idx && *needed) {
+                    needed[*from] = true;
+                }
+            }
+        }
+        needed.into_iter().map(|needed| !needed).collect()
+    }
+
+    /// Topological order of the passes that aren't culled, preferring declaration order.
+    fn order(&self, dependencies: &[(usize, usize, bool)]) -> Vec<usize> {
+        let mut remaining: Vec<usize> = vec![0; self.passes.len()];
+        for (from, to, _) in dependencies {
+            if !self.culled[*from] && !self.culled[*to] {
+                remaining[*to] += 1;
+            }
+        }
+        let mut scheduled = vec![false; self.passes.len()];
+        let mut order = Vec::new();
+        while let Some(next) = (0..self.passes.len()).find(|idx| !self.culled[*idx] && !scheduled[*idx] && remaining[*idx] == 0) {
+            scheduled[next] = true;
+            order.push(next);
+            for (_, to, _) in dependencies.iter().filter(|(from, _, _)| *from == next) {
+                remaining[*to] = remaining[*to].saturating_sub(1);
+            }
+        }
+        order
+    }
+
+    /// Orders the passes, derives the barriers and creates the transient images and render passes.
+    pub fn compile(&mut self, device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties) -> Result<(), Box<dyn std::error::Error>> {
+        let dependencies = self.dependencies();
+        self.culled = self.cull(&dependencies);
+        let order = self.order(&dependencies);
+        for (pass, culled) in self.passes.iter().zip(&self.culled) {
+            if *culled {
+                debug!("Render graph: culled pass {}", pass.name);
+            }
+        }
+
+        // lifetimes of the transient images, as positions in the order
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.images.len()];
+        for (position, pass) in order.iter().enumerate() {
+            for image_use in &self.passes[*pass].images {
+                let lifetime = &mut lifetimes[image_use.image.0];
+                *lifetime = Some(lifetime.map_or((position, position), |(first, _)| (first, position)));
+            }
+        }
+        let aliases = self.create_transient_images(device, memory_properties, &lifetimes)?;
+
+        let mut states: Vec<ResourceState> = self.images.iter().map(|image| match image.imported {
+            Some((initial_layout, _)) => ResourceState::imported(initial_layout),
+            None => ResourceState::transient(),
+        }).collect();
+        let mut buffer_states = vec![ResourceState::imported(vk::ImageLayout::UNDEFINED); self.buffers.len()];
+        for (position, pass_idx) in order.iter().enumerate() {
+            let pass = &self.passes[*pass_idx];
+            let mut barriers = Barriers::default();
+            for image_use in &pass.images {
+                let image = image_use.image;
+                if lifetimes[image.0].map(|(first, _)| first) == Some(position) {
+                    // an image sharing memory with an earlier one has to wait until that one is done
+                    if let Some(previous) = aliases.get(&image) {
+                        let previous = states[previous.0];
+                        states[image.0].write = previous.write;
+                        states[image.0].read_stages = previous.read_stages;
+                    }
+                }
+                let state = &mut states[image.0];
+                let access = &image_use.access;
+                if state.layout != image_use.layout || access.writes {
+                    let old_layout = if access.reads { state.layout } else { vk::ImageLayout::UNDEFINED };
+                    let (src_stage, src_access) = state.pending();
+                    if state.layout != image_use.layout || !src_stage.is_empty() {
+                        barriers.add_image(image, old_layout, image_use.layout, (src_stage, src_access), access);
+                    }
+                    *state = ResourceState {
+                        layout: image_use.layout,
+                        write: Some((access.stage, if access.writes { access.access } else { vk::AccessFlags::empty() })),
+                        read_stages: if access.writes { vk::PipelineStageFlags::empty() } else { access.stage },
+                        synced_stages: access.stage,
+                    };
+                } else {
+                    if let Some(write) = state.write {
+                        if !state.synced_stages.contains(access.stage) {
+                            barriers.add_image(image, state.layout, state.layout, write, access);
+                            state.synced_stages |= access.stage;
+                        }
+                    }
+                    state.read_stages |= access.stage;
+                }
+            }
+            for buffer_use in &pass.buffers {
+                let state = &mut buffer_states[buffer_use.buffer.0];
+                let access = &buffer_use.access;
+                if access.writes {
+                    let pending = state.pending();
+                    if !pending.0.is_empty() {
+                        barriers.add(pending, access);
+                    }
+                    state.write = Some((access.stage, access.access));
+                    state.read_stages = vk::PipelineStageFlags::empty();
+                    state.synced_stages = vk::PipelineStageFlags::empty();
+                } else {
+                    if let Some(write) = state.write {
+                        if !state.synced_stages.contains(access.stage) {
+                            barriers.add(write, access);
+                            state.synced_stages |= access.stage;
+                        }
+                    }
+                    state.read_stages |= access.stage;
+                }
+            }
+            let (render_pass, clear_values) = if pass.attachments.is_empty() {
+                (vk::RenderPass::null(), vec![])
+            } else {
+                self.create_render_pass(device, pass, &order[position + 1..])?
+            };
+            self.compiled.push(CompiledPass { pass: *pass_idx, barriers, render_pass, clear_values });
+        }
+
+        for (idx, image) in self.images.iter().enumerate() {
+            if let Some((_, final_layout)) = image.imported {
+                let state = &states[idx];
+                if state.layout != final_layout && final_layout != vk::ImageLayout::UNDEFINED {
+                    let access = Access { stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE, access: vk::AccessFlags::empty(), reads: true, writes: false };
+                    self.final_barriers.add_image(ImageHandle(idx), state.layout, final_layout, state.pending(), &access);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the transient images that are used by some pass. Images are placed in the memory
+    /// of an earlier one when their lifetimes don't overlap. Returns which image each one replaces.
+    fn create_transient_images(&mut self, device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, lifetimes: &[Option<(usize, usize)>]) -> Result<HashMap<ImageHandle, ImageHandle>, Box<dyn std::error::Error>> {
+        struct Slot {
+            memory_type: u32,
+            size: vk::DeviceSize,
+            occupant: ImageHandle,
+            images: Vec<vk::Image>,
+        }
+        let mut transients: Vec<(usize, (usize, usize))> = lifetimes.iter().enumerate()
+            .filter(|(idx, _)| self.images[*idx].imported.is_none())
+            .filter_map(|(idx, lifetime)| lifetime.map(|lifetime| (idx, lifetime)))
+            .collect();
+        transients.sort_by_key(|(_, (first, _))| *first);
+
+        let mut created = Vec::new();
+        for (idx, _) in &transients {
+            let resource = &self.images[*idx];
+            let mut usage = resource.usage;
+            // images only used as attachments never have to be in memory as a whole
+            if (usage & !(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT)).is_empty() {
+                usage |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+            }
+            let image_info = vk::ImageCreateInfo::builder()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(vk::Extent3D { width: resource.info.extent.width, height: resource.info.extent.height, depth: 1 })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(resource.info.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .usage(usage)
+                .samples(resource.info.samples)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE);
+            let image = unsafe { device.create_image(&image_info, None) }?;
+            // registered right away so `destroy` cleans up after errors
+            self.transient_images.insert(ImageHandle(*idx), (image, vk::ImageView::null()));
+            let requirements = unsafe { device.get_image_memory_requirements(image) };
+            let memory_type = find_memory_type(memory_properties, requirements.memory_type_bits, vk::MemoryPropertyFlags::DEVICE_LOCAL)?;
+            created.push((image, requirements.size, memory_type));
+        }
+
+        let placement: Vec<(usize, usize, u32)> = transients.iter().zip(&created)
+            .map(|((_, (first, last)), (_, _, memory_type))| (*first, *last, *memory_type))
+            .collect();
+        let mut slots: Vec<Slot> = Vec::new();
+        let mut aliases = HashMap::new();
+        for (((idx, _), (image, size, memory_type)), slot_idx) in transients.iter().zip(created).zip(assign_memory_slots(&placement)) {
+            match slots.get_mut(slot_idx) {
+                Some(slot) => {
+                    debug!("Render graph: {} shares memory with {}", self.images[*idx].name, self.images[slot.occupant.0].name);
+                    aliases.insert(ImageHandle(*idx), slot.occupant);
+                    slot.size = std::cmp::max(slot.size, size);
+                    slot.occupant = ImageHandle(*idx);
+                    slot.images.push(image);
+                }
+                None => slots.push(Slot { memory_type, size, occupant: ImageHandle(*idx), images: vec![image] }),
+            }
+        }
+
+        for slot in &slots {
+            let alloc_info = vk::MemoryAllocateInfo::builder()
+                .allocation_size(slot.size)
+                .memory_type_index(slot.memory_type);
+            let memory = unsafe { device.allocate_memory(&alloc_info, None) }?;
+            self.memory.push(memory);
+            for image in &slot.images {
+                unsafe { device.bind_image_memory(*image, memory, 0) }?;
+            }
+        }
+        for (handle, (image, view)) in self.transient_images.iter_mut() {
+            let info = &self.images[handle.0].info;
+            let view_info = vk::ImageViewCreateInfo::builder()
+                .image(*image)
+                .view_type(vk::ImageViewType::TYPE_2D)
+                .format(info.format)
+                .subresource_range(vk::ImageSubresourceRange::builder()
+                    .aspect_mask(aspect_flags(info.format))
+                    .base_mip_level(0)
+                    .level_count(1)
+                    .base_array_layer(0)
+                    .layer_count(1)
+                    .build());
+            *view = unsafe { device.create_image_view(&view_info, None) }?;
+        }
+        Ok(aliases)
+    }
+
+    /// A render pass with a single subpass. Layouts don't change within it, the transitions
+    /// are part of the barriers before the pass. Attachments are only stored if a later pass
+    /// reads them or they are imported.
+    fn create_render_pass(&self, device: &ash::Device, pass: &Pass, later: &[usize]) -> Result<(vk::RenderPass, Vec<vk::ClearValue>), Box<dyn std::error::Error>> {
+        let extent = self.images[pass.attachments[0].image.0].info.extent;
+        if pass.attachments.iter().any(|attachment| {
+            let other = self.images[attachment.image.0].info.extent;
+            (other.width, other.height) != (extent.width, extent.height)
+        }) {
+            return Err(RenderGraphError::AttachmentExtents { pass: pass.name.clone() }.into());
+        }
+        let used_later = |image: ImageHandle| self.images[image.0].imported.is_some() || later.iter().any(|idx| {
+            self.passes[*idx].images.iter().any(|image_use| image_use.image == image && image_use.access.reads)
+        });
+
+        let mut descriptions = Vec::new();
+        let mut clear_values = Vec::new();
+        let mut color_refs = Vec::new();
+        let mut depth_ref = None;
+        let mut resolve_refs = Vec::new();
+        for (idx, attachment) in pass.attachments.iter().enumerate() {
+            let info = &self.images[attachment.image.0].info;
+            let layout = match attachment.kind {
+                AttachmentKind::Depth => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                _ => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            };
+            let (load_op, clear_value) = match attachment.load {
+                AttachmentLoad::Clear(clear_value) => (vk::AttachmentLoadOp::CLEAR, clear_value),
+                AttachmentLoad::Load => (vk::AttachmentLoadOp::LOAD, vk::ClearValue::default()),
+                AttachmentLoad::DontCare => (vk::AttachmentLoadOp::DONT_CARE, vk::ClearValue::default()),
+            };
+            let store_op = if used_later(attachment.image) { vk::AttachmentStoreOp::STORE } else { vk::AttachmentStoreOp::DONT_CARE };
+            descriptions.push(vk::AttachmentDescription::builder()
+                .format(info.format)
+                .samples(info.samples)
+                .load_op(load_op)
+                .store_op(store_op)
+                .stencil_load_op(if load_op == vk::AttachmentLoadOp::LOAD { load_op } else { vk::AttachmentLoadOp::DONT_CARE })
+                .stencil_store_op(store_op)
+                .initial_layout(layout)
+                .final_layout(layout)
+                .build());
+            clear_values.push(clear_value);
+            let reference = vk::AttachmentReference::builder()
+                .attachment(idx as u32)
+                .layout(layout)
+                .build();
+            match attachment.kind {
+                AttachmentKind::Color => color_refs.push(reference),
+                AttachmentKind::Depth => depth_ref = Some(reference),
+                AttachmentKind::Resolve => resolve_refs.push(reference),
+            }
+        }
+        if resolve_refs.len() > color_refs.len() {
+            return Err(RenderGraphError::ResolveAttachments { pass: pass.name.clone() }.into());
+        }
+        if !resolve_refs.is_empty() {
+            resolve_refs.resize(color_refs.len(), vk::AttachmentReference::builder().attachment(vk::ATTACHMENT_UNUSED).build());
+        }
+
+        let mut subpass = vk::SubpassDescription::builder()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_refs);
+        if let Some(depth_ref) = &depth_ref {
+            subpass = subpass.depth_stencil_attachment(depth_ref);
+        }
+        if !resolve_refs.is_empty() {
+            subpass = subpass.resolve_attachments(&resolve_refs);
+        }
+        let subpasses = [subpass.build()];
+        let render_pass_info = vk::RenderPassCreateInfo::builder()
+            .attachments(&descriptions)
+            .subpasses(&subpasses);
+        Ok((unsafe { device.create_render_pass(&render_pass_info, None) }?, clear_values))
+    }
+
+    /// Records the compiled graph. `imports` binds every imported image that is used to an
+    /// image and view, `record` is called with each pass in order, inside its render pass
+    /// for passes with attachments. With a `profiler`, every pass is timed in a scope named after
+    /// it, including its barriers.
+    pub fn execute<F: FnMut(PassHandle, vk::CommandBuffer)>(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, imports: &[(ImageHandle, vk::Image, vk::ImageView)], mut profiler: Option<&mut Profiler>, mut record: F) -> Result<(), vk::Result> {
+        let images = &self.images;
+        let transient_images = &self.transient_images;
+        let lookup = |handle: ImageHandle| -> (vk::Image, vk::ImageView) {
+            transient_images.get(&handle).cloned()
+                .or_else(|| imports.iter().find(|(import, ..)| *import == handle).map(|(_, image, view)| (*image, *view)))
+                .unwrap_or_else(|| panic!("Render graph: image {} is not bound", images[handle.0].name))
+        };
+        let resolve = |handle: ImageHandle| {
+            let subresource_range = vk::ImageSubresourceRange::builder()
+                .aspect_mask(aspect_flags(images[handle.0].info.format))
+                .base_mip_level(0)
+                .level_count(vk::REMAINING_MIP_LEVELS)
+                .base_array_layer(0)
+                .layer_count(vk::REMAINING_ARRAY_LAYERS)
+                .build();
+            (lookup(handle).0, subresource_range)
+        };
+
+        for compiled in &self.compiled {
+            let pass = &self.passes[compiled.pass];
+            let scope = profiler.as_mut().map(|profiler| profiler.begin_scope(device, command_buffer, &pass.name));
+            compiled.barriers.record(device, command_buffer, &resolve);
+            if compiled.render_pass == vk::RenderPass::null() {
+                record(PassHandle(compiled.pass), command_buffer);
+            } else {
+                let views: Vec<vk::ImageView> = pass.attachments.iter().map(|attachment| lookup(attachment.image).1).collect();
+                let extent = images[pass.attachments[0].image.0].info.extent;
+                let framebuffer = match self.framebuffers.get(&(compiled.pass, views.clone())) {
+                    Some(framebuffer) => *framebuffer,
+                    None => {
+                        let framebuffer_info = vk::FramebufferCreateInfo::builder()
+                            .render_pass(compiled.render_pass)
+                            .attachments(&views)
+                            .width(extent.width)
+                            .height(extent.height)
+                            .layers(1);
+                        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_info, None) }?;
+                        self.framebuffers.insert((compiled.pass, views), framebuffer);
+                        framebuffer
+                    }
+                };
+                let render_pass_info = vk::RenderPassBeginInfo::builder()
+                    .render_pass(compiled.render_pass)
+                    .framebuffer(framebuffer)
+                    .render_area(vk::Rect2D { offset: vk::Offset2D::default(), extent })
+                    .clear_values(&compiled.clear_values);
+                let contents = if pass.secondary_command_buffers { vk::SubpassContents::SECONDARY_COMMAND_BUFFERS } else { vk::SubpassContents::INLINE };
+                unsafe { device.cmd_begin_render_pass(command_buffer, &render_pass_info, contents) };
+                record(PassHandle(compiled.pass), command_buffer);
+                unsafe { device.cmd_end_render_pass(command_buffer) };
+            }
+            if let (Some(profiler), Some(scope)) = (profiler.as_mut(), scope) {
+                profiler.end_scope(device, command_buffer, scope);
+            }
+        }
+        self.final_barriers.record(device, command_buffer, &resolve);
+        Ok(())
+    }
+
+    /// The graph in Graphviz dot format. Passes are boxes numbered in execution order, culled
+    /// ones dashed, resources ellipses, imported ones bold. Edges point from the resources a pass
+    /// reads to the pass and from the pass to the resources it writes.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph render_graph {\n    rankdir=LR;\n    node [fontname=\"sans-serif\"];\n    edge [fontname=\"sans-serif\", fontsize=10];\n");
+        for (idx, pass) in self.passes.iter().enumerate() {
+            let position = self.compiled.iter().position(|compiled| compiled.pass == idx);
+            let (label, style) = match position {
+                Some(position) => (format!("#{} {}", position, pass.name), "filled"),
+                None => (pass.name.clone(), "dashed"),
+            };
+            dot += &format!("    pass{} [label=\"{}\", shape=box, style={}, fillcolor=lightblue];\n", idx, label, style);
+        }
+        for (idx, image) in self.images.iter().enumerate() {
+            let info = &image.info;
+            dot += &format!("    image{} [label=\"{}\\n{:?} {}x{} x{}\", shape=ellipse{}];\n", idx, image.name, info.format, info.extent.width, info.extent.height,
+                info.samples.as_raw(), if image.imported.is_some() { ", style=bold" } else { "" });
+        }
+        for (idx, name) in self.buffers.iter().enumerate() {
+            dot += &format!("    buffer{} [label=\"{}\", shape=ellipse, style=bold];\n", idx, name);
+        }
+        for (idx, pass) in self.passes.iter().enumerate() {
+            for image_use in &pass.images {
+                if image_use.access.reads {
+                    dot += &format!("    image{} -> pass{} [label=\"{}\"];\n", image_use.image.0, idx, image_use.kind);
+                }
+                if image_use.access.writes {
+                    dot += &format!("    pass{} -> image{} [label=\"{}\"];\n", idx, image_use.image.0, image_use.kind);
+                }
+            }
+            for buffer_use in &pass.buffers {
+                if buffer_use.access.reads {
+                    dot += &format!("    buffer{} -> pass{};\n", buffer_use.buffer.0, idx);
+                }
+                if buffer_use.access.writes {
+                    dot += &format!("    pass{} -> buffer{};\n", idx, buffer_use.buffer.0);
+                }
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        unsafe {
+            for (_, framebuffer) in self.framebuffers.drain() {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            for compiled in self.compiled.drain(..) {
+                device.destroy_render_pass(compiled.render_pass, None);
+            }
+            for (_, (image, view)) in self.transient_images.drain() {
+                device.destroy_image_view(view, None);
+                device.destroy_image(image, None);
+            }
+            for memory in self.memory.drain(..) {
+                device.free_memory(memory, None);
+            }
+        }
+    }
+}
+
+/// Assigns each transient image, given as `(first, last, memory_type)` sorted by `first`, to a
+/// memory slot. An image reuses the slot of earlier ones if the last of them is done before the
+/// image is first used and needs the same memory type. Returns the slot index of each image,
+/// slots are numbered in the order they are first used.
+fn assign_memory_slots(images: &[(usize, usize, u32)]) -> Vec<usize> {
+    // memory type and last use of every slot
+    let mut slots: Vec<(u32, usize)> = Vec::new();
+    images.iter().map(|(first, last, memory_type)| {
+        match slots.iter().position(|(slot_type, last_use)| last_use < first && slot_type == memory_type) {
+            Some(slot) => {
+                slots[slot].1 = *last;
+                slot
+            }
+            None => {
+                slots.push((*memory_type, *last));
+                slots.len() - 1
+            }
+        }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> ImageInfo {
+        ImageInfo::new(vk::Format::R8G8B8A8_UNORM, vk::Extent2D { width: 4, height: 4 })
+    }
+
+    /// The scene is drawn, then sampled by a blur nothing reads and by the pass writing the swapchain image.
+    fn scene_graph() -> RenderGraph {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.import_image("swapchain", info(), vk::ImageLayout::UNDEFINED, vk::ImageLayout::PRESENT_SRC_KHR);
+        let scene = graph.create_image("scene", info());
+        let blurred = graph.create_image("blurred", info());
+        graph.add_pass("scene").color_attachment(scene, AttachmentLoad::DontCare);
+        graph.add_pass("blur").sampled_image(scene, vk::PipelineStageFlags::FRAGMENT_SHADER).color_attachment(blurred, AttachmentLoad::DontCare);
+        graph.add_pass("post").sampled_image(scene, vk::PipelineStageFlags::FRAGMENT_SHADER).color_attachment(swapchain, AttachmentLoad::DontCare);
+        graph
+    }
+
+    #[test]
+    fn dependencies_follow_reads_and_writes() {
+        assert_eq!(scene_graph().dependencies(), vec![(0, 1, true), (0, 2, true)]);
+
+        let mut graph = RenderGraph::new();
+        let particles = graph.import_buffer("particles");
+        graph.add_pass("simulate").write_buffer(particles, vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE);
+        graph.add_pass("draw").read_buffer(particles, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+        graph.add_pass("simulate again").write_buffer(particles, vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE);
+        // the second write has to wait for the read and for the first write, but doesn't use them
+        assert_eq!(graph.dependencies(), vec![(0, 1, true), (1, 2, false), (0, 2, false)]);
+    }
+
+    #[test]
+    fn passes_without_used_results_are_culled() {
+        let graph = scene_graph();
+        assert_eq!(graph.cull(&graph.dependencies()), vec![false, true, false]);
+    }
+
+    #[test]
+    fn passes_with_side_effects_are_kept() {
+        for &side_effects in &[false, true] {
+            let mut graph = RenderGraph::new();
+            let scene = graph.create_image("scene", info());
+            graph.add_pass("scene").color_attachment(scene, AttachmentLoad::DontCare);
+            // reads the scene back for a screenshot, which the graph can't see
+            let capture = graph.add_pass("capture").transfer_source(scene);
+            if side_effects {
+                capture.side_effects();
+            }
+            assert_eq!(graph.cull(&graph.dependencies()), vec![!side_effects, !side_effects]);
+        }
+    }
+
+    #[test]
+    fn storage_images_are_written_in_general_layout() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.import_image("swapchain", info(), vk::ImageLayout::UNDEFINED, vk::ImageLayout::PRESENT_SRC_KHR);
+        let lighting = graph.create_image("lighting", info());
+        graph.add_pass("light").storage_image(lighting, vk::PipelineStageFlags::COMPUTE_SHADER, true);
+        graph.add_pass("post").storage_image(lighting, vk::PipelineStageFlags::COMPUTE_SHADER, false).storage_image(swapchain, vk::PipelineStageFlags::COMPUTE_SHADER, true);
+        assert_eq!(graph.dependencies(), vec![(0, 1, true)]);
+        assert_eq!(graph.cull(&graph.dependencies()), vec![false, false]);
+        let light = &graph.passes[0].images[0];
+        assert_eq!(light.layout, vk::ImageLayout::GENERAL);
+        assert_eq!(light.access.access, vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE);
+        assert_eq!(graph.passes[1].images[0].access.access, vk::AccessFlags::SHADER_READ);
+        assert!(graph.images[lighting.0].usage.contains(vk::ImageUsageFlags::STORAGE));
+    }
+
+    #[test]
+    fn order_skips_culled_passes() {
+        let mut graph = scene_graph();
+        let dependencies = graph.dependencies();
+        graph.culled = graph.cull(&dependencies);
+        assert_eq!(graph.order(&dependencies), vec![0, 2]);
+    }
+
+    #[test]
+    fn order_runs_writers_before_readers() {
+        let mut graph = RenderGraph::new();
+        let swapchain = graph.import_image("swapchain", info(), vk::ImageLayout::UNDEFINED, vk::ImageLayout::PRESENT_SRC_KHR);
+        let scene = graph.create_image("scene", info());
+        let particles = graph.import_buffer("particles");
+        graph.add_pass("simulate").write_buffer(particles, vk::PipelineStageFlags::COMPUTE_SHADER, vk::AccessFlags::SHADER_WRITE);
+        graph.add_pass("scene").color_attachment(scene, AttachmentLoad::DontCare);
+        graph.add_pass("particles").read_buffer(particles, vk::PipelineStageFlags::VERTEX_INPUT, vk::AccessFlags::VERTEX_ATTRIBUTE_READ).color_attachment(scene, AttachmentLoad::Load);
+        graph.add_pass("post").sampled_image(scene, vk::PipelineStageFlags::FRAGMENT_SHADER).color_attachment(swapchain, AttachmentLoad::DontCare);
+        let dependencies = graph.dependencies();
+        graph.culled = graph.cull(&dependencies);
+        let order = graph.order(&dependencies);
+        assert_eq!(order, vec![0, 1, 2, 3]);
+        for (from, to, _) in dependencies {
+            let position = |pass| order.iter().position(|idx| *idx == pass).unwrap();
+            assert!(position(from) < position(to), "{} has to run before {}", from, to);
+        }
+    }
+
+    #[test]
+    fn memory_is_shared_once_the_previous_image_is_done() {
+        // (first, last, memory type) of each image and the expected slots
+        let cases = [
+            (vec![(0, 1, 0), (2, 3, 0)], vec![0, 0]),
+            // used by the same pass, so they can't share
+            (vec![(0, 1, 0), (1, 2, 0)], vec![0, 1]),
+            (vec![(0, 1, 0), (2, 3, 1)], vec![0, 1]),
+            (vec![(0, 0, 0), (0, 2, 0), (1, 1, 0), (3, 3, 0)], vec![0, 1, 0, 0]),
+            (vec![], vec![]),
+        ];
+        for (images, slots) in &cases {
+            assert_eq!(assign_memory_slots(images), *slots, "{:?}", images);
+        }
+    }
+
+    #[test]
+    fn dot_marks_imported_images_and_culled_passes() {
+        let dot = scene_graph().to_dot();
+        assert!(dot.starts_with("digraph render_graph {"));
+        assert!(dot.ends_with("}\n"));
+        // nothing is compiled, so every pass is drawn as culled
+        assert!(dot.contains("    pass1 [label=\"blur\", shape=box, style=dashed, fillcolor=lightblue];\n"));
+        assert!(dot.contains("    image0 [label=\"swapchain\\nR8G8B8A8_UNORM 4x4 x1\", shape=ellipse, style=bold];\n"));
+        assert!(dot.contains("    image1 [label=\"scene\\nR8G8B8A8_UNORM 4x4 x1\", shape=ellipse];\n"));
+        assert!(dot.contains("    image1 -> pass2 [label=\"sampled\"];\n"));
+        assert!(dot.contains("    pass2 -> image0 [label=\"color\"];\n"));
+    }
+}
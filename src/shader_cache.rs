@@ -0,0 +1,162 @@
+use ash::vk;
+use std::{
+    fmt::Write as _,
+    io,
+    path::PathBuf,
+};
+
+use crate::{
+    shader_loader::ShaderLoader,
+    shader_options::{ShaderCompileSettings, ShaderDefines},
+    spirv::{load_spirv, write_spirv},
+};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// FNV-1a, which unlike `DefaultHasher` is stable across Rust releases.
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME))
+}
+
+fn content_hash(data: &str) -> u64 {
+    fnv1a(FNV_OFFSET_BASIS, data.as_bytes())
+}
+
+/// On-disk cache of compiled shaders. Entries are keyed by a hash of the source and everything
+/// that affects compilation. Each entry also records the hashes of the files it included, so
+/// editing an include invalidates it as well.
+pub struct ShaderCache {
+    dir: PathBuf,
+}
+
+impl ShaderCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self {
+            dir: dir.into(),
+        }
+    }
+
+    pub fn key(&self, filename: &str, stage: vk::ShaderStageFlags, defines: &ShaderDefines, settings: &ShaderCompileSettings, source: &str) -> String {
+        let mut hash = FNV_OFFSET_BASIS;
+        for part in &[filename.to_owned(), format!("{:?}", stage), format!("{:?}", defines), format!("{:?}", settings), source.to_owned()] {
+            hash = fnv1a(hash, part.as_bytes());
+            hash = fnv1a(hash, &[0]);
+        }
+        format!("{:016x}", hash)
+    }
+
+    /// Returns the cached SPIR-V and the names of the files it included, if the entry
+    /// exists and none of its includes changed.
+    pub fn lookup(&self, key: &str, loader: &ShaderLoader) -> Option<(Vec<u32>, Vec<String>)> {
+        let deps = std::fs::read_to_string(self.dir.join(format!("{}.deps", key))).ok()?;
+        let mut includes = Vec::new();
+        for line in deps.lines() {
+            let mut parts = line.splitn(2, ' ');
+            let hash = u64::from_str_radix(parts.next()?, 16).ok()?;
+            let include = parts.next()?;
+            if content_hash(&loader.read(include).ok()?) != hash {
+                return None;
+            }
+            includes.push(include.to_owned());
+        }
+        let code = load_spirv(self.dir.join(format!("{}.spv", key))).ok()?;
+        Some((code, includes))
+    }
+
+    pub fn store(&self, key: &str, code: &[u32], includes: &[String], loader: &ShaderLoader) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let mut deps = String::new();
+        for include in includes {
+            writeln!(deps, "{:016x} {}", content_hash(&loader.read(include)?), include).unwrap();
+        }
+        // Write the SPIR-V first, an entry only counts as present once its deps file exists.
+        write_spirv(self.dir.join(format!("{}.spv", key)), code)?;
+        std::fs::write(self.dir.join(format!("{}.deps", key)), deps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflection::SPIRV_MAGIC;
+
+    /// A directory of its own for each test, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("vulkan-experiments-{}-{}", std::process::id(), name));
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn key(filename: &str, stage: vk::ShaderStageFlags, defines: &ShaderDefines, settings: &ShaderCompileSettings, source: &str) -> String {
+        ShaderCache::new("unused").key(filename, stage, defines, settings, source)
+    }
+
+    #[test]
+    fn keys_change_with_every_input() {
+        let defines = ShaderDefines::new();
+        let settings = ShaderCompileSettings::default();
+        let base = key("a.fs", vk::ShaderStageFlags::FRAGMENT, &defines, &settings, "void main() {}");
+        assert_eq!(base.len(), 16);
+        assert!(base.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(key("a.fs", vk::ShaderStageFlags::FRAGMENT, &defines, &settings, "void main() {}"), base);
+
+        let optimized = ShaderCompileSettings { optimize: !settings.optimize, ..settings.clone() };
+        let others = [
+            key("b.fs", vk::ShaderStageFlags::FRAGMENT, &defines, &settings, "void main() {}"),
+            key("a.fs", vk::ShaderStageFlags::VERTEX, &defines, &settings, "void main() {}"),
+            key("a.fs", vk::ShaderStageFlags::FRAGMENT, &defines.clone().define("FOG", None), &settings, "void main() {}"),
+            key("a.fs", vk::ShaderStageFlags::FRAGMENT, &defines, &optimized, "void main() {}"),
+            key("a.fs", vk::ShaderStageFlags::FRAGMENT, &defines, &settings, "void main() { }"),
+            // the parts are separated, moving text from one to the next changes the key
+            key("a.f", vk::ShaderStageFlags::FRAGMENT, &defines, &settings, "svoid main() {}"),
+        ];
+        for (idx, other) in others.iter().enumerate() {
+            assert_ne!(*other, base, "change {}", idx);
+        }
+    }
+
+    #[test]
+    fn hits_until_an_include_changes() {
+        let shaders = TempDir::new("cache-shaders");
+        let cache_dir = TempDir::new("cache-entries");
+        std::fs::write(shaders.0.join("common.glsl"), "float fog() { return 1.0; }").unwrap();
+        let loader = ShaderLoader::from_disk(&shaders.0);
+        let cache = ShaderCache::new(&cache_dir.0);
+        let code = [SPIRV_MAGIC, 0x0001_0000, 0, 1, 0];
+
+        assert!(cache.lookup("0123456789abcdef", &loader).is_none());
+        cache.store("0123456789abcdef", &code, &["common.glsl".to_owned()], &loader).unwrap();
+        let (cached, includes) = cache.lookup("0123456789abcdef", &loader).unwrap();
+        assert_eq!(cached, code);
+        assert_eq!(includes, vec!["common.glsl".to_owned()]);
+        assert!(cache.lookup("fedcba9876543210", &loader).is_none());
+
+        std::fs::write(shaders.0.join("common.glsl"), "float fog() { return 0.5; }").unwrap();
+        assert!(cache.lookup("0123456789abcdef", &loader).is_none());
+        std::fs::remove_file(shaders.0.join("common.glsl")).unwrap();
+        assert!(cache.lookup("0123456789abcdef", &loader).is_none());
+    }
+
+    #[test]
+    fn entries_without_deps_are_misses() {
+        let cache_dir = TempDir::new("cache-without-deps");
+        let loader = ShaderLoader::from_disk(&cache_dir.0);
+        let cache = ShaderCache::new(&cache_dir.0);
+        cache.store("0123456789abcdef", &[SPIRV_MAGIC, 0x0001_0000, 0, 1, 0], &[], &loader).unwrap();
+        assert!(cache.lookup("0123456789abcdef", &loader).is_some());
+        // an interrupted store leaves the SPIR-V without its deps file
+        std::fs::remove_file(cache_dir.0.join("0123456789abcdef.deps")).unwrap();
+        assert!(cache.lookup("0123456789abcdef", &loader).is_none());
+    }
+}
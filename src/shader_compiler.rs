@@ -0,0 +1,158 @@
+use ash::vk;
+use log::{debug, info, warn};
+use std::{
+    cell::RefCell,
+    path::Path,
+};
+
+use crate::{
+    shader_cache::ShaderCache,
+    shader_diagnostics::{parse_diagnostics, Diagnostic, ShaderCompileError},
+    shader_loader::ShaderLoader,
+    shader_options::{ShaderCompileSettings, ShaderDefines},
+    spirv::{spirv_filename, write_spirv},
+};
+
+#[derive(Debug)]
+pub struct UnsupportedShaderStage(pub vk::ShaderStageFlags);
+impl std::error::Error for UnsupportedShaderStage {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for UnsupportedShaderStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unsupported shader stage {:?}", self.0)
+    }
+}
+
+fn shader_kind(stage: vk::ShaderStageFlags) -> Result<shaderc::ShaderKind, UnsupportedShaderStage> {
+    Ok(match stage {
+        vk::ShaderStageFlags::VERTEX => shaderc::ShaderKind::Vertex,
+        vk::ShaderStageFlags::TESSELLATION_CONTROL => shaderc::ShaderKind::TessControl,
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => shaderc::ShaderKind::TessEvaluation,
+        vk::ShaderStageFlags::GEOMETRY => shaderc::ShaderKind::Geometry,
+        vk::ShaderStageFlags::FRAGMENT => shaderc::ShaderKind::Fragment,
+        vk::ShaderStageFlags::COMPUTE => shaderc::ShaderKind::Compute,
+        _ => return Err(UnsupportedShaderStage(stage)),
+    })
+}
+
+/// The stage of a shader source file, derived from its extension. Files with other
+/// extensions (like `.glsl`) are only used through `#include`.
+pub fn stage_for_filename(filename: &str) -> Option<vk::ShaderStageFlags> {
+    match Path::new(filename).extension()?.to_str()? {
+        "vs" => Some(vk::ShaderStageFlags::VERTEX),
+        "tcs" => Some(vk::ShaderStageFlags::TESSELLATION_CONTROL),
+        "tes" => Some(vk::ShaderStageFlags::TESSELLATION_EVALUATION),
+        "gs" => Some(vk::ShaderStageFlags::GEOMETRY),
+        "fs" => Some(vk::ShaderStageFlags::FRAGMENT),
+        "cs" => Some(vk::ShaderStageFlags::COMPUTE),
+        _ => None,
+    }
+}
+
+/// Turns GLSL sources into SPIR-V with shaderc, going through the shader cache if there is one.
+pub struct ShaderCompiler {
+    compiler: shaderc::Compiler,
+    pub loader: ShaderLoader,
+    pub settings: ShaderCompileSettings,
+    cache: Option<ShaderCache>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl ShaderCompiler {
+    pub fn new(loader: ShaderLoader, cache: Option<ShaderCache>) -> Option<Self> {
+        Some(Self {
+            compiler: shaderc::Compiler::new()?,
+            loader,
+            settings: Default::default(),
+            cache,
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Debug builds read the sources from the source tree, watch them and cache the results,
+    /// release builds compile the sources embedded into the binary.
+    pub fn for_build() -> Option<Self> {
+        if cfg!(debug_assertions) {
+            Self::new(
+                ShaderLoader::from_disk(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders")),
+                Some(ShaderCache::new(concat!(env!("CARGO_MANIFEST_DIR"), "/target/shader-cache"))),
+            )
+        } else {
+            Self::new(ShaderLoader::embedded(), None)
+        }
+    }
+
+    pub fn compile(&mut self, filename: &str, stage: vk::ShaderStageFlags, defines: &ShaderDefines) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+        let source = self.loader.load(filename)?;
+        let kind = shader_kind(stage)?;
+
+        let key = self.cache.as_ref().map(|cache| cache.key(filename, stage, defines, &self.settings, &source));
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some((code, includes)) = cache.lookup(key, &self.loader) {
+                debug!("{}: using cached SPIR-V {}", filename, key);
+                self.loader.set_includes(filename, includes);
+                return Ok(code);
+            }
+        }
+
+        if !defines.is_empty() {
+            debug!("{}: compiling with defines {:?}", filename, defines);
+        }
+        let includes = RefCell::new(Vec::new());
+        let options = self.settings.compile_options(defines, &self.loader, &includes)?;
+        let artifact = match self.compiler.compile_into_spirv(&source, kind, filename, "main", Some(&options)) {
+            Ok(artifact) => artifact,
+            Err(shaderc::Error::CompilationError(_, output)) => return Err(Box::new(ShaderCompileError::new(filename, &output, &self.loader))),
+            Err(err) => return Err(Box::new(err)),
+        };
+        drop(options);
+        if artifact.get_num_warnings() > 0 {
+            for mut warning in parse_diagnostics(&artifact.get_warning_messages()) {
+                warning.attach_context(&self.loader);
+                warn!(target: "shaderc", "{}", warning);
+                self.warnings.push(warning);
+            }
+        }
+        let mut includes = includes.into_inner();
+        includes.sort();
+        includes.dedup();
+
+        let code = artifact.as_binary().to_vec();
+        let spirv_version = ((code[1] >> 16) & 0xff, (code[1] >> 8) & 0xff);
+        if spirv_version > self.settings.target.spirv_version() {
+            warn!("{}: SPIR-V {}.{} is newer than the target environment {:?}", filename, spirv_version.0, spirv_version.1, self.settings.target);
+        }
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Err(err) = cache.store(key, &code, &includes, &self.loader) {
+                warn!("{}: failed to write shader cache entry: {}", filename, err);
+            }
+        }
+        self.loader.set_includes(filename, includes);
+        Ok(code)
+    }
+
+    /// Returns the warnings of all compilations since the last call.
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Compiles every shader source to `<out_dir>/<name>.spv`, for builds that load
+    /// precompiled SPIR-V instead of compiling at runtime.
+    pub fn compile_all(&mut self, out_dir: &Path) -> Result<usize, Box<dyn std::error::Error>> {
+        std::fs::create_dir_all(out_dir)?;
+        let mut count = 0;
+        for filename in self.loader.list()? {
+            if let Some(stage) = stage_for_filename(&filename) {
+                let code = self.compile(&filename, stage, &ShaderDefines::new())?;
+                let path = out_dir.join(spirv_filename(&filename));
+                write_spirv(&path, &code)?;
+                info!("{} -> {}", filename, path.display());
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
@@ -0,0 +1,306 @@
+use std::fmt::Write as _;
+
+use crate::shader_loader::ShaderLoader;
+
+/// Lines of source shown before the offending line.
+const CONTEXT_LINES: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// One message from the shader compiler.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Starts at 1 like the line, only some compilers report it.
+    pub column: Option<u32>,
+    pub message: String,
+    /// The offending line and the ones before it, as (line number, text).
+    pub context: Vec<(u32, String)>,
+}
+
+/// Parses shaderc output, which has lines of the form `file:line: error: message` or
+/// `file:line:column: error: message`. Lines that don't start a new diagnostic continue the
+/// message of the previous one, summary lines like `1 error generated.` are skipped.
+pub fn parse_diagnostics(output: &str) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for line in output.lines() {
+        match parse_line(line) {
+            Some(diagnostic) => diagnostics.push(diagnostic),
+            None if line.trim().is_empty() || line.trim_end().ends_with(" generated.") => {}
+            None => if let Some(previous) = diagnostics.last_mut() {
+                previous.message.push('\n');
+                previous.message.push_str(line.trim_end());
+            },
+        }
+    }
+    diagnostics
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    let (location, severity, message) = [(": error: ", Severity::Error), (": warning: ", Severity::Warning)].iter()
+        .find_map(|(separator, severity)| line.find(separator).map(|idx| (&line[..idx], *severity, &line[idx + separator.len()..])))
+        .or_else(|| line.strip_prefix("error: ").map(|message| ("", Severity::Error, message)))
+        .or_else(|| line.strip_prefix("warning: ").map(|message| ("", Severity::Warning, message)))?;
+    // up to two numbers at the end are the line and column, the file itself may contain
+    // colons, like the drive in Windows paths
+    let mut file = location;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        match file.rfind(':').map(|idx| (idx, file[idx + 1..].parse::<u32>())) {
+            Some((idx, Ok(number))) => {
+                numbers.push(number);
+                file = &file[..idx];
+            }
+            _ => break,
+        }
+    }
+    let (line, column) = match numbers[..] {
+        [column, line] => (Some(line), Some(column)),
+        [line] => (Some(line), None),
+        _ => (None, None),
+    };
+    Some(Diagnostic {
+        severity,
+        file: if file.is_empty() { None } else { Some(file.to_owned()) },
+        line,
+        column,
+        message: message.trim().to_owned(),
+        context: Vec::new(),
+    })
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(escaped, "\\u{:04x}", c as u32).unwrap(),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Diagnostic {
+    /// Fills `context` with the surrounding source lines.
+    pub fn attach_context(&mut self, loader: &ShaderLoader) {
+        if let (Some(file), Some(line)) = (&self.file, self.line) {
+            if let Ok(source) = loader.read(file) {
+                let first = line.saturating_sub(CONTEXT_LINES).max(1);
+                self.context = source.lines().enumerate()
+                    .map(|(idx, text)| (idx as u32 + 1, text.to_owned()))
+                    .filter(|(number, _)| *number >= first && *number <= line)
+                    .collect();
+            }
+        }
+    }
+
+    /// Column range to underline in the offending line. glslang quotes the token it
+    /// complains about, so that is used when it can be found, then the word at the column
+    /// if there is one; otherwise the whole line is marked.
+    fn highlight(&self, text: &str) -> (usize, usize) {
+        let quoted = self.message.split('\'').nth(1).map(str::trim).filter(|token| !token.is_empty());
+        if let Some(start) = quoted.and_then(|token| text.find(token)) {
+            return (start, quoted.unwrap().len());
+        }
+        let column_start = self.column.and_then(|column| text.char_indices().nth(column.max(1) as usize - 1)).map(|(idx, _)| idx);
+        if let Some(start) = column_start {
+            let word = text[start..].find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len() - start);
+            return (start, word.max(1));
+        }
+        let start = text.len() - text.trim_start().len();
+        (start, text.trim().len().max(1))
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"severity\":\"{}\",\"file\":{},\"line\":{},\"column\":{},\"message\":\"{}\"}}",
+            self.severity,
+            self.file.as_ref().map(|file| format!("\"{}\"", escape_json(file))).unwrap_or_else(|| "null".to_owned()),
+            self.line.map(|line| line.to_string()).unwrap_or_else(|| "null".to_owned()),
+            self.column.map(|column| column.to_string()).unwrap_or_else(|| "null".to_owned()),
+            escape_json(&self.message),
+        )
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}: {}", self.severity, self.message)?;
+        match (&self.file, self.line, self.column) {
+            (Some(file), Some(line), Some(column)) => writeln!(f, "  --> {}:{}:{}", file, line, column)?,
+            (Some(file), Some(line), None) => writeln!(f, "  --> {}:{}", file, line)?,
+            (Some(file), None, _) => writeln!(f, "  --> {}", file)?,
+            _ => {}
+        }
+        let width = self.context.last().map(|(number, _)| number.to_string().len()).unwrap_or(0);
+        for (number, text) in &self.context {
+            writeln!(f, "{:>width$} | {}", number, text, width = width)?;
+            if Some(*number) == self.line {
+                let (start, len) = self.highlight(text);
+                let indent: String = text[..start].chars().map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+                writeln!(f, "{:>width$} | {}^{}", "", indent, "~".repeat(len - 1), width = width)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A failed shader compilation, with the diagnostics parsed from the compiler output.
+pub struct ShaderCompileError {
+    pub filename: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl ShaderCompileError {
+    pub fn new(filename: &str, output: &str, loader: &ShaderLoader) -> Self {
+        let mut diagnostics = parse_diagnostics(output);
+        for diagnostic in &mut diagnostics {
+            diagnostic.attach_context(loader);
+        }
+        Self {
+            filename: filename.to_owned(),
+            diagnostics,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("[{}]", self.diagnostics.iter().map(Diagnostic::to_json).collect::<Vec<_>>().join(","))
+    }
+}
+
+impl std::error::Error for ShaderCompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for ShaderCompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self.diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Error).count();
+        writeln!(f, "Failed to compile {}: {} error(s)", self.filename, errors)?;
+        for diagnostic in &self.diagnostics {
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+// Debug renders the same way, so returning the error from main stays readable.
+impl std::fmt::Debug for ShaderCompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(message: &str, column: Option<u32>, context: &[(u32, &str)]) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            file: Some("shader.frag".to_owned()),
+            line: context.last().map(|(number, _)| *number),
+            column,
+            message: message.to_owned(),
+            context: context.iter().map(|(number, text)| (*number, text.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn locations_and_severities() {
+        // output line, severity, file, line, column and message
+        let cases = [
+            ("shader.frag:12: error: 'x' : undeclared identifier", Severity::Error, Some("shader.frag"), Some(12), None, "'x' : undeclared identifier"),
+            ("shader.frag:12:5: error: 'x' : undeclared identifier", Severity::Error, Some("shader.frag"), Some(12), Some(5), "'x' : undeclared identifier"),
+            ("common/noise.glsl:3: warning: unused variable", Severity::Warning, Some("common/noise.glsl"), Some(3), None, "unused variable"),
+            ("C:\\shaders\\shader.vert:7:1: error: syntax error", Severity::Error, Some("C:\\shaders\\shader.vert"), Some(7), Some(1), "syntax error"),
+            ("shader.frag: error: #version must come first", Severity::Error, Some("shader.frag"), None, None, "#version must come first"),
+            ("error: no main function", Severity::Error, None, None, None, "no main function"),
+            ("warning:   trailing spaces   ", Severity::Warning, None, None, None, "trailing spaces"),
+        ];
+        for (output, severity, file, line, column, message) in &cases {
+            let diagnostics = parse_diagnostics(output);
+            assert_eq!(diagnostics.len(), 1, "{}", output);
+            let diagnostic = &diagnostics[0];
+            assert_eq!(diagnostic.severity, *severity, "{}", output);
+            assert_eq!(diagnostic.file.as_deref(), *file, "{}", output);
+            assert_eq!((diagnostic.line, diagnostic.column), (*line, *column), "{}", output);
+            assert_eq!(diagnostic.message, *message, "{}", output);
+        }
+    }
+
+    #[test]
+    fn continuation_lines_and_summaries() {
+        let output = "\
+shader.frag:4: error: 'foo' : no matching overloaded function found
+  candidates are: foo(vf3)
+shader.frag:9: warning: implicit conversion
+
+2 errors generated.
+1 warning and 1 error generated.
+";
+        let diagnostics = parse_diagnostics(output);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "'foo' : no matching overloaded function found\n  candidates are: foo(vf3)");
+        assert_eq!((diagnostics[1].severity, diagnostics[1].line), (Severity::Warning, Some(9)));
+        assert_eq!(diagnostics[1].message, "implicit conversion");
+        // nothing to continue yet
+        assert!(parse_diagnostics("In file included from shader.frag\n").is_empty());
+    }
+
+    #[test]
+    fn carets_under_the_offending_token() {
+        // message, column, offending line and the expected caret line
+        let cases = [
+            // the quoted token is found in the line
+            ("'colour' : undeclared identifier", None, "    fragColor = colour;", "   |                 ^~~~~~"),
+            // the word at the column
+            ("syntax error", Some(10), "    vec3 normal", "   |          ^~~~~~"),
+            ("syntax error", Some(7), "\tvec3 x;", "   | \t     ^"),
+            // otherwise the line without its indentation
+            ("syntax error", None, "  return;", "   |   ^~~~~~~"),
+            ("'missing' : undeclared identifier", None, "", "   | ^"),
+        ];
+        for (message, column, text, caret) in &cases {
+            let rendered = diagnostic(message, *column, &[(11, "void main() {"), (12, text)]).to_string();
+            let lines: Vec<&str> = rendered.lines().collect();
+            assert_eq!(lines[0], format!("error: {}", message));
+            assert_eq!(lines[1], match column {
+                Some(column) => format!("  --> shader.frag:12:{}", column),
+                None => "  --> shader.frag:12".to_owned(),
+            });
+            assert_eq!(lines[2], "11 | void main() {");
+            assert_eq!(lines[3], format!("12 | {}", text));
+            assert_eq!(lines[4], *caret, "{}", message);
+            assert_eq!(lines.len(), 5);
+        }
+    }
+
+    #[test]
+    fn json_is_escaped() {
+        let mut diagnostic = diagnostic("'\"' : bad \\ char\n\tat\u{1}", Some(3), &[(2, "")]);
+        diagnostic.file = Some("C:\\shaders\\a.frag".to_owned());
+        assert_eq!(diagnostic.to_json(), r#"{"severity":"error","file":"C:\\shaders\\a.frag","line":2,"column":3,"message":"'\"' : bad \\ char\n\tat\u0001"}"#);
+
+        let diagnostic = Diagnostic { severity: Severity::Warning, file: None, line: None, column: None, message: "unused".to_owned(), context: Vec::new() };
+        assert_eq!(diagnostic.to_json(), r#"{"severity":"warning","file":null,"line":null,"column":null,"message":"unused"}"#);
+    }
+}
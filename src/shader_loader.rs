@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+const EMBEDDED_SHADERS: &[(&str, &str)] = &[
+    ("common.glsl", include_str!("shaders/common.glsl")),
+    ("triangle.vs", include_str!("shaders/triangle.vs")),
+    ("triangle.fs", include_str!("shaders/triangle.fs")),
+    ("particles.cs", include_str!("shaders/particles.cs")),
+    ("particles.vs", include_str!("shaders/particles.vs")),
+    ("particles.fs", include_str!("shaders/particles.fs")),
+    ("saxpy.cs", include_str!("shaders/saxpy.cs")),
+    ("fullscreen.vs", include_str!("shaders/fullscreen.vs")),
+    ("tonemap.fs", include_str!("shaders/tonemap.fs")),
+    ("fxaa.fs", include_str!("shaders/fxaa.fs")),
+    ("vignette.fs", include_str!("shaders/vignette.fs")),
+    ("color_grading.fs", include_str!("shaders/color_grading.fs")),
+    ("overlay.vs", include_str!("shaders/overlay.vs")),
+    ("overlay.fs", include_str!("shaders/overlay.fs")),
+    ("debug_ui.vs", include_str!("shaders/debug_ui.vs")),
+    ("debug_ui.fs", include_str!("shaders/debug_ui.fs")),
+    ("text.vs", include_str!("shaders/text.vs")),
+    ("text.fs", include_str!("shaders/text.fs")),
+    ("sprite.vs", include_str!("shaders/sprite.vs")),
+    ("sprite.fs", include_str!("shaders/sprite.fs")),
+    ("shape.fs", include_str!("shaders/shape.fs")),
+];
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Provides shader sources either from the copies baked into the binary or,
+/// in development mode, from a directory on disk that is watched for changes.
+pub struct ShaderLoader {
+    root: Option<PathBuf>,
+    modified: HashMap<String, Option<SystemTime>>,
+    includes: HashMap<String, Vec<String>>,
+    last_poll: Instant,
+}
+
+impl ShaderLoader {
+    pub fn embedded() -> Self {
+        Self {
+            root: None,
+            modified: HashMap::new(),
+            includes: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn from_disk<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: Some(root.into()),
+            modified: HashMap::new(),
+            includes: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn is_watching(&self) -> bool {
+        self.root.is_some()
+    }
+
+    fn modification_time(&self, filename: &str) -> Option<SystemTime> {
+        let path = self.root.as_ref()?.join(filename);
+        std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    /// Names of all shader sources, including the ones only meant to be included.
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        match &self.root {
+            Some(root) => {
+                let mut names = Vec::new();
+                for entry in std::fs::read_dir(root)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_file() {
+                        names.push(entry.file_name().to_string_lossy().to_string());
+                    }
+                }
+                names.sort();
+                Ok(names)
+            }
+            None => Ok(EMBEDDED_SHADERS.iter().map(|(name, _)| (*name).to_owned()).collect()),
+        }
+    }
+
+    /// Reads a source file without watching it.
+    pub fn read(&self, filename: &str) -> io::Result<String> {
+        match &self.root {
+            Some(root) => std::fs::read_to_string(root.join(filename)),
+            None => EMBEDDED_SHADERS.iter()
+                .find(|(name, _)| *name == filename)
+                .map(|(_, source)| (*source).to_owned())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("No embedded shader named {}", filename))),
+        }
+    }
+
+    /// Reads a source file and watches it for changes.
+    pub fn load(&mut self, filename: &str) -> io::Result<String> {
+        let source = self.read(filename)?;
+        self.watch(filename);
+        Ok(source)
+    }
+
+    fn watch(&mut self, filename: &str) {
+        if self.is_watching() {
+            let modified = self.modification_time(filename);
+            self.modified.insert(filename.to_owned(), modified);
+        }
+    }
+
+    /// Records the files included by `filename` during its last compilation, so changes to them
+    /// are attributed to it.
+    pub fn set_includes(&mut self, filename: &str, includes: Vec<String>) {
+        for include in &includes {
+            self.watch(include);
+        }
+        self.includes.insert(filename.to_owned(), includes);
+    }
+
+    /// Whether `filename` or one of the files it includes is in `changed`.
+    pub fn is_affected(&self, filename: &str, changed: &[String]) -> bool {
+        changed.iter().any(|changed| changed == filename || self.includes.get(filename).map(|includes| includes.contains(changed)).unwrap_or(false))
+    }
+
+    /// Maps an `#include` directive to a file name relative to the shader root.
+    /// `#include "file"` is relative to the including file, `#include <file>` to the root.
+    pub fn resolve_include(requested: &str, include_type: shaderc::IncludeType, requesting: &str) -> Result<String, String> {
+        let base = match include_type {
+            shaderc::IncludeType::Relative => Path::new(requesting).parent().unwrap_or_else(|| Path::new("")).to_path_buf(),
+            shaderc::IncludeType::Standard => PathBuf::new(),
+        };
+        let mut components: Vec<String> = Vec::new();
+        for component in base.join(requested).components() {
+            match component {
+                Component::Normal(name) => components.push(name.to_string_lossy().to_string()),
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    if components.pop().is_none() {
+                        return Err(format!("{} is outside of the shader root", requested));
+                    }
+                }
+                Component::RootDir | Component::Prefix(_) => return Err(format!("{} is not a relative path", requested)),
+            }
+        }
+        Ok(components.join("/"))
+    }
+
+    /// Returns the previously loaded files that were modified on disk since they were last loaded.
+    /// Checks at most every `POLL_INTERVAL` so it can be called once per frame.
+    pub fn changed_files(&mut self) -> Vec<String> {
+        if !self.is_watching() || self.last_poll.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let changed: Vec<(String, Option<SystemTime>)> = self.modified.iter().filter_map(|(filename, modified)| {
+            let current = self.modification_time(filename);
+            if current != *modified {
+                Some((filename.clone(), current))
+            } else {
+                None
+            }
+        }).collect();
+        for (filename, modified) in &changed {
+            self.modified.insert(filename.clone(), *modified);
+        }
+        changed.into_iter().map(|(filename, _)| filename).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_shader_is_embedded() {
+        let on_disk = ShaderLoader::from_disk(concat!(env!("CARGO_MANIFEST_DIR"), "/src/shaders")).list().unwrap();
+        let mut embedded = ShaderLoader::embedded().list().unwrap();
+        embedded.sort();
+        assert_eq!(embedded, on_disk);
+    }
+
+    #[test]
+    fn includes_resolve_against_the_including_file_or_the_root() {
+        use shaderc::IncludeType::{Relative, Standard};
+        // requested, include type, requesting file and the resolved name
+        let cases = [
+            ("common.glsl", Relative, "triangle.fs", "common.glsl"),
+            ("common.glsl", Relative, "post/tonemap.fs", "post/common.glsl"),
+            ("../common.glsl", Relative, "post/tonemap.fs", "common.glsl"),
+            ("./lib/noise.glsl", Relative, "post/tonemap.fs", "post/lib/noise.glsl"),
+            ("common.glsl", Standard, "post/tonemap.fs", "common.glsl"),
+            ("lib/../common.glsl", Standard, "post/tonemap.fs", "common.glsl"),
+            // includes of includes are relative to the included file
+            ("noise.glsl", Relative, "post/lib/blur.glsl", "post/lib/noise.glsl"),
+        ];
+        for (requested, include_type, requesting, expected) in &cases {
+            assert_eq!(ShaderLoader::resolve_include(requested, *include_type, requesting).as_deref(), Ok(*expected), "{} from {}", requested, requesting);
+        }
+    }
+
+    #[test]
+    fn includes_cant_escape_the_shader_root() {
+        use shaderc::IncludeType::{Relative, Standard};
+        let cases = [
+            ("../common.glsl", Relative, "triangle.fs"),
+            ("../../common.glsl", Relative, "post/tonemap.fs"),
+            ("lib/../../common.glsl", Relative, "triangle.fs"),
+            ("../common.glsl", Standard, "post/tonemap.fs"),
+            ("/etc/passwd", Standard, "triangle.fs"),
+            ("/etc/passwd", Relative, "triangle.fs"),
+        ];
+        for (requested, include_type, requesting) in &cases {
+            assert!(ShaderLoader::resolve_include(requested, *include_type, requesting).is_err(), "{} from {}", requested, requesting);
+        }
+    }
+}
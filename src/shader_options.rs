@@ -0,0 +1,119 @@
+#[cfg(feature = "shaderc")]
+use std::cell::RefCell;
+
+#[cfg(feature = "shaderc")]
+use crate::shader_loader::ShaderLoader;
+
+#[cfg(feature = "shaderc")]
+#[derive(Debug)]
+pub struct CompileOptionsUnavailable();
+#[cfg(feature = "shaderc")]
+impl std::error::Error for CompileOptionsUnavailable {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+#[cfg(feature = "shaderc")]
+impl std::fmt::Display for CompileOptionsUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to initialize shaderc compile options")
+    }
+}
+
+/// The Vulkan version shaders are compiled for. This also selects the SPIR-V version
+/// of the output, since shaderc derives it from the target environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetEnvironment {
+    Vulkan1_0,
+    Vulkan1_1,
+}
+
+impl TargetEnvironment {
+    /// The newest environment supported by a Vulkan API version as encoded by `vk_make_version!`.
+    pub fn for_api_version(api_version: u32) -> Self {
+        if ash::vk_version_minor!(api_version) >= 1 || ash::vk_version_major!(api_version) > 1 {
+            TargetEnvironment::Vulkan1_1
+        } else {
+            TargetEnvironment::Vulkan1_0
+        }
+    }
+    fn env_version(self) -> u32 {
+        match self {
+            TargetEnvironment::Vulkan1_0 => 1 << 22,
+            TargetEnvironment::Vulkan1_1 => (1 << 22) | (1 << 12),
+        }
+    }
+    pub fn spirv_version(self) -> (u32, u32) {
+        match self {
+            TargetEnvironment::Vulkan1_0 => (1, 0),
+            TargetEnvironment::Vulkan1_1 => (1, 3),
+        }
+    }
+}
+
+/// Preprocessor definitions injected into a shader, used to build variants of the same source.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefines(Vec<(String, Option<String>)>);
+
+impl ShaderDefines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.0.retain(|(existing, _)| existing != name);
+        self.0.push((name.to_owned(), value.map(str::to_owned)));
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Option<&str>)> {
+        self.0.iter().map(|(name, value)| (name.as_str(), value.as_deref()))
+    }
+}
+
+/// Settings applied to every shader compilation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShaderCompileSettings {
+    pub target: TargetEnvironment,
+    pub optimize: bool,
+    pub debug_info: bool,
+}
+
+impl Default for ShaderCompileSettings {
+    fn default() -> Self {
+        Self {
+            target: TargetEnvironment::Vulkan1_0,
+            optimize: !cfg!(debug_assertions),
+            debug_info: cfg!(debug_assertions),
+        }
+    }
+}
+
+#[cfg(feature = "shaderc")]
+impl ShaderCompileSettings {
+    /// Builds the shaderc options for one compilation. `#include` directives are resolved
+    /// through `loader`, and the names of all included files are collected in `includes`.
+    pub fn compile_options<'a>(&self, defines: &ShaderDefines, loader: &'a ShaderLoader, includes: &'a RefCell<Vec<String>>) -> Result<shaderc::CompileOptions<'a>, CompileOptionsUnavailable> {
+        let mut options = shaderc::CompileOptions::new().ok_or(CompileOptionsUnavailable())?;
+        options.set_target_env(shaderc::TargetEnv::Vulkan, self.target.env_version());
+        options.set_optimization_level(if self.optimize {
+            shaderc::OptimizationLevel::Performance
+        } else {
+            shaderc::OptimizationLevel::Zero
+        });
+        if self.debug_info {
+            options.set_generate_debug_info();
+        }
+        for (name, value) in defines.iter() {
+            options.add_macro_definition(name, value);
+        }
+        options.set_include_callback(move |requested, include_type, requesting, _depth| {
+            let resolved_name = ShaderLoader::resolve_include(requested, include_type, requesting)?;
+            let content = loader.read(&resolved_name).map_err(|err| format!("{}: {}", resolved_name, err))?;
+            includes.borrow_mut().push(resolved_name.clone());
+            Ok(shaderc::ResolvedInclude { resolved_name, content })
+        });
+        Ok(options)
+    }
+}
@@ -0,0 +1,29 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+// 16 slices of 16x16 side by side, red along x, green along y and blue selecting the slice.
+layout(set = 0, binding = 1) uniform sampler2D lut;
+
+layout(location = 0) in vec2 texCoord;
+layout(location = 0) out vec4 outColor;
+
+const float LUT_SIZE = 16.0;
+
+vec3 srgb_encode(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+void main() {
+    // LUTs are indexed by sRGB encoded colors, the LUT texture's sRGB format decodes the result
+    vec3 coord = srgb_encode(clamp(texture(inputImage, texCoord).rgb, 0.0, 1.0)) * (LUT_SIZE - 1.0);
+    float slice = floor(coord.b);
+    // filtering covers red and green, the two nearest slices are blended by hand
+    vec2 uv = vec2((slice * LUT_SIZE + coord.r + 0.5) / (LUT_SIZE * LUT_SIZE), (coord.g + 0.5) / LUT_SIZE);
+    vec3 lower = textureLod(lut, uv, 0.0).rgb;
+    vec3 upper = textureLod(lut, uv + vec2(1.0 / LUT_SIZE, 0.0), 0.0).rgb;
+    outColor = opaque(mix(lower, upper, coord.b - slice));
+}
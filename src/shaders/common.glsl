@@ -0,0 +1,9 @@
+// Utility functions shared between shaders, use with #include "common.glsl".
+#ifndef COMMON_GLSL
+#define COMMON_GLSL
+
+vec4 opaque(vec3 color) {
+    return vec4(color, 1.0);
+}
+
+#endif
@@ -0,0 +1,20 @@
+#version 450
+
+layout(push_constant) uniform DebugUiParameters {
+    // the screen in egui points, which the vertex positions are given in
+    vec2 screenSize;
+};
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 texCoord;
+// sRGB with premultiplied alpha, like the swapchain image it is blended into
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+
+void main() {
+    gl_Position = vec4(2.0 * position / screenSize - 1.0, 0.0, 1.0);
+    fragColor = color;
+    fragTexCoord = texCoord;
+}
@@ -0,0 +1,10 @@
+#version 450
+
+layout(location = 0) out vec2 texCoord;
+
+// Three vertices without vertex buffer, spanning a triangle with corners at (-1, -1), (3, -1)
+// and (-1, 3) that covers the screen with texture coordinates 0 to 1 on it.
+void main() {
+    texCoord = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(texCoord * 2.0 - 1.0, 0.0, 1.0);
+}
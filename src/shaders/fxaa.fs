@@ -0,0 +1,47 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+layout(location = 0) in vec2 texCoord;
+layout(location = 0) out vec4 outColor;
+
+// Blur distance along an edge, in texels.
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+// Perceived brightness, the input is linear.
+float luma(vec3 color) {
+    return dot(sqrt(color), vec3(0.299, 0.587, 0.114));
+}
+
+vec3 sample_at(vec2 offset) {
+    return texture(inputImage, texCoord + offset).rgb;
+}
+
+// Estimates the edge direction from the corners and blends along it, keeping the
+// wider blend unless it picks up colors from beyond the edge.
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(inputImage, 0));
+    float lumaNW = luma(sample_at(vec2(-1.0, -1.0) * texel));
+    float lumaNE = luma(sample_at(vec2(1.0, -1.0) * texel));
+    float lumaSW = luma(sample_at(vec2(-1.0, 1.0) * texel));
+    float lumaSE = luma(sample_at(vec2(1.0, 1.0) * texel));
+    float lumaM = luma(sample_at(vec2(0.0)));
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 dir = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+    dir = clamp(dir * rcpDirMin, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * texel;
+
+    vec3 rgbA = 0.5 * (sample_at(dir * (1.0 / 3.0 - 0.5)) + sample_at(dir * (2.0 / 3.0 - 0.5)));
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (sample_at(dir * -0.5) + sample_at(dir * 0.5));
+    float lumaB = luma(rgbB);
+    outColor = opaque(lumaB < lumaMin || lumaB > lumaMax ? rgbA : rgbB);
+}
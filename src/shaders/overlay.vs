@@ -0,0 +1,21 @@
+#version 450
+
+// per instance, the top left corner and size of a rectangle in normalized device coordinates
+layout(location = 0) in vec4 rect;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+
+vec2 corners[6] = vec2[](
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
+    vec2(0.0, 0.0)
+);
+
+void main() {
+    gl_Position = vec4(rect.xy + corners[gl_VertexIndex] * rect.zw, 0.0, 1.0);
+    fragColor = color;
+}
@@ -0,0 +1,43 @@
+#version 450
+
+// the workgroup size is specialization constant 0
+layout(local_size_x_id = 0) in;
+
+struct Particle {
+    vec2 position;
+    vec2 velocity;
+    vec4 color;
+};
+
+layout(std430, set = 0, binding = 0) buffer Particles {
+    Particle particles[];
+};
+
+layout(push_constant) uniform Simulation {
+    float deltaTime;
+    uint count;
+} simulation;
+
+const float GRAVITY = 0.8;
+
+void main() {
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= simulation.count) {
+        return;
+    }
+
+    Particle particle = particles[index];
+    particle.velocity.y += GRAVITY * simulation.deltaTime;
+    particle.position += particle.velocity * simulation.deltaTime;
+
+    // bounce off the edges of the viewport
+    if (abs(particle.position.x) > 1.0) {
+        particle.position.x = sign(particle.position.x);
+        particle.velocity.x = -particle.velocity.x;
+    }
+    if (abs(particle.position.y) > 1.0) {
+        particle.position.y = sign(particle.position.y);
+        particle.velocity.y = -particle.velocity.y;
+    }
+    particles[index] = particle;
+}
@@ -0,0 +1,25 @@
+#version 450
+
+// per instance, read straight from the particle storage buffer
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 corner;
+
+layout(constant_id = 0) const float SIZE = 0.006;
+
+vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(1.0, -1.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(-1.0, 1.0),
+    vec2(-1.0, -1.0)
+);
+
+void main() {
+    corner = corners[gl_VertexIndex];
+    gl_Position = vec4(position + corner * SIZE, 0.0, 1.0);
+    fragColor = color;
+}
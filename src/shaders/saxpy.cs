@@ -0,0 +1,25 @@
+#version 450
+
+// y = a * x + y, the classic first GPGPU kernel
+// the workgroup size is specialization constant 0
+layout(local_size_x_id = 0) in;
+
+layout(std430, set = 0, binding = 0) readonly buffer X {
+    float x[];
+};
+layout(std430, set = 0, binding = 1) buffer Y {
+    float y[];
+};
+
+layout(push_constant) uniform Parameters {
+    float a;
+    uint count;
+} parameters;
+
+void main() {
+    uint index = gl_GlobalInvocationID.x;
+    if (index >= parameters.count) {
+        return;
+    }
+    y[index] = parameters.a * x[index] + y[index];
+}
@@ -0,0 +1,20 @@
+#version 450
+
+layout(push_constant) uniform SpriteParameters {
+    // from window pixels to clip space
+    mat4 projection;
+};
+
+// the corners of sprites and shapes in window pixels, already transformed
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 texCoord;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+
+void main() {
+    gl_Position = projection * vec4(position, 0.0, 1.0);
+    fragColor = color;
+    fragTexCoord = texCoord;
+}
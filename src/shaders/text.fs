@@ -0,0 +1,17 @@
+#version 450
+
+// signed distance to the glyph outlines, 0.5 on the outline and larger inside
+layout(set = 0, binding = 0) uniform sampler2D glyphAtlas;
+
+layout(location = 0) in vec4 fragColor;
+layout(location = 1) in vec2 fragTexCoord;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float distance = texture(glyphAtlas, fragTexCoord).r;
+    // antialiased over about a pixel at any size
+    float smoothing = 0.7 * fwidth(distance);
+    float coverage = smoothstep(0.5 - smoothing, 0.5 + smoothing, distance);
+    outColor = vec4(fragColor.rgb, fragColor.a * coverage);
+}
@@ -0,0 +1,30 @@
+#version 450
+
+layout(push_constant) uniform TextParameters {
+    // the glyph rectangles are in window pixels
+    vec2 screenSize;
+};
+
+// per instance, the top left corner and size of a glyph, where it is in the atlas and its color
+layout(location = 0) in vec4 rect;
+layout(location = 1) in vec4 texRect;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
+
+vec2 corners[6] = vec2[](
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
+    vec2(0.0, 0.0)
+);
+
+void main() {
+    vec2 corner = corners[gl_VertexIndex];
+    gl_Position = vec4(2.0 * (rect.xy + corner * rect.zw) / screenSize - 1.0, 0.0, 1.0);
+    fragColor = color;
+    fragTexCoord = mix(texRect.xy, texRect.zw, corner);
+}
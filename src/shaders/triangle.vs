@@ -12,9 +12,17 @@ vec3 colors[3] = vec3[](
     vec3(0.0, 0.0, 1.0)
 );
 
+vec2 texCoords[3] = vec2[](
+    vec2(0.5, 0.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0)
+);
+
 layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 fragTexCoord;
 
 void main() {
     gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
     fragColor = colors[gl_VertexIndex];
+    fragTexCoord = texCoords[gl_VertexIndex];
 }
@@ -0,0 +1,19 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_GOOGLE_include_directive : require
+
+#include "common.glsl"
+
+layout(set = 0, binding = 0) uniform sampler2D inputImage;
+
+layout(location = 0) in vec2 texCoord;
+layout(location = 0) out vec4 outColor;
+
+// How much darker the corners get.
+const float STRENGTH = 0.5;
+
+void main() {
+    // 0 in the center, 1 in the corners
+    float radius = length(texCoord - 0.5) * sqrt(2.0);
+    outColor = opaque(texture(inputImage, texCoord).rgb * (1.0 - STRENGTH * smoothstep(0.5, 1.0, radius)));
+}
@@ -0,0 +1,148 @@
+use ash::vk;
+
+use crate::reflection::{ScalarType, ShaderReflection};
+
+/// Rust types that can be the value of a specialization constant.
+pub trait SpecializationValue: Copy {
+    const SCALAR_TYPE: ScalarType;
+    fn to_bytes(self) -> Vec<u8>;
+}
+
+impl SpecializationValue for bool {
+    const SCALAR_TYPE: ScalarType = ScalarType::Bool;
+    // booleans are VkBool32
+    fn to_bytes(self) -> Vec<u8> {
+        (self as u32).to_ne_bytes().to_vec()
+    }
+}
+impl SpecializationValue for i32 {
+    const SCALAR_TYPE: ScalarType = ScalarType::Int;
+    fn to_bytes(self) -> Vec<u8> {
+        self.to_ne_bytes().to_vec()
+    }
+}
+impl SpecializationValue for u32 {
+    const SCALAR_TYPE: ScalarType = ScalarType::UInt;
+    fn to_bytes(self) -> Vec<u8> {
+        self.to_ne_bytes().to_vec()
+    }
+}
+impl SpecializationValue for f32 {
+    const SCALAR_TYPE: ScalarType = ScalarType::Float;
+    fn to_bytes(self) -> Vec<u8> {
+        self.to_ne_bytes().to_vec()
+    }
+}
+impl SpecializationValue for f64 {
+    const SCALAR_TYPE: ScalarType = ScalarType::Double;
+    fn to_bytes(self) -> Vec<u8> {
+        self.to_ne_bytes().to_vec()
+    }
+}
+
+#[derive(Debug)]
+pub enum SpecializationMismatch {
+    UnknownConstant { stage: vk::ShaderStageFlags, constant_id: u32 },
+    TypeMismatch { stage: vk::ShaderStageFlags, constant_id: u32, name: String, expected: ScalarType, actual: ScalarType },
+}
+impl std::error::Error for SpecializationMismatch {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for SpecializationMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpecializationMismatch::UnknownConstant { stage, constant_id } =>
+                write!(f, "{:?} shader has no specialization constant {}", stage, constant_id),
+            SpecializationMismatch::TypeMismatch { stage, constant_id, name, expected, actual } =>
+                write!(f, "{:?} specialization constant `{}` ({}) is {:?}, but was given {:?}", stage, name, constant_id, expected, actual),
+        }
+    }
+}
+
+/// Values for a shader's `layout(constant_id = N) const` declarations, packed the way
+/// `VkSpecializationInfo` expects them. Pipelines created from the same module with
+/// different values are variants without recompiling the GLSL.
+#[derive(Debug, Clone, Default)]
+pub struct SpecializationConstants {
+    entries: Vec<vk::SpecializationMapEntry>,
+    types: Vec<ScalarType>,
+    data: Vec<u8>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set<T: SpecializationValue>(mut self, constant_id: u32, value: T) -> Self {
+        let bytes = value.to_bytes();
+        if let Some(idx) = self.entries.iter().position(|entry| entry.constant_id == constant_id) {
+            self.entries.remove(idx);
+            self.types.remove(idx);
+        }
+        // values are appended, a replaced value just leaves its old bytes unreferenced
+        self.entries.push(vk::SpecializationMapEntry::builder()
+            .constant_id(constant_id)
+            .offset(self.data.len() as u32)
+            .size(bytes.len())
+            .build());
+        self.types.push(T::SCALAR_TYPE);
+        self.data.extend(bytes);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The value for `constant_id` as `u32`, if it was set as `u32`.
+    pub fn get_u32(&self, constant_id: u32) -> Option<u32> {
+        let idx = self.entries.iter().position(|entry| entry.constant_id == constant_id)?;
+        if self.types[idx] != ScalarType::UInt {
+            return None;
+        }
+        let offset = self.entries[idx].offset as usize;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.data[offset..offset + 4]);
+        Some(u32::from_ne_bytes(bytes))
+    }
+
+    /// Checks that the shader declares every constant with the type it was given.
+    pub fn check(&self, reflection: &ShaderReflection) -> Result<(), SpecializationMismatch> {
+        for (entry, actual) in self.entries.iter().zip(&self.types) {
+            match reflection.specialization_constants.iter().find(|constant| constant.constant_id == entry.constant_id) {
+                None => return Err(SpecializationMismatch::UnknownConstant { stage: reflection.stage, constant_id: entry.constant_id }),
+                Some(constant) if constant.scalar_type != *actual => return Err(SpecializationMismatch::TypeMismatch {
+                    stage: reflection.stage,
+                    constant_id: entry.constant_id,
+                    name: constant.name.clone(),
+                    expected: constant.scalar_type,
+                    actual: *actual,
+                }),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Workgroup size of a compute shader after specialization.
+    pub fn local_size(&self, reflection: &ShaderReflection) -> [u32; 3] {
+        let mut local_size = reflection.local_size.unwrap_or([1, 1, 1]);
+        for (axis, constant_id) in reflection.local_size_ids.iter().enumerate() {
+            if let Some(size) = constant_id.and_then(|constant_id| self.get_u32(constant_id)) {
+                local_size[axis] = size;
+            }
+        }
+        local_size
+    }
+
+    /// The returned struct points into `self`, which has to outlive it.
+    pub fn info(&self) -> vk::SpecializationInfo {
+        vk::SpecializationInfo::builder()
+            .map_entries(&self.entries)
+            .data(&self.data)
+            .build()
+    }
+}
@@ -0,0 +1,113 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::reflection::SPIRV_MAGIC;
+
+#[derive(Debug)]
+pub enum SpirvError {
+    Io(io::Error),
+    Misaligned(usize),
+    InvalidMagic(u32),
+}
+impl std::error::Error for SpirvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpirvError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpirvError::Io(err) => write!(f, "Failed to read SPIR-V: {}", err),
+            SpirvError::Misaligned(len) => write!(f, "SPIR-V size of {} bytes is not a multiple of 4", len),
+            SpirvError::InvalidMagic(magic) => write!(f, "Invalid SPIR-V magic number {:#010x}", magic),
+        }
+    }
+}
+impl From<io::Error> for SpirvError {
+    fn from(err: io::Error) -> Self {
+        SpirvError::Io(err)
+    }
+}
+
+/// Converts a SPIR-V binary to words. Modules written with the opposite endianness are byte swapped.
+pub fn read_spirv(bytes: &[u8]) -> Result<Vec<u32>, SpirvError> {
+    if bytes.is_empty() || !bytes.len().is_multiple_of(4) {
+        return Err(SpirvError::Misaligned(bytes.len()));
+    }
+    let words: Vec<u32> = bytes.chunks_exact(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect();
+    match words[0] {
+        SPIRV_MAGIC => Ok(words),
+        magic if magic.swap_bytes() == SPIRV_MAGIC => Ok(words.into_iter().map(u32::swap_bytes).collect()),
+        magic => Err(SpirvError::InvalidMagic(magic)),
+    }
+}
+
+pub fn load_spirv<P: AsRef<Path>>(path: P) -> Result<Vec<u32>, SpirvError> {
+    read_spirv(&std::fs::read(path)?)
+}
+
+pub fn write_spirv<P: AsRef<Path>>(path: P, words: &[u32]) -> io::Result<()> {
+    let bytes: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+    std::fs::write(path, bytes)
+}
+
+/// Where `--compile-shaders` writes the SPIR-V unless told otherwise, and where builds
+/// without the shaderc feature load it from: `shaders` next to the executable.
+pub fn default_spirv_dir() -> io::Result<PathBuf> {
+    Ok(std::env::current_exe()?.with_file_name("shaders"))
+}
+
+/// Name of the precompiled file for a shader source, e.g. `triangle.vs.spv`.
+pub fn spirv_filename(filename: &str) -> String {
+    format!("{}.spv", filename)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WORDS: [u32; 3] = [SPIRV_MAGIC, 0x0001_0300, 0x1234_5678];
+
+    fn bytes(words: &[u32], to_bytes: fn(u32) -> [u8; 4]) -> Vec<u8> {
+        words.iter().flat_map(|word| to_bytes(*word).to_vec()).collect()
+    }
+
+    #[test]
+    fn little_and_big_endian_modules() {
+        assert_eq!(read_spirv(&bytes(&WORDS, u32::to_le_bytes)).unwrap(), WORDS);
+        assert_eq!(read_spirv(&bytes(&WORDS, u32::to_be_bytes)).unwrap(), WORDS);
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut words = WORDS;
+        words[0] = 0x0203_0723;
+        assert!(matches!(read_spirv(&bytes(&words, u32::to_le_bytes)), Err(SpirvError::InvalidMagic(0x0203_0723))));
+        // a GLSL source passed by mistake
+        assert!(matches!(read_spirv(b"#version 450\n\0\0\0"), Err(SpirvError::InvalidMagic(_))));
+    }
+
+    #[test]
+    fn lengths_have_to_be_whole_words() {
+        let valid = bytes(&WORDS, u32::to_le_bytes);
+        for len in &[0, 1, 3, 5, 11] {
+            assert!(matches!(read_spirv(&valid[..*len]), Err(SpirvError::Misaligned(misaligned)) if misaligned == *len), "{} bytes", len);
+        }
+        assert!(read_spirv(&valid[..4]).is_ok());
+    }
+
+    #[test]
+    fn written_modules_load_again() {
+        let path = std::env::temp_dir().join(format!("vulkan-experiments-{}-roundtrip.spv", std::process::id()));
+        write_spirv(&path, &WORDS).unwrap();
+        let loaded = load_spirv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), WORDS);
+        assert!(matches!(load_spirv(&path), Err(SpirvError::Io(_))));
+    }
+}
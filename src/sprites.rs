@@ -0,0 +1,455 @@
+use std::f32::consts::PI;
+
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::warn;
+
+use crate::{
+    buffer::Buffer,
+    descriptors::{allocate_descriptor_sets, create_descriptor_pool, create_descriptor_set_layouts, write_combined_image_sampler},
+    frame::MAX_FRAMES_IN_FLIGHT,
+    pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines},
+    reflection::{DescriptorBinding, PipelineReflection},
+};
+
+/// The vertex shader, the fragment shader of sprites and the one of shapes.
+pub const SPRITE_SHADERS: [&str; 3] = ["sprite.vs", "sprite.fs", "shape.fs"];
+
+/// Bytes the vertex buffers of a frame are created with at least, they grow when a frame needs more.
+const MIN_BUFFER_SIZE: usize = 64 * 1024;
+/// Textures that can be added with `add_texture`.
+const MAX_TEXTURES: u32 = 64;
+/// Window pixels between the points of a circle's outline, larger circles get more of them.
+const CIRCLE_SEGMENT_LENGTH: f32 = 4.0;
+const MIN_CIRCLE_SEGMENTS: usize = 12;
+const MAX_CIRCLE_SEGMENTS: usize = 256;
+
+/// A texture added to a `SpriteBatch` with `add_texture`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpriteTexture(usize);
+
+/// Matches the vertex inputs of sprite.vs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SpriteVertex {
+    /// In window pixels.
+    position: [f32; 2],
+    tex_coord: [f32; 2],
+    color: [f32; 4],
+}
+
+/// Matches the push constant block in sprite.vs.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SpriteParameters {
+    projection: [[f32; 4]; 4],
+}
+
+/// A textured quad.
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub texture: SpriteTexture,
+    /// Where `origin` ends up, in window pixels.
+    pub position: [f32; 2],
+    /// In window pixels, negative to mirror the sprite.
+    pub size: [f32; 2],
+    /// The point the sprite is placed by and rotated around, from (0, 0) at the top left
+    /// corner to (1, 1) at the bottom right one.
+    pub origin: [f32; 2],
+    /// In radians, clockwise since y points down in window pixels.
+    pub rotation: f32,
+    /// The part of the texture drawn, left, top, right, bottom in normalized coordinates.
+    pub uv_rect: [f32; 4],
+    /// Multiplies the texture, with straight alpha.
+    pub tint: [f32; 4],
+    /// Higher layers are drawn over lower ones.
+    pub layer: i32,
+}
+
+impl Sprite {
+    /// The whole texture untinted and unrotated, placed by its top left corner on layer 0.
+    pub fn new(texture: SpriteTexture, position: [f32; 2], size: [f32; 2]) -> Self {
+        Self {
+            texture,
+            position,
+            size,
+            origin: [0.0, 0.0],
+            rotation: 0.0,
+            uv_rect: [0.0, 0.0, 1.0, 1.0],
+            tint: [1.0; 4],
+            layer: 0,
+        }
+    }
+
+    pub fn origin(mut self, origin: [f32; 2]) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn uv_rect(mut self, uv_rect: [f32; 4]) -> Self {
+        self.uv_rect = uv_rect;
+        self
+    }
+
+    pub fn tint(mut self, tint: [f32; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+/// The vertices of a sprite or shape, textured by `texture` or a plain color without one.
+struct Item {
+    layer: i32,
+    texture: Option<SpriteTexture>,
+    first_vertex: usize,
+    vertex_count: usize,
+}
+
+/// Consecutive vertices in the buffer of a frame drawn with one draw call.
+struct Batch {
+    texture: Option<SpriteTexture>,
+    first_vertex: u32,
+    vertex_count: u32,
+}
+
+/// Draws 2D sprites and shapes over the final image, in window pixels with y pointing down.
+/// Everything added during a frame is sorted by layer and, within a layer, by texture, then
+/// drawn with a draw call per run of the same texture. Shapes are drawn by a pipeline without
+/// a texture and sort before the sprites of their layer, so overlapping sprites with different
+/// textures or shapes need different layers to be drawn in a specific order.
+pub struct SpriteBatch {
+    items: Vec<Item>,
+    /// Of `items`, in the order they were added.
+    vertices: Vec<SpriteVertex>,
+    batches: Vec<Batch>,
+    descriptor_bindings: Vec<DescriptorBinding>,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_pool: vk::DescriptorPool,
+    /// One per texture, indexed by `SpriteTexture`.
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    sampler: vk::Sampler,
+    sprite_pipeline_layout: vk::PipelineLayout,
+    sprite_pipeline: vk::Pipeline,
+    shape_pipeline_layout: vk::PipelineLayout,
+    shape_pipeline: vk::Pipeline,
+    /// Vertex buffer of each frame in flight, created when something is first drawn.
+    vertex_buffers: Vec<Option<Buffer>>,
+}
+
+impl SpriteBatch {
+    /// `reflection` is the one of the vertex shader and `sprite_shader`. The shader modules can
+    /// be destroyed once this returns. `sampler` is used for every texture.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, sprite_shader: vk::ShaderModule, shape_shader: vk::ShaderModule, reflection: &PipelineReflection, sampler: vk::Sampler) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut result = Self {
+            items: Vec::new(),
+            vertices: Vec::new(),
+            batches: Vec::new(),
+            descriptor_bindings: reflection.descriptor_bindings.clone(),
+            descriptor_set_layouts: Vec::new(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_sets: Vec::new(),
+            sampler,
+            sprite_pipeline_layout: vk::PipelineLayout::null(),
+            sprite_pipeline: vk::Pipeline::null(),
+            shape_pipeline_layout: vk::PipelineLayout::null(),
+            shape_pipeline: vk::Pipeline::null(),
+            vertex_buffers: (0..MAX_FRAMES_IN_FLIGHT).map(|_| None).collect(),
+        };
+        if let Err(err) = result.create_resources(device, pipeline_cache, render_pass, vertex_shader, sprite_shader, shape_shader, reflection) {
+            result.destroy(device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_resources(&mut self, device: &ash::Device, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, sprite_shader: vk::ShaderModule, shape_shader: vk::ShaderModule, reflection: &PipelineReflection) -> Result<(), Box<dyn std::error::Error>> {
+        if reflection.descriptor_bindings.iter().any(|binding| binding.set != 0) {
+            return Err(format!("{}: all inputs have to be in descriptor set 0", SPRITE_SHADERS[1]).into());
+        }
+        self.descriptor_set_layouts = create_descriptor_set_layouts(device, reflection)?;
+        self.descriptor_pool = create_descriptor_pool(device, &reflection.descriptor_bindings, MAX_TEXTURES)?;
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        self.sprite_pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None) }?;
+        // shapes only use the push constants of the vertex shader
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        self.shape_pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None) }?;
+
+        let bindings = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<SpriteVertex>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()];
+        let attributes = [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(0)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(8)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(2)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(16)
+                .build(),
+        ];
+        let builders: Vec<GraphicsPipelineBuilder> = [(self.sprite_pipeline_layout, sprite_shader), (self.shape_pipeline_layout, shape_shader)].iter().map(|(layout, fragment_shader)| {
+            GraphicsPipelineBuilder::new(*layout, render_pass)
+                .stage(vk::ShaderStageFlags::VERTEX, vertex_shader)
+                .stage(vk::ShaderStageFlags::FRAGMENT, *fragment_shader)
+                .vertex_input(&bindings, &attributes)
+                .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+                .color_attachments(&[GraphicsPipelineBuilder::alpha_blend_attachment()])
+        }).collect();
+        let pipelines = create_graphics_pipelines(device, pipeline_cache, &builders)?;
+        self.sprite_pipeline = pipelines[0];
+        self.shape_pipeline = pipelines[1];
+        Ok(())
+    }
+
+    /// Makes the image behind `view` usable by sprites, it has to outlive the batch. Fails
+    /// after `MAX_TEXTURES` textures.
+    pub fn add_texture(&mut self, device: &ash::Device, view: vk::ImageView) -> Result<SpriteTexture, vk::Result> {
+        let descriptor_set = allocate_descriptor_sets(device, self.descriptor_pool, &self.descriptor_set_layouts)?[0];
+        for binding in &self.descriptor_bindings {
+            match (binding.name.as_str(), binding.descriptor_type) {
+                ("spriteTexture", vk::DescriptorType::COMBINED_IMAGE_SAMPLER) => write_combined_image_sampler(device, descriptor_set, binding.binding, view, self.sampler),
+                (name, descriptor_type) => warn!("{}: nothing to bind to {} ({:?})", SPRITE_SHADERS[1], name, descriptor_type),
+            }
+        }
+        self.descriptor_sets.push(descriptor_set);
+        Ok(SpriteTexture(self.descriptor_sets.len() - 1))
+    }
+
+    /// Drops the sprites and shapes of the previous frame.
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.vertices.clear();
+    }
+
+    fn add(&mut self, layer: i32, texture: Option<SpriteTexture>, vertices: &[SpriteVertex]) {
+        self.items.push(Item { layer, texture, first_vertex: self.vertices.len(), vertex_count: vertices.len() });
+        self.vertices.extend_from_slice(vertices);
+    }
+
+    pub fn draw_sprite(&mut self, sprite: &Sprite) {
+        let (sin, cos) = sprite.rotation.sin_cos();
+        let [left, top, right, bottom] = sprite.uv_rect;
+        let corners = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        let tex_coords = [[left, top], [right, top], [right, bottom], [left, bottom]];
+        let positions = corners.map(|corner: [f32; 2]| {
+            let x = (corner[0] - sprite.origin[0]) * sprite.size[0];
+            let y = (corner[1] - sprite.origin[1]) * sprite.size[1];
+            [sprite.position[0] + x * cos - y * sin, sprite.position[1] + x * sin + y * cos]
+        });
+        self.add(sprite.layer, Some(sprite.texture), &quad(positions, tex_coords, sprite.tint));
+    }
+
+    /// `rect` is the top left corner and size in window pixels.
+    pub fn fill_rect(&mut self, rect: [f32; 4], color: [f32; 4], layer: i32) {
+        self.add(layer, None, &rect_vertices(rect, color));
+    }
+
+    /// Draws the outline of `rect` `thickness` pixels wide, inside the rectangle.
+    pub fn stroke_rect(&mut self, rect: [f32; 4], thickness: f32, color: [f32; 4], layer: i32) {
+        let [x, y, width, height] = rect;
+        let thickness = thickness.min(width / 2.0).min(height / 2.0);
+        let sides = [
+            [x, y, width, thickness],
+            [x, y + height - thickness, width, thickness],
+            [x, y + thickness, thickness, height - 2.0 * thickness],
+            [x + width - thickness, y + thickness, thickness, height - 2.0 * thickness],
+        ];
+        let vertices: Vec<SpriteVertex> = sides.iter().flat_map(|side| rect_vertices(*side, color).to_vec()).collect();
+        self.add(layer, None, &vertices);
+    }
+
+    pub fn fill_circle(&mut self, center: [f32; 2], radius: f32, color: [f32; 4], layer: i32) {
+        let points = circle_points(center, radius);
+        let vertices: Vec<SpriteVertex> = points.iter().zip(points.iter().cycle().skip(1))
+            .flat_map(|(from, to)| vec![vertex(center, color), vertex(*from, color), vertex(*to, color)])
+            .collect();
+        self.add(layer, None, &vertices);
+    }
+
+    /// Draws the outline of the circle `thickness` pixels wide, inside the circle.
+    pub fn stroke_circle(&mut self, center: [f32; 2], radius: f32, thickness: f32, color: [f32; 4], layer: i32) {
+        if radius <= 0.0 {
+            return;
+        }
+        let outer = circle_points(center, radius);
+        let inner_radius = (radius - thickness).max(0.0);
+        // the inner points are on the same angles as the outer ones
+        let inner: Vec<[f32; 2]> = outer.iter()
+            .map(|point| [center[0] + (point[0] - center[0]) * inner_radius / radius, center[1] + (point[1] - center[1]) * inner_radius / radius])
+            .collect();
+        let vertices: Vec<SpriteVertex> = (0..outer.len()).flat_map(|idx| {
+            let next = (idx + 1) % outer.len();
+            quad([outer[idx], outer[next], inner[next], inner[idx]], [[0.0; 2]; 4], color).to_vec()
+        }).collect();
+        self.add(layer, None, &vertices);
+    }
+
+    /// Draws a line `thickness` pixels wide centered on the segment from `from` to `to`, with flat ends.
+    pub fn draw_line(&mut self, from: [f32; 2], to: [f32; 2], thickness: f32, color: [f32; 4], layer: i32) {
+        let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+        let normal = [-dy / length * thickness / 2.0, dx / length * thickness / 2.0];
+        let corners = [
+            [from[0] + normal[0], from[1] + normal[1]],
+            [to[0] + normal[0], to[1] + normal[1]],
+            [to[0] - normal[0], to[1] - normal[1]],
+            [from[0] - normal[0], from[1] - normal[1]],
+        ];
+        self.add(layer, None, &quad(corners, [[0.0; 2]; 4], color));
+    }
+
+    /// Sorts what was added during this frame and writes it to the vertex buffer of `frame`,
+    /// which the GPU has to be done with.
+    pub fn prepare(&mut self, device: &ash::Device, memory_properties: &vk::PhysicalDeviceMemoryProperties, frame: usize) -> Result<(), Box<dyn std::error::Error>> {
+        self.batches.clear();
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+        // stable, so the items of a layer and texture stay in the order they were added
+        self.items.sort_by_key(|item| (item.layer, item.texture));
+        let mut vertices = Vec::with_capacity(self.vertices.len());
+        for item in &self.items {
+            match self.batches.last_mut() {
+                Some(batch) if batch.texture == item.texture => batch.vertex_count += item.vertex_count as u32,
+                _ => self.batches.push(Batch { texture: item.texture, first_vertex: vertices.len() as u32, vertex_count: item.vertex_count as u32 }),
+            }
+            vertices.extend_from_slice(&self.vertices[item.first_vertex..item.first_vertex + item.vertex_count]);
+        }
+        let size = std::mem::size_of_val(&vertices[..]);
+        let buffer = &mut self.vertex_buffers[frame];
+        if buffer.as_ref().map_or(0, |buffer| buffer.size) < size as vk::DeviceSize {
+            let larger = Buffer::host_visible(device, memory_properties, size.max(MIN_BUFFER_SIZE).next_power_of_two() as vk::DeviceSize, vk::BufferUsageFlags::VERTEX_BUFFER)?;
+            if let Some(previous) = buffer.replace(larger) {
+                previous.destroy(device);
+            }
+        }
+        buffer.as_ref().unwrap().write(device, &vertices)?;
+        Ok(())
+    }
+
+    /// Draws what `prepare` wrote for `frame`. Has to be recorded inside the sprites' render pass.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, extent: vk::Extent2D) {
+        if self.batches.is_empty() {
+            return;
+        }
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D { offset: vk::Offset2D::default(), extent }];
+        let parameters = SpriteParameters { projection: window_projection(extent) };
+        let bytes = unsafe { std::slice::from_raw_parts(&parameters as *const SpriteParameters as *const u8, std::mem::size_of::<SpriteParameters>()) };
+        let mut bound_pipeline = vk::Pipeline::null();
+        let mut bound_texture = None;
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffers[frame].as_ref().unwrap().buffer], &[0]);
+            for batch in &self.batches {
+                let (pipeline, pipeline_layout) = match batch.texture {
+                    Some(_) => (self.sprite_pipeline, self.sprite_pipeline_layout),
+                    None => (self.shape_pipeline, self.shape_pipeline_layout),
+                };
+                if pipeline != bound_pipeline {
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+                    device.cmd_push_constants(command_buffer, pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, bytes);
+                    bound_pipeline = pipeline;
+                    // the other pipeline's layout has no descriptor sets
+                    bound_texture = None;
+                }
+                if let Some(texture) = batch.texture.filter(|texture| Some(*texture) != bound_texture) {
+                    device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline_layout, 0, &[self.descriptor_sets[texture.0]], &[]);
+                    bound_texture = Some(texture);
+                }
+                device.cmd_draw(command_buffer, batch.vertex_count, 1, batch.first_vertex, 0);
+            }
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.shape_pipeline, None);
+            device.destroy_pipeline(self.sprite_pipeline, None);
+            device.destroy_pipeline_layout(self.shape_pipeline_layout, None);
+            device.destroy_pipeline_layout(self.sprite_pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            for descriptor_set_layout in &self.descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(*descriptor_set_layout, None);
+            }
+        }
+        for buffer in self.vertex_buffers.iter().flatten() {
+            buffer.destroy(device);
+        }
+    }
+}
+
+/// Orthographic projection from window pixels of a window of `extent`, with y pointing down
+/// like in Vulkan's clip space, so the top left corner is (0, 0). Column major.
+fn window_projection(extent: vk::Extent2D) -> [[f32; 4]; 4] {
+    [
+        [2.0 / extent.width as f32, 0.0, 0.0, 0.0],
+        [0.0, 2.0 / extent.height as f32, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [-1.0, -1.0, 0.0, 1.0],
+    ]
+}
+
+fn vertex(position: [f32; 2], color: [f32; 4]) -> SpriteVertex {
+    SpriteVertex { position, tex_coord: [0.0; 2], color }
+}
+
+/// Two triangles covering the quad with the corners `positions` in order around it.
+fn quad(positions: [[f32; 2]; 4], tex_coords: [[f32; 2]; 4], color: [f32; 4]) -> [SpriteVertex; 6] {
+    let corner = |idx: usize| SpriteVertex { position: positions[idx], tex_coord: tex_coords[idx], color };
+    [corner(0), corner(1), corner(2), corner(2), corner(3), corner(0)]
+}
+
+fn rect_vertices([x, y, width, height]: [f32; 4], color: [f32; 4]) -> [SpriteVertex; 6] {
+    quad([[x, y], [x + width, y], [x + width, y + height], [x, y + height]], [[0.0; 2]; 4], color)
+}
+
+/// Points around the outline of a circle, closer together than `CIRCLE_SEGMENT_LENGTH` pixels
+/// unless there'd be more than `MAX_CIRCLE_SEGMENTS` of them.
+fn circle_points(center: [f32; 2], radius: f32) -> Vec<[f32; 2]> {
+    let segments = ((2.0 * PI * radius / CIRCLE_SEGMENT_LENGTH).ceil() as usize).clamp(MIN_CIRCLE_SEGMENTS, MAX_CIRCLE_SEGMENTS);
+    (0..segments).map(|idx| {
+        let (sin, cos) = (2.0 * PI * idx as f32 / segments as f32).sin_cos();
+        [center[0] + radius * cos, center[1] + radius * sin]
+    }).collect()
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+use log::warn;
+use rusttype::{Font, Scale, point};
+
+use crate::{
+    buffer::Buffer,
+    descriptors::{allocate_descriptor_sets, create_descriptor_pool, create_descriptor_set_layouts, write_combined_image_sampler},
+    frame::MAX_FRAMES_IN_FLIGHT,
+    image::Image,
+    pipeline_builder::{GraphicsPipelineBuilder, create_graphics_pipelines},
+    reflection::{DescriptorBinding, PipelineReflection},
+    texture::TextureLoader,
+};
+
+pub const TEXT_SHADERS: [&str; 2] = ["text.vs", "text.fs"];
+
+/// Pixels per em the glyphs are rasterized at for the atlas, text of every size is drawn from them.
+const ATLAS_FONT_SIZE: f32 = 48.0;
+/// Pixels the distance field reaches out from the outlines, which is also the padding around each glyph.
+const SDF_SPREAD: usize = 6;
+const ATLAS_WIDTH: usize = 512;
+/// The atlas has the printable ASCII characters, the others are drawn as `?`.
+const FIRST_CHAR: char = ' ';
+const LAST_CHAR: char = '~';
+const REPLACEMENT_CHAR: char = '?';
+/// Glyphs per frame, the ones after that are dropped.
+const MAX_GLYPHS: usize = 16384;
+
+/// A glyph in the atlas, in pixels at `ATLAS_FONT_SIZE`.
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    /// From the pen position on the baseline to the top left corner of the glyph's padded rectangle.
+    offset: [f32; 2],
+    /// Size of the padded rectangle, zero for glyphs without an outline like the space.
+    size: [f32; 2],
+    /// The padded rectangle in normalized atlas coordinates, left, top, right, bottom.
+    tex_rect: [f32; 4],
+    advance: f32,
+}
+
+/// A TrueType font with its glyphs rasterized into a signed distance field atlas.
+pub struct SdfFont {
+    font: Font<'static>,
+    glyphs: HashMap<char, Glyph>,
+    ascent: f32,
+    /// From one baseline to the next.
+    line_height: f32,
+    atlas_extent: vk::Extent2D,
+    /// Distance to the nearest outline, 0.5 on it, above inside the glyph. Reaches 0 and 1
+    /// at `SDF_SPREAD` pixels from the outline.
+    atlas: Vec<u8>,
+}
+
+impl SdfFont {
+    /// Rasterizes the characters from `FIRST_CHAR` to `LAST_CHAR` of the TrueType or OpenType font in `data`.
+    pub fn new(data: Vec<u8>) -> Result<Self, Box<dyn std::error::Error>> {
+        let font = Font::from_bytes(data)?;
+        let scale = Scale::uniform(ATLAS_FONT_SIZE);
+        let v_metrics = font.v_metrics(scale);
+        let padding = SDF_SPREAD as f32;
+
+        let mut fields = Vec::new();
+        for c in FIRST_CHAR..=LAST_CHAR {
+            let glyph = font.glyph(c).scaled(scale);
+            let advance = glyph.h_metrics().advance_width;
+            let glyph = glyph.positioned(point(0.0, 0.0));
+            let field = glyph.pixel_bounding_box().map(|bounds| {
+                let (width, height) = (bounds.width() as usize + 2 * SDF_SPREAD, bounds.height() as usize + 2 * SDF_SPREAD);
+                let mut coverage = vec![0.0; width * height];
+                glyph.draw(|x, y, value| coverage[(y as usize + SDF_SPREAD) * width + x as usize + SDF_SPREAD] = value);
+                ([bounds.min.x as f32 - padding, bounds.min.y as f32 - padding], width, height, signed_distance_field(&coverage, width, height))
+            });
+            fields.push((c, advance, field));
+        }
+
+        let sizes: Vec<(usize, usize)> = fields.iter().map(|(_, _, field)| field.as_ref().map_or((0, 0), |(_, width, height, _)| (*width, *height))).collect();
+        let (positions, atlas_height) = pack(&sizes, ATLAS_WIDTH);
+        let atlas_height = std::cmp::max(atlas_height, 1);
+        let mut atlas = vec![0; ATLAS_WIDTH * atlas_height];
+        let mut glyphs = HashMap::new();
+        for ((c, advance, field), (x, y)) in fields.into_iter().zip(positions) {
+            let mut glyph = Glyph { offset: [0.0; 2], size: [0.0; 2], tex_rect: [0.0; 4], advance };
+            if let Some((offset, width, height, distances)) = field {
+                for (row, distances) in distances.chunks(width).enumerate() {
+                    let start = (y + row) * ATLAS_WIDTH + x;
+                    atlas[start..start + width].copy_from_slice(distances);
+                }
+                glyph.offset = offset;
+                glyph.size = [width as f32, height as f32];
+                glyph.tex_rect = [
+                    x as f32 / ATLAS_WIDTH as f32,
+                    y as f32 / atlas_height as f32,
+                    (x + width) as f32 / ATLAS_WIDTH as f32,
+                    (y + height) as f32 / atlas_height as f32,
+                ];
+            }
+            glyphs.insert(c, glyph);
+        }
+        Ok(Self {
+            font,
+            glyphs,
+            ascent: v_metrics.ascent,
+            line_height: v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
+            atlas_extent: vk::Extent2D { width: ATLAS_WIDTH as u32, height: atlas_height as u32 },
+            atlas,
+        })
+    }
+
+    /// The glyph of `c`, or of `REPLACEMENT_CHAR` if it isn't in the atlas.
+    fn glyph(&self, c: char) -> (char, Glyph) {
+        match self.glyphs.get(&c) {
+            Some(glyph) => (c, *glyph),
+            None => (REPLACEMENT_CHAR, self.glyphs[&REPLACEMENT_CHAR]),
+        }
+    }
+
+    /// Added to the advance of `first` when it's followed by `second`.
+    fn kerning(&self, first: char, second: char) -> f32 {
+        self.font.pair_kerning(Scale::uniform(ATLAS_FONT_SIZE), first, second)
+    }
+}
+
+/// Signed distances to the edge of the `coverage` of a `width` by `height` bitmap, encoded
+/// as in the `SdfFont` atlas. Pixels with at least half coverage count as inside.
+fn signed_distance_field(coverage: &[f32], width: usize, height: usize) -> Vec<u8> {
+    let inside: Vec<bool> = coverage.iter().map(|coverage| *coverage >= 0.5).collect();
+    let outside: Vec<bool> = inside.iter().map(|inside| !inside).collect();
+    let to_inside = nearest_distances(&inside, width, height);
+    let to_outside = nearest_distances(&outside, width, height);
+    (0..coverage.len()).map(|idx| {
+        // the edge runs halfway between an inside and an outside pixel
+        let distance = if inside[idx] { to_outside[idx] - 0.5 } else { 0.5 - to_inside[idx] };
+        ((0.5 + distance / (2.0 * SDF_SPREAD as f32)).clamp(0.0, 1.0) * 255.0).round() as u8
+    }).collect()
+}
+
+/// Distance from every pixel to the nearest one set in `seeds`, with the 8-point sequential
+/// Euclidean distance transform: every pixel takes the offset to the nearest seed from its
+/// neighbors in a forward and a backward pass.
+fn nearest_distances(seeds: &[bool], width: usize, height: usize) -> Vec<f32> {
+    // far enough to be out of any glyph's reach, small enough not to overflow when squared
+    const FAR: i32 = 1 << 14;
+    let mut offsets: Vec<(i32, i32)> = seeds.iter().map(|seed| if *seed { (0, 0) } else { (FAR, FAR) }).collect();
+    let length_squared = |(x, y): (i32, i32)| x * x + y * y;
+    let compare = |offsets: &mut [(i32, i32)], x: usize, y: usize, dx: i32, dy: i32| {
+        let (neighbor_x, neighbor_y) = (x as i32 + dx, y as i32 + dy);
+        if neighbor_x < 0 || neighbor_y < 0 || neighbor_x >= width as i32 || neighbor_y >= height as i32 {
+            return;
+        }
+        let neighbor = offsets[neighbor_y as usize * width + neighbor_x as usize];
+        let candidate = (neighbor.0 + dx, neighbor.1 + dy);
+        let current = &mut offsets[y * width + x];
+        if length_squared(candidate) < length_squared(*current) {
+            *current = candidate;
+        }
+    };
+    for y in 0..height {
+        for x in 0..width {
+            for (dx, dy) in &[(-1, 0), (0, -1), (-1, -1), (1, -1)] {
+                compare(&mut offsets, x, y, *dx, *dy);
+            }
+        }
+        for x in (0..width).rev() {
+            compare(&mut offsets, x, y, 1, 0);
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            for (dx, dy) in &[(1, 0), (0, 1), (-1, 1), (1, 1)] {
+                compare(&mut offsets, x, y, *dx, *dy);
+            }
+        }
+        for x in 0..width {
+            compare(&mut offsets, x, y, -1, 0);
+        }
+    }
+    offsets.into_iter().map(|offset| (length_squared(offset) as f32).sqrt()).collect()
+}
+
+/// Places rectangles of `sizes` in rows from the top left of an area `width` wide, the tallest
+/// first so the rows waste little space. Returns their top left corners and the height used.
+fn pack(sizes: &[(usize, usize)], width: usize) -> (Vec<(usize, usize)>, usize) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|idx| std::cmp::Reverse(sizes[*idx].1));
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut row_height) = (0, 0, 0);
+    for idx in order {
+        let (rect_width, rect_height) = sizes[idx];
+        if rect_width == 0 || rect_height == 0 {
+            continue;
+        }
+        if x + rect_width > width {
+            x = 0;
+            y += row_height;
+            row_height = 0;
+        }
+        positions[idx] = (x, y);
+        x += rect_width;
+        row_height = std::cmp::max(row_height, rect_height);
+    }
+    (positions, y + row_height)
+}
+
+/// Matches the instance inputs of text.vs.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct GlyphInstance {
+    /// Top left corner and size in window pixels.
+    rect: [f32; 4],
+    tex_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+/// Matches the push constant block in text.vs.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TextParameters {
+    screen_size: [f32; 2],
+}
+
+/// Draws text with a signed distance field font, so it stays sharp at any size. The text of a
+/// frame is collected with `draw_text` and drawn with a single instanced draw, a quad per glyph.
+pub struct TextRenderer {
+    font: SdfFont,
+    atlas: Option<Image>,
+    descriptor_bindings: Vec<DescriptorBinding>,
+    descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    /// Instance buffer of each frame in flight.
+    buffers: Vec<Buffer>,
+    glyphs: Vec<GlyphInstance>,
+}
+
+impl TextRenderer {
+    /// Uploads the atlas of `font`, sampled with `sampler`. The shader modules can be destroyed once this returns.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(loader: &TextureLoader, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection, sampler: vk::Sampler, font: SdfFont) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut result = Self {
+            font,
+            atlas: None,
+            descriptor_bindings: reflection.descriptor_bindings.clone(),
+            descriptor_set_layouts: Vec::new(),
+            descriptor_pool: vk::DescriptorPool::null(),
+            descriptor_set: vk::DescriptorSet::null(),
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            buffers: Vec::new(),
+            glyphs: Vec::new(),
+        };
+        if let Err(err) = result.create_resources(loader, pipeline_cache, render_pass, vertex_shader, fragment_shader, reflection, sampler) {
+            result.destroy(loader.device);
+            return Err(err);
+        }
+        Ok(result)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_resources(&mut self, loader: &TextureLoader, pipeline_cache: vk::PipelineCache, render_pass: vk::RenderPass, vertex_shader: vk::ShaderModule, fragment_shader: vk::ShaderModule, reflection: &PipelineReflection, sampler: vk::Sampler) -> Result<(), Box<dyn std::error::Error>> {
+        let device = loader.device;
+        if reflection.descriptor_bindings.iter().any(|binding| binding.set != 0) {
+            return Err(format!("{}: all inputs have to be in descriptor set 0", TEXT_SHADERS[1]).into());
+        }
+        let atlas = loader.create(self.font.atlas_extent, vk::Format::R8_UNORM, &[&self.font.atlas], false)?;
+        let atlas_view = atlas.view;
+        self.atlas = Some(atlas);
+        self.descriptor_set_layouts = create_descriptor_set_layouts(device, reflection)?;
+        self.descriptor_pool = create_descriptor_pool(device, &reflection.descriptor_bindings, 1)?;
+        self.descriptor_set = allocate_descriptor_sets(device, self.descriptor_pool, &self.descriptor_set_layouts)?[0];
+        for binding in &self.descriptor_bindings {
+            match (binding.name.as_str(), binding.descriptor_type) {
+                ("glyphAtlas", vk::DescriptorType::COMBINED_IMAGE_SAMPLER) => write_combined_image_sampler(device, self.descriptor_set, binding.binding, atlas_view, sampler),
+                (name, descriptor_type) => warn!("{}: nothing to bind to {} ({:?})", TEXT_SHADERS[1], name, descriptor_type),
+            }
+        }
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(&self.descriptor_set_layouts)
+            .push_constant_ranges(&reflection.push_constant_ranges);
+        self.pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None) }?;
+        let bindings = [vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(std::mem::size_of::<GlyphInstance>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()];
+        let attributes: Vec<vk::VertexInputAttributeDescription> = (0..3).map(|location| vk::VertexInputAttributeDescription::builder()
+            .binding(0)
+            .location(location)
+            .format(vk::Format::R32G32B32A32_SFLOAT)
+            .offset(16 * location)
+            .build()).collect();
+        let builder = GraphicsPipelineBuilder::new(self.pipeline_layout, render_pass)
+            .stage(vk::ShaderStageFlags::VERTEX, vertex_shader)
+            .stage(vk::ShaderStageFlags::FRAGMENT, fragment_shader)
+            .vertex_input(&bindings, &attributes)
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+            .color_attachments(&[GraphicsPipelineBuilder::alpha_blend_attachment()]);
+        self.pipeline = create_graphics_pipelines(device, pipeline_cache, &[builder])?[0];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let size = (MAX_GLYPHS * std::mem::size_of::<GlyphInstance>()) as vk::DeviceSize;
+            self.buffers.push(Buffer::host_visible(device, loader.memory_properties, size, vk::BufferUsageFlags::VERTEX_BUFFER)?);
+        }
+        Ok(())
+    }
+
+    /// Drops the text of the previous frame.
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+    }
+
+    /// Adds `text` with the top left corner of its first line at `position` in window pixels,
+    /// `size` pixels per em. Lines are separated by `\n`. `color` has straight alpha and is
+    /// written to the swapchain image as it is.
+    pub fn draw_text(&mut self, position: [f32; 2], size: f32, color: [f32; 4], text: &str) {
+        let scale = size / ATLAS_FONT_SIZE;
+        let font = &self.font;
+        let (mut x, mut baseline) = (position[0], position[1] + font.ascent * scale);
+        let mut previous = None;
+        for c in text.chars() {
+            if c == '\n' {
+                x = position[0];
+                baseline += font.line_height * scale;
+                previous = None;
+                continue;
+            }
+            let (c, glyph) = font.glyph(c);
+            if let Some(previous) = previous {
+                x += font.kerning(previous, c) * scale;
+            }
+            if glyph.size[0] > 0.0 {
+                self.glyphs.push(GlyphInstance {
+                    rect: [x + glyph.offset[0] * scale, baseline + glyph.offset[1] * scale, glyph.size[0] * scale, glyph.size[1] * scale],
+                    tex_rect: glyph.tex_rect,
+                    color,
+                });
+            }
+            x += glyph.advance * scale;
+            previous = Some(c);
+        }
+    }
+
+    /// Writes the text of this frame to the instance buffer of `frame`, which the GPU has to be done with.
+    pub fn prepare(&mut self, device: &ash::Device, frame: usize) -> Result<(), vk::Result> {
+        if self.glyphs.len() > MAX_GLYPHS {
+            warn!("{} glyphs in a frame, only drawing the first {}", self.glyphs.len(), MAX_GLYPHS);
+            self.glyphs.truncate(MAX_GLYPHS);
+        }
+        if self.glyphs.is_empty() {
+            return Ok(());
+        }
+        self.buffers[frame].write(device, &self.glyphs)
+    }
+
+    /// Draws what `prepare` wrote for `frame`. Has to be recorded inside the text's render pass.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, frame: usize, extent: vk::Extent2D) {
+        if self.glyphs.is_empty() {
+            return;
+        }
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        let scissors = [vk::Rect2D { offset: vk::Offset2D::default(), extent }];
+        let parameters = TextParameters { screen_size: [extent.width as f32, extent.height as f32] };
+        let bytes = unsafe { std::slice::from_raw_parts(&parameters as *const TextParameters as *const u8, std::mem::size_of::<TextParameters>()) };
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &viewports);
+            device.cmd_set_scissor(command_buffer, 0, &scissors);
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline_layout, 0, &[self.descriptor_set], &[]);
+            device.cmd_push_constants(command_buffer, self.pipeline_layout, vk::ShaderStageFlags::VERTEX, 0, bytes);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.buffers[frame].buffer], &[0]);
+            device.cmd_draw(command_buffer, 6, self.glyphs.len() as u32, 0, 0);
+        }
+    }
+
+    pub fn destroy(&self, device: &ash::Device) {
+        unsafe {
+            device.destroy_pipeline(self.pipeline, None);
+            device.destroy_pipeline_layout(self.pipeline_layout, None);
+            device.destroy_descriptor_pool(self.descriptor_pool, None);
+            for descriptor_set_layout in &self.descriptor_set_layouts {
+                device.destroy_descriptor_set_layout(*descriptor_set_layout, None);
+            }
+        }
+        if let Some(atlas) = &self.atlas {
+            atlas.destroy(device);
+        }
+        for buffer in &self.buffers {
+            buffer.destroy(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_distance_field() {
+        // a 20x20 square in the middle of a 40x40 image
+        let size = 40;
+        let coverage: Vec<f32> = (0..size * size).map(|idx| {
+            let (x, y) = (idx % size, idx / size);
+            if (10..30).contains(&x) && (10..30).contains(&y) { 1.0 } else { 0.0 }
+        }).collect();
+        let field = signed_distance_field(&coverage, size, size);
+        let row = &field[20 * size..21 * size];
+        // the edge runs between columns 9 and 10, halfway between the pixels on either side
+        assert_eq!(row[9] as u32 + row[10] as u32, 255);
+        assert!(row[9] < 128 && row[10] > 127);
+        // pixel centers `k - 0.5` away from the edge, saturated from `SDF_SPREAD` on
+        for k in 1..=10 {
+            let saturated = k as f32 - 0.5 >= SDF_SPREAD as f32;
+            assert_eq!(row[9 + k] == 255, saturated, "{} inside", k);
+            assert_eq!(row[10 - k] == 0, saturated, "{} outside", k);
+            assert!(row[9 + k] >= row[8 + k] && row[10 - k] <= row[11 - k]);
+        }
+        // symmetric on the opposite edge
+        assert_eq!(row[29] as u32 + row[30] as u32, 255);
+    }
+
+    #[test]
+    fn packed_rectangles_dont_overlap() {
+        // pseudo random sizes, including empty glyphs like the space
+        let mut seed = 12345u32;
+        let mut next = |max: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((seed >> 16) % (max + 1)) as usize
+        };
+        let sizes: Vec<(usize, usize)> = (0..300).map(|_| (next(60), next(70))).collect();
+        let width = 256;
+        let (positions, height) = pack(&sizes, width);
+        let rects: Vec<(usize, usize, usize, usize)> = positions.iter().zip(&sizes)
+            .filter(|(_, (width, height))| *width > 0 && *height > 0)
+            .map(|((x, y), (width, height))| (*x, *y, *width, *height))
+            .collect();
+        for (idx, &(x, y, rect_width, rect_height)) in rects.iter().enumerate() {
+            assert!(x + rect_width <= width && y + rect_height <= height, "{:?} is outside of {}x{}", rects[idx], width, height);
+            for &(other_x, other_y, other_width, other_height) in &rects[idx + 1..] {
+                let overlap = x < other_x + other_width && other_x < x + rect_width && y < other_y + other_height && other_y < y + rect_height;
+                assert!(!overlap, "{:?} overlaps {:?}", rects[idx], (other_x, other_y, other_width, other_height));
+            }
+        }
+    }
+}
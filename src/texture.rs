@@ -0,0 +1,479 @@
+use ash::{
+    vk,
+    version::{DeviceV1_0, InstanceV1_0},
+};
+use log::warn;
+use std::{
+    collections::HashMap,
+    path::Path,
+};
+
+use crate::{
+    buffer::{Buffer, one_time_submit},
+    image::{Image, mip_extent, mip_level_count, transition_layout},
+};
+
+#[derive(Debug)]
+pub enum TextureError {
+    /// The container's format has no Vulkan equivalent here.
+    UnknownFormat(String),
+    /// The device can't sample the format, e.g. BCn on mobile or ASTC on desktop GPUs.
+    UnsupportedFormat(vk::Format),
+    Supercompressed,
+    NotTwoDimensional,
+    /// A mip level has fewer bytes than its size requires.
+    Truncated { level: u32 },
+    /// A mip level in a KTX2 file doesn't have the size its extent and format require.
+    LevelSize { level: u32, expected: usize, actual: usize },
+}
+impl std::error::Error for TextureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+impl std::fmt::Display for TextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TextureError::UnknownFormat(format) => write!(f, "Unknown texture format {}", format),
+            TextureError::UnsupportedFormat(format) => write!(f, "{:?} textures are not supported by the device", format),
+            TextureError::Supercompressed => write!(f, "Supercompressed textures are not supported"),
+            TextureError::NotTwoDimensional => write!(f, "Only 2D textures without array layers or faces are supported"),
+            TextureError::Truncated { level } => write!(f, "Mip level {} is truncated", level),
+            TextureError::LevelSize { level, expected, actual } => write!(f, "Mip level {} has {} bytes instead of {}", level, actual, expected),
+        }
+    }
+}
+
+/// Width and height in texels and size in bytes of a texel block, 1x1 for uncompressed formats.
+fn block_size(format: vk::Format) -> Option<(u32, u32, u32)> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB | vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => Some((1, 1, 4)),
+        vk::Format::BC1_RGB_UNORM_BLOCK | vk::Format::BC1_RGB_SRGB_BLOCK | vk::Format::BC1_RGBA_UNORM_BLOCK | vk::Format::BC1_RGBA_SRGB_BLOCK
+            | vk::Format::BC4_UNORM_BLOCK | vk::Format::BC4_SNORM_BLOCK => Some((4, 4, 8)),
+        vk::Format::BC2_UNORM_BLOCK | vk::Format::BC2_SRGB_BLOCK | vk::Format::BC3_UNORM_BLOCK | vk::Format::BC3_SRGB_BLOCK
+            | vk::Format::BC5_UNORM_BLOCK | vk::Format::BC5_SNORM_BLOCK | vk::Format::BC6H_UFLOAT_BLOCK | vk::Format::BC6H_SFLOAT_BLOCK
+            | vk::Format::BC7_UNORM_BLOCK | vk::Format::BC7_SRGB_BLOCK => Some((4, 4, 16)),
+        vk::Format::ETC2_R8G8B8_UNORM_BLOCK | vk::Format::ETC2_R8G8B8_SRGB_BLOCK | vk::Format::ETC2_R8G8B8A1_UNORM_BLOCK | vk::Format::ETC2_R8G8B8A1_SRGB_BLOCK
+            | vk::Format::EAC_R11_UNORM_BLOCK | vk::Format::EAC_R11_SNORM_BLOCK => Some((4, 4, 8)),
+        vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK | vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK
+            | vk::Format::EAC_R11G11_UNORM_BLOCK | vk::Format::EAC_R11G11_SNORM_BLOCK => Some((4, 4, 16)),
+        // every ASTC block has 16 bytes, only its footprint differs
+        vk::Format::ASTC_4X4_UNORM_BLOCK | vk::Format::ASTC_4X4_SRGB_BLOCK => Some((4, 4, 16)),
+        vk::Format::ASTC_5X4_UNORM_BLOCK | vk::Format::ASTC_5X4_SRGB_BLOCK => Some((5, 4, 16)),
+        vk::Format::ASTC_5X5_UNORM_BLOCK | vk::Format::ASTC_5X5_SRGB_BLOCK => Some((5, 5, 16)),
+        vk::Format::ASTC_6X5_UNORM_BLOCK | vk::Format::ASTC_6X5_SRGB_BLOCK => Some((6, 5, 16)),
+        vk::Format::ASTC_6X6_UNORM_BLOCK | vk::Format::ASTC_6X6_SRGB_BLOCK => Some((6, 6, 16)),
+        vk::Format::ASTC_8X5_UNORM_BLOCK | vk::Format::ASTC_8X5_SRGB_BLOCK => Some((8, 5, 16)),
+        vk::Format::ASTC_8X6_UNORM_BLOCK | vk::Format::ASTC_8X6_SRGB_BLOCK => Some((8, 6, 16)),
+        vk::Format::ASTC_8X8_UNORM_BLOCK | vk::Format::ASTC_8X8_SRGB_BLOCK => Some((8, 8, 16)),
+        vk::Format::ASTC_10X5_UNORM_BLOCK | vk::Format::ASTC_10X5_SRGB_BLOCK => Some((10, 5, 16)),
+        vk::Format::ASTC_10X6_UNORM_BLOCK | vk::Format::ASTC_10X6_SRGB_BLOCK => Some((10, 6, 16)),
+        vk::Format::ASTC_10X8_UNORM_BLOCK | vk::Format::ASTC_10X8_SRGB_BLOCK => Some((10, 8, 16)),
+        vk::Format::ASTC_10X10_UNORM_BLOCK | vk::Format::ASTC_10X10_SRGB_BLOCK => Some((10, 10, 16)),
+        vk::Format::ASTC_12X10_UNORM_BLOCK | vk::Format::ASTC_12X10_SRGB_BLOCK => Some((12, 10, 16)),
+        vk::Format::ASTC_12X12_UNORM_BLOCK | vk::Format::ASTC_12X12_SRGB_BLOCK => Some((12, 12, 16)),
+        _ => None,
+    }
+}
+
+/// Size in bytes of mip level `level` of an image of `format`, tightly packed.
+fn level_size(extent: vk::Extent2D, format: vk::Format, level: u32) -> Result<usize, TextureError> {
+    let (block_width, block_height, block_bytes) = block_size(format).ok_or_else(|| TextureError::UnknownFormat(format!("{:?}", format)))?;
+    let level_extent = mip_extent(extent, level);
+    Ok(level_extent.width.div_ceil(block_width) as usize * level_extent.height.div_ceil(block_height) as usize * block_bytes as usize)
+}
+
+/// Checks that every level has exactly the size `level_size` gives, as levels are copied
+/// to the image without looking at their length.
+fn check_levels(levels: &[&[u8]], extent: vk::Extent2D, format: vk::Format) -> Result<(), TextureError> {
+    for (level, data) in levels.iter().enumerate() {
+        let expected = level_size(extent, format, level as u32)?;
+        if data.len() != expected {
+            return Err(TextureError::LevelSize { level: level as u32, expected, actual: data.len() });
+        }
+    }
+    Ok(())
+}
+
+/// Splits `level_count` mip levels of `format` that follow each other, each one tightly packed,
+/// like the levels in a DDS file.
+fn split_levels(mut data: &[u8], extent: vk::Extent2D, format: vk::Format, level_count: u32) -> Result<Vec<&[u8]>, TextureError> {
+    let mut levels = Vec::new();
+    for level in 0..level_count {
+        let size = level_size(extent, format, level)?;
+        if data.len() < size {
+            return Err(TextureError::Truncated { level });
+        }
+        levels.push(&data[..size]);
+        data = &data[size..];
+    }
+    Ok(levels)
+}
+
+/// The Vulkan format for the formats DDS files are commonly written with.
+fn dds_format(format: ddsfile::DxgiFormat) -> Option<vk::Format> {
+    use ddsfile::DxgiFormat;
+    Some(match format {
+        DxgiFormat::R8G8B8A8_UNorm => vk::Format::R8G8B8A8_UNORM,
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => vk::Format::R8G8B8A8_SRGB,
+        DxgiFormat::B8G8R8A8_UNorm => vk::Format::B8G8R8A8_UNORM,
+        DxgiFormat::B8G8R8A8_UNorm_sRGB => vk::Format::B8G8R8A8_SRGB,
+        DxgiFormat::BC1_UNorm => vk::Format::BC1_RGBA_UNORM_BLOCK,
+        DxgiFormat::BC1_UNorm_sRGB => vk::Format::BC1_RGBA_SRGB_BLOCK,
+        DxgiFormat::BC2_UNorm => vk::Format::BC2_UNORM_BLOCK,
+        DxgiFormat::BC2_UNorm_sRGB => vk::Format::BC2_SRGB_BLOCK,
+        DxgiFormat::BC3_UNorm => vk::Format::BC3_UNORM_BLOCK,
+        DxgiFormat::BC3_UNorm_sRGB => vk::Format::BC3_SRGB_BLOCK,
+        DxgiFormat::BC4_UNorm => vk::Format::BC4_UNORM_BLOCK,
+        DxgiFormat::BC4_SNorm => vk::Format::BC4_SNORM_BLOCK,
+        DxgiFormat::BC5_UNorm => vk::Format::BC5_UNORM_BLOCK,
+        DxgiFormat::BC5_SNorm => vk::Format::BC5_SNORM_BLOCK,
+        DxgiFormat::BC6H_UF16 => vk::Format::BC6H_UFLOAT_BLOCK,
+        DxgiFormat::BC6H_SF16 => vk::Format::BC6H_SFLOAT_BLOCK,
+        DxgiFormat::BC7_UNorm => vk::Format::BC7_UNORM_BLOCK,
+        DxgiFormat::BC7_UNorm_sRGB => vk::Format::BC7_SRGB_BLOCK,
+        _ => return None,
+    })
+}
+
+/// Creates sampled images from image files: PNG and JPEG are decoded on the CPU and get
+/// their mip chain generated on the GPU, KTX2 and DDS files are uploaded as they are,
+/// including block compressed (BCn, ETC2, ASTC) data and their mip levels.
+pub struct TextureLoader<'a> {
+    pub instance: &'a ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: &'a ash::Device,
+    pub memory_properties: &'a vk::PhysicalDeviceMemoryProperties,
+    pub command_pool: vk::CommandPool,
+    pub queue: vk::Queue,
+}
+
+impl<'a> TextureLoader<'a> {
+    /// Picks the container by file extension.
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> Result<Image, Box<dyn std::error::Error>> {
+        self.load_file(path.as_ref(), true)
+    }
+
+    /// Like `load`, but PNG and JPEG images only get their full size level, for images that
+    /// are never minified like lookup tables. KTX2 and DDS files keep the levels they contain.
+    pub fn load_without_mipmaps<P: AsRef<Path>>(&self, path: P) -> Result<Image, Box<dyn std::error::Error>> {
+        self.load_file(path.as_ref(), false)
+    }
+
+    fn load_file(&self, path: &Path, generate_mipmaps: bool) -> Result<Image, Box<dyn std::error::Error>> {
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        let result = match extension.as_deref() {
+            Some("ktx2") => std::fs::read(path).map_err(Box::<dyn std::error::Error>::from).and_then(|data| self.load_ktx2(&data)),
+            Some("dds") => std::fs::read(path).map_err(Box::<dyn std::error::Error>::from).and_then(|data| self.load_dds(&data)),
+            _ => image::open(path).map_err(Box::<dyn std::error::Error>::from).and_then(|image| {
+                let pixels = image.to_rgba();
+                let (width, height) = pixels.dimensions();
+                self.create(vk::Extent2D { width, height }, vk::Format::R8G8B8A8_SRGB, &[&pixels.into_raw()], generate_mipmaps)
+            }),
+        };
+        result.map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn load_ktx2(&self, data: &[u8]) -> Result<Image, Box<dyn std::error::Error>> {
+        let reader = ktx2::Reader::new(data)?;
+        let header = reader.header();
+        if header.supercompression_scheme.is_some() {
+            return Err(TextureError::Supercompressed.into());
+        }
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count != 1 {
+            return Err(TextureError::NotTwoDimensional.into());
+        }
+        // KTX2 uses the VkFormat values
+        let format = header.format.map(|format| vk::Format::from_raw(format.0.get() as i32))
+            .ok_or_else(|| TextureError::UnknownFormat("undefined".to_owned()))?;
+        let levels: Vec<&[u8]> = reader.levels().collect();
+        // a level count of 0 asks for the mip chain to be generated
+        let generate_mipmaps = header.level_count == 0;
+        let extent = vk::Extent2D { width: header.pixel_width, height: std::cmp::max(header.pixel_height, 1) };
+        check_levels(&levels, extent, format)?;
+        self.create(extent, format, &levels, generate_mipmaps)
+    }
+
+    pub fn load_dds(&self, data: &[u8]) -> Result<Image, Box<dyn std::error::Error>> {
+        let dds = ddsfile::Dds::read(data)?;
+        if dds.get_depth() > 1 || dds.get_num_array_layers() > 1 {
+            return Err(TextureError::NotTwoDimensional.into());
+        }
+        let dxgi_format = dds.get_dxgi_format().ok_or_else(|| TextureError::UnknownFormat(format!("{:?}", dds.get_d3d_format())))?;
+        let format = dds_format(dxgi_format).ok_or_else(|| TextureError::UnknownFormat(format!("{:?}", dxgi_format)))?;
+        let extent = vk::Extent2D { width: dds.get_width(), height: dds.get_height() };
+        let levels = split_levels(dds.get_data(0)?, extent, format, dds.get_num_mipmap_levels())?;
+        self.create(extent, format, &levels, false)
+    }
+
+    fn format_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        unsafe { self.instance.get_physical_device_format_properties(self.physical_device, format) }.optimal_tiling_features
+    }
+
+    /// A sampled image with `levels` as its first mip levels, tightly packed texels or blocks
+    /// of `format`. With `generate_mipmaps`, the rest of the mip chain is generated from the
+    /// last given level if the format can be blitted with linear filtering. The returned
+    /// image is in `SHADER_READ_ONLY_OPTIMAL` layout.
+    pub fn create(&self, extent: vk::Extent2D, format: vk::Format, levels: &[&[u8]], generate_mipmaps: bool) -> Result<Image, Box<dyn std::error::Error>> {
+        let device = self.device;
+        let features = self.format_features(format);
+        if !features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE) {
+            return Err(TextureError::UnsupportedFormat(format).into());
+        }
+        let blit_features = vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST | vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        let mip_levels = if generate_mipmaps && features.contains(blit_features) {
+            mip_level_count(extent)
+        } else {
+            if generate_mipmaps {
+                warn!("{:?} doesn't support linear blits, no mipmaps are generated", format);
+            }
+            levels.len() as u32
+        };
+
+        // every level starts at a multiple of 16 bytes, which covers all texel block sizes
+        let offsets: Vec<vk::DeviceSize> = levels.iter().scan(0, |offset, level| {
+            let current = *offset;
+            *offset = (current + level.len() as vk::DeviceSize + 15) & !15;
+            Some(current)
+        }).collect();
+        let size = offsets.last().unwrap_or(&0) + levels.last().map(|level| level.len()).unwrap_or(0) as vk::DeviceSize;
+        let staging = Buffer::host_visible(device, self.memory_properties, std::cmp::max(size, 1), vk::BufferUsageFlags::TRANSFER_SRC)?;
+        let mut staged = Vec::with_capacity(size as usize);
+        for (level, offset) in levels.iter().zip(&offsets) {
+            staged.resize(*offset as usize, 0);
+            staged.extend_from_slice(level);
+        }
+        let result = staging.write(device, &staged).map_err(Box::<dyn std::error::Error>::from).and_then(|_| {
+            let usage = vk::ImageUsageFlags::TRANSFER_SRC | vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED;
+            let image = Image::new(device, self.memory_properties, extent, format, mip_levels, vk::SampleCountFlags::TYPE_1, usage)?;
+            let subresource_range = image.subresource_range();
+            let regions: Vec<vk::BufferImageCopy> = offsets.iter().enumerate().map(|(level, offset)| {
+                let level_extent = mip_extent(extent, level as u32);
+                vk::BufferImageCopy::builder()
+                    .buffer_offset(*offset)
+                    .image_subresource(vk::ImageSubresourceLayers::builder()
+                        .aspect_mask(subresource_range.aspect_mask)
+                        .mip_level(level as u32)
+                        .base_array_layer(0)
+                        .layer_count(1)
+                        .build())
+                    .image_extent(vk::Extent3D { width: level_extent.width, height: level_extent.height, depth: 1 })
+                    .build()
+            }).collect();
+            let result = one_time_submit(device, self.command_pool, self.queue, |command_buffer| {
+                transition_layout(device, command_buffer, image.image, subresource_range, vk::ImageLayout::UNDEFINED, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+                unsafe { device.cmd_copy_buffer_to_image(command_buffer, staging.buffer, image.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions) };
+                let given = std::cmp::max(levels.len() as u32, 1);
+                let mut loaded = subresource_range;
+                loaded.level_count = std::cmp::min(given, mip_levels);
+                transition_layout(device, command_buffer, image.image, loaded, vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+                if mip_levels > given {
+                    Self::record_mipmaps(device, command_buffer, &image, given - 1);
+                }
+            });
+            if let Err(err) = result {
+                image.destroy(device);
+                return Err(err.into());
+            }
+            Ok(image)
+        });
+        staging.destroy(device);
+        result
+    }
+
+    /// Fills the levels after `base_level`, which has to be in `SHADER_READ_ONLY_OPTIMAL` layout
+    /// while the following ones are in `TRANSFER_DST_OPTIMAL`, by blitting each level to the next.
+    /// All of them end up in `SHADER_READ_ONLY_OPTIMAL`.
+    fn record_mipmaps(device: &ash::Device, command_buffer: vk::CommandBuffer, image: &Image, base_level: u32) {
+        let level_range = |level| {
+            let mut range = image.subresource_range();
+            range.base_mip_level = level;
+            range.level_count = 1;
+            range
+        };
+        let level_layers = |level| vk::ImageSubresourceLayers::builder()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .mip_level(level)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build();
+        let corner = |extent: vk::Extent2D| vk::Offset3D { x: extent.width as i32, y: extent.height as i32, z: 1 };
+        transition_layout(device, command_buffer, image.image, level_range(base_level), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        for level in base_level + 1..image.mip_levels {
+            let regions = [vk::ImageBlit::builder()
+                .src_subresource(level_layers(level - 1))
+                .src_offsets([vk::Offset3D::default(), corner(mip_extent(image.extent, level - 1))])
+                .dst_subresource(level_layers(level))
+                .dst_offsets([vk::Offset3D::default(), corner(mip_extent(image.extent, level))])
+                .build()];
+            unsafe { device.cmd_blit_image(command_buffer, image.image, vk::ImageLayout::TRANSFER_SRC_OPTIMAL, image.image, vk::ImageLayout::TRANSFER_DST_OPTIMAL, &regions, vk::Filter::LINEAR) };
+            transition_layout(device, command_buffer, image.image, level_range(level - 1), vk::ImageLayout::TRANSFER_SRC_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            // the level just written is the source of the next blit
+            let next_layout = if level + 1 < image.mip_levels { vk::ImageLayout::TRANSFER_SRC_OPTIMAL } else { vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL };
+            transition_layout(device, command_buffer, image.image, level_range(level), vk::ImageLayout::TRANSFER_DST_OPTIMAL, next_layout);
+        }
+    }
+}
+
+/// What a sampler is looked up by in the `SamplerCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    /// Used for magnification, minification and, as the closest match, between mip levels.
+    pub filter: vk::Filter,
+    pub address_mode: vk::SamplerAddressMode,
+    /// Maximum anisotropy, `None` or 1 disable anisotropic filtering.
+    pub anisotropy: Option<u32>,
+}
+
+impl SamplerKey {
+    pub fn new(filter: vk::Filter, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            filter,
+            address_mode,
+            anisotropy: None,
+        }
+    }
+
+    pub fn anisotropy(mut self, anisotropy: u32) -> Self {
+        self.anisotropy = Some(anisotropy);
+        self
+    }
+}
+
+/// Samplers are few and can be shared by any number of textures, so every distinct
+/// combination of settings is only created once.
+pub struct SamplerCache {
+    samplers: HashMap<SamplerKey, vk::Sampler>,
+    /// The device limit, `None` if the `samplerAnisotropy` feature isn't enabled.
+    max_anisotropy: Option<f32>,
+}
+
+impl SamplerCache {
+    pub fn new(max_anisotropy: Option<f32>) -> Self {
+        Self {
+            samplers: HashMap::new(),
+            max_anisotropy,
+        }
+    }
+
+    /// Anisotropy is clamped to what the device supports.
+    pub fn get(&mut self, device: &ash::Device, key: SamplerKey) -> Result<vk::Sampler, vk::Result> {
+        if let Some(sampler) = self.samplers.get(&key) {
+            return Ok(*sampler);
+        }
+        let anisotropy = match (key.anisotropy, self.max_anisotropy) {
+            (Some(anisotropy), Some(max_anisotropy)) if anisotropy > 1 => Some((anisotropy as f32).min(max_anisotropy)),
+            _ => None,
+        };
+        let mipmap_mode = match key.filter {
+            vk::Filter::NEAREST => vk::SamplerMipmapMode::NEAREST,
+            _ => vk::SamplerMipmapMode::LINEAR,
+        };
+        let sampler_info = vk::SamplerCreateInfo::builder()
+            .mag_filter(key.filter)
+            .min_filter(key.filter)
+            .mipmap_mode(mipmap_mode)
+            .address_mode_u(key.address_mode)
+            .address_mode_v(key.address_mode)
+            .address_mode_w(key.address_mode)
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0))
+            .min_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE)
+            .border_color(vk::BorderColor::FLOAT_OPAQUE_BLACK);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }?;
+        self.samplers.insert(key, sampler);
+        Ok(sampler)
+    }
+
+    pub fn destroy(&mut self, device: &ash::Device) {
+        for (_, sampler) in self.samplers.drain() {
+            unsafe { device.destroy_sampler(sampler, None) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_sizes() {
+        let cases = [
+            (vk::Format::R8G8B8A8_SRGB, Some((1, 1, 4))),
+            (vk::Format::B8G8R8A8_UNORM, Some((1, 1, 4))),
+            (vk::Format::BC1_RGBA_SRGB_BLOCK, Some((4, 4, 8))),
+            (vk::Format::BC4_SNORM_BLOCK, Some((4, 4, 8))),
+            (vk::Format::BC3_UNORM_BLOCK, Some((4, 4, 16))),
+            (vk::Format::BC7_SRGB_BLOCK, Some((4, 4, 16))),
+            (vk::Format::ETC2_R8G8B8_SRGB_BLOCK, Some((4, 4, 8))),
+            (vk::Format::ETC2_R8G8B8A8_UNORM_BLOCK, Some((4, 4, 16))),
+            (vk::Format::EAC_R11_SNORM_BLOCK, Some((4, 4, 8))),
+            (vk::Format::EAC_R11G11_UNORM_BLOCK, Some((4, 4, 16))),
+            (vk::Format::ASTC_4X4_UNORM_BLOCK, Some((4, 4, 16))),
+            (vk::Format::ASTC_8X5_SRGB_BLOCK, Some((8, 5, 16))),
+            (vk::Format::ASTC_12X12_SRGB_BLOCK, Some((12, 12, 16))),
+            (vk::Format::R32_SFLOAT, None),
+        ];
+        for (format, expected) in &cases {
+            assert_eq!(block_size(*format), *expected, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn dds_level_sizes() {
+        // extent, format, level count and the size of each level
+        let cases = [
+            ((4, 4), vk::Format::R8G8B8A8_UNORM, 3, vec![64, 16, 4]),
+            ((5, 3), vk::Format::R8G8B8A8_UNORM, 3, vec![60, 8, 4]),
+            ((16, 8), vk::Format::BC1_RGBA_UNORM_BLOCK, 5, vec![64, 16, 8, 8, 8]),
+            // partial blocks at the edges still take a whole block
+            ((10, 6), vk::Format::BC3_UNORM_BLOCK, 4, vec![96, 32, 16, 16]),
+            ((1, 1), vk::Format::BC7_UNORM_BLOCK, 1, vec![16]),
+        ];
+        for ((width, height), format, level_count, sizes) in &cases {
+            let total: usize = sizes.iter().sum();
+            // one extra byte, trailing data is ignored
+            let data: Vec<u8> = (0..=total).map(|idx| idx as u8).collect();
+            let levels = split_levels(&data, vk::Extent2D { width: *width, height: *height }, *format, *level_count).unwrap();
+            assert_eq!(levels.iter().map(|level| level.len()).collect::<Vec<_>>(), *sizes, "{}x{} {:?}", width, height, format);
+            let mut offset = 0;
+            for level in levels {
+                assert_eq!(level[0], offset as u8);
+                offset += level.len();
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_dds_level() {
+        let data = vec![0; 64 + 16 + 3];
+        let result = split_levels(&data, vk::Extent2D { width: 4, height: 4 }, vk::Format::R8G8B8A8_UNORM, 3);
+        assert!(matches!(result, Err(TextureError::Truncated { level: 2 })));
+    }
+
+    #[test]
+    fn unknown_formats_are_errors() {
+        let data = vec![0; 64];
+        let result = split_levels(&data, vk::Extent2D { width: 4, height: 4 }, vk::Format::R32_SFLOAT, 1);
+        assert!(matches!(result, Err(TextureError::UnknownFormat(ref format)) if format == "R32_SFLOAT"));
+        let result = check_levels(&[&data], vk::Extent2D { width: 4, height: 4 }, vk::Format::R32_SFLOAT);
+        assert!(matches!(result, Err(TextureError::UnknownFormat(_))));
+    }
+
+    #[test]
+    fn ktx2_level_sizes() {
+        let extent = vk::Extent2D { width: 10, height: 6 };
+        // ASTC 6x6 rounds 10x6 up to 2x1 blocks, then 5x3 and 3x2 to 1x1
+        let (level0, level1, level2) = (vec![0; 32], vec![0; 16], vec![0; 16]);
+        assert!(check_levels(&[&level0, &level1, &level2], extent, vk::Format::ASTC_6X6_UNORM_BLOCK).is_ok());
+        assert!(check_levels(&[], extent, vk::Format::ASTC_6X6_UNORM_BLOCK).is_ok());
+
+        let short = vec![0; 8];
+        let result = check_levels(&[&level0, &short], extent, vk::Format::ASTC_6X6_UNORM_BLOCK);
+        assert!(matches!(result, Err(TextureError::LevelSize { level: 1, expected: 16, actual: 8 })));
+        // a level that is too long most likely means the format is wrong
+        let result = check_levels(&[&level0], extent, vk::Format::ETC2_R8G8B8_UNORM_BLOCK);
+        assert!(matches!(result, Err(TextureError::LevelSize { level: 0, expected: 48, actual: 32 })));
+    }
+}
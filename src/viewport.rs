@@ -0,0 +1,242 @@
+use ash::{
+    vk,
+    version::DeviceV1_0,
+};
+
+/// A viewport and the scissor rectangle that clips it. Pipelines keep both as dynamic
+/// state, so a region is recorded into the command buffer before drawing into it.
+#[derive(Debug, Clone, Copy)]
+pub struct ViewportRegion {
+    pub viewport: vk::Viewport,
+    pub scissor: vk::Rect2D,
+}
+
+impl ViewportRegion {
+    /// A region in pixels, scissored to its own bounds.
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            viewport: vk::Viewport::builder()
+                .x(x as f32)
+                .y(y as f32)
+                .width(width as f32)
+                .height(height as f32)
+                .min_depth(0.0)
+                .max_depth(1.0)
+                .build(),
+            scissor: vk::Rect2D::builder()
+                .offset(vk::Offset2D::builder().x(x).y(y).build())
+                .extent(vk::Extent2D::builder().width(width).height(height).build())
+                .build(),
+        }
+    }
+
+    pub fn full(extent: vk::Extent2D) -> Self {
+        Self::new(0, 0, extent.width, extent.height)
+    }
+
+    /// A region given in fractions of `extent`, so it follows the window size.
+    pub fn relative(extent: vk::Extent2D, x: f32, y: f32, width: f32, height: f32) -> Self {
+        let left = (x * extent.width as f32).round() as i32;
+        let top = (y * extent.height as f32).round() as i32;
+        let right = ((x + width) * extent.width as f32).round() as i32;
+        let bottom = ((y + height) * extent.height as f32).round() as i32;
+        Self::new(left, top, (right - left).max(0) as u32, (bottom - top).max(0) as u32)
+    }
+
+    /// Narrows the scissor to its intersection with `rect`, e.g. for clipping UI widgets.
+    #[allow(dead_code)]
+    pub fn clip(mut self, rect: vk::Rect2D) -> Self {
+        let left = self.scissor.offset.x.max(rect.offset.x);
+        let top = self.scissor.offset.y.max(rect.offset.y);
+        let right = (self.scissor.offset.x + self.scissor.extent.width as i32).min(rect.offset.x + rect.extent.width as i32);
+        let bottom = (self.scissor.offset.y + self.scissor.extent.height as i32).min(rect.offset.y + rect.extent.height as i32);
+        self.scissor = vk::Rect2D::builder()
+            .offset(vk::Offset2D::builder().x(left).y(top).build())
+            .extent(vk::Extent2D::builder().width((right - left).max(0) as u32).height((bottom - top).max(0) as u32).build())
+            .build();
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scissor.extent.width == 0 || self.scissor.extent.height == 0
+    }
+
+    /// Clears the scissor rectangle of the current subpass's attachments, color attachment 0
+    /// and the depth attachment, to `clear_values` in attachment order.
+    pub fn clear(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, clear_values: &[vk::ClearValue]) {
+        let attachments = [
+            vk::ClearAttachment::builder()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .color_attachment(0)
+                .clear_value(clear_values[0])
+                .build(),
+            vk::ClearAttachment::builder()
+                .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                .clear_value(clear_values[1])
+                .build(),
+        ];
+        let rects = [vk::ClearRect::builder()
+            .rect(self.scissor)
+            .base_array_layer(0)
+            .layer_count(1)
+            .build()];
+        unsafe { device.cmd_clear_attachments(command_buffer, &attachments, &rects) };
+    }
+
+    /// Sets viewport and scissor 0 for the following draws.
+    pub fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        unsafe {
+            device.cmd_set_viewport(command_buffer, 0, &[self.viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[self.scissor]);
+        }
+    }
+}
+
+/// How the window is divided into regions that each get the scene drawn into them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ViewportLayout {
+    #[default]
+    Single,
+    SplitScreen { columns: u32, rows: u32 },
+    /// The full window with a smaller copy in the bottom right corner, `scale` times the window size.
+    PictureInPicture { scale: f32 },
+}
+
+impl ViewportLayout {
+    pub fn regions(&self, extent: vk::Extent2D) -> Vec<ViewportRegion> {
+        match *self {
+            ViewportLayout::Single => vec![ViewportRegion::full(extent)],
+            ViewportLayout::SplitScreen { columns, rows } => {
+                let (width, height) = (1.0 / columns as f32, 1.0 / rows as f32);
+                (0..rows).flat_map(|row| (0..columns).map(move |column| (column, row)))
+                    .map(|(column, row)| ViewportRegion::relative(extent, column as f32 * width, row as f32 * height, width, height))
+                    .collect()
+            }
+            ViewportLayout::PictureInPicture { scale } => {
+                let margin = 0.02;
+                vec![
+                    ViewportRegion::full(extent),
+                    ViewportRegion::relative(extent, 1.0 - scale - margin, 1.0 - scale - margin, scale, scale),
+                ]
+            }
+        }.into_iter().filter(|region| !region.is_empty()).collect()
+    }
+
+    /// The layout after this one, for cycling through them with a key.
+    pub fn next(&self) -> Self {
+        match self {
+            ViewportLayout::Single => ViewportLayout::SplitScreen { columns: 2, rows: 1 },
+            ViewportLayout::SplitScreen { columns: 2, rows: 1 } => ViewportLayout::SplitScreen { columns: 2, rows: 2 },
+            ViewportLayout::SplitScreen { .. } => ViewportLayout::PictureInPicture { scale: 0.3 },
+            ViewportLayout::PictureInPicture { .. } => ViewportLayout::Single,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D { offset: vk::Offset2D { x, y }, extent: extent(width, height) }
+    }
+
+    /// (x, y, width, height) of the scissor, which `new` keeps in sync with the viewport.
+    fn bounds(region: &ViewportRegion) -> (i32, i32, u32, u32) {
+        let scissor = region.scissor;
+        (scissor.offset.x, scissor.offset.y, scissor.extent.width, scissor.extent.height)
+    }
+
+    #[test]
+    fn new_matches_viewport_and_scissor() {
+        let region = ViewportRegion::new(10, 20, 30, 40);
+        let viewport = region.viewport;
+        assert_eq!((viewport.x, viewport.y, viewport.width, viewport.height), (10.0, 20.0, 30.0, 40.0));
+        assert_eq!((viewport.min_depth, viewport.max_depth), (0.0, 1.0));
+        assert_eq!(bounds(&region), (10, 20, 30, 40));
+    }
+
+    #[test]
+    fn relative_rounds_to_pixels() {
+        let cases = [
+            ((0.0, 0.0, 1.0, 1.0), (0, 0, 800, 600)),
+            ((0.5, 0.0, 0.5, 1.0), (400, 0, 400, 600)),
+            // a third of 800 pixels, the edges are rounded so neighbours don't overlap or leave gaps
+            ((0.0, 0.0, 1.0 / 3.0, 1.0), (0, 0, 267, 600)),
+            ((1.0 / 3.0, 0.0, 1.0 / 3.0, 1.0), (267, 0, 266, 600)),
+            ((0.5, 0.5, 0.0, 0.0), (400, 300, 0, 0)),
+            ((0.5, 0.5, -0.25, 0.25), (400, 300, 0, 150)),
+        ];
+        for &((x, y, width, height), expected) in &cases {
+            assert_eq!(bounds(&ViewportRegion::relative(extent(800, 600), x, y, width, height)), expected, "{:?}", (x, y, width, height));
+        }
+    }
+
+    #[test]
+    fn clip_intersects_the_scissor() {
+        let cases = [
+            (rect(0, 0, 100, 100), (10, 10, 50, 50)),
+            (rect(20, 30, 10, 10), (20, 30, 10, 10)),
+            (rect(40, 40, 100, 100), (40, 40, 20, 20)),
+            (rect(-10, 0, 30, 100), (10, 10, 10, 50)),
+            // outside of the scissor, nothing is left
+            (rect(100, 100, 10, 10), (100, 100, 0, 0)),
+        ];
+        for (clip, expected) in &cases {
+            let region = ViewportRegion::new(10, 10, 50, 50).clip(*clip);
+            assert_eq!(bounds(&region), *expected, "{:?}", clip);
+            assert_eq!(region.is_empty(), expected.2 == 0 || expected.3 == 0);
+            // the viewport keeps its transform, only the scissor changes
+            assert_eq!((region.viewport.x, region.viewport.width), (10.0, 50.0));
+        }
+    }
+
+    #[test]
+    fn regions_cover_the_window() {
+        let window = extent(800, 600);
+        let cases = [
+            (ViewportLayout::Single, vec![(0, 0, 800, 600)]),
+            (ViewportLayout::SplitScreen { columns: 2, rows: 1 }, vec![(0, 0, 400, 600), (400, 0, 400, 600)]),
+            (ViewportLayout::SplitScreen { columns: 2, rows: 2 }, vec![(0, 0, 400, 300), (400, 0, 400, 300), (0, 300, 400, 300), (400, 300, 400, 300)]),
+            (ViewportLayout::PictureInPicture { scale: 0.25 }, vec![(0, 0, 800, 600), (584, 438, 200, 150)]),
+        ];
+        for (layout, expected) in &cases {
+            let regions: Vec<_> = layout.regions(window).iter().map(bounds).collect();
+            assert_eq!(regions, *expected, "{:?}", layout);
+        }
+    }
+
+    #[test]
+    fn empty_regions_are_dropped() {
+        assert!(ViewportLayout::Single.regions(extent(0, 0)).is_empty());
+        // too narrow for three columns
+        assert_eq!(ViewportLayout::SplitScreen { columns: 3, rows: 1 }.regions(extent(1, 10)).len(), 1);
+        assert_eq!(ViewportLayout::PictureInPicture { scale: 0.0 }.regions(extent(800, 600)).len(), 1);
+    }
+
+    #[test]
+    fn next_cycles_through_every_layout() {
+        let mut layout = ViewportLayout::default();
+        let mut seen = vec![layout];
+        loop {
+            layout = layout.next();
+            if layout == ViewportLayout::default() {
+                break;
+            }
+            assert!(!seen.contains(&layout), "{:?} came up twice", layout);
+            seen.push(layout);
+        }
+        assert_eq!(seen, vec![
+            ViewportLayout::Single,
+            ViewportLayout::SplitScreen { columns: 2, rows: 1 },
+            ViewportLayout::SplitScreen { columns: 2, rows: 2 },
+            ViewportLayout::PictureInPicture { scale: 0.3 },
+        ]);
+        // other split screens, e.g. passed to set_viewport_layout, continue with picture in picture
+        assert_eq!(ViewportLayout::SplitScreen { columns: 3, rows: 1 }.next(), ViewportLayout::PictureInPicture { scale: 0.3 });
+    }
+}